@@ -3,61 +3,556 @@
 // Abstract Syntax Tree (AST). This structure represents the code's
 // hierarchy and meaning, decoupled from the raw source text.
 
+// A range of byte offsets into the source text, used to point
+// diagnostics at the code they are about.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+use std::collections::HashMap;
+use std::fmt;
+
 // Using Debug trait to allow printing the AST for inspection.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Type {
     Float,
     Int,
     Bool,
+    Float2,
+    Float3,
+    Float4,
+    Int2,
+    Int3,
+    Int4,
+    Bool2,
+    Bool3,
+    Bool4,
+    Void,
+    // `atomic<int>`, a value only accessed through the atomic intrinsics,
+    // so concurrent updates from different invocations are never lost.
+    Atomic(Box<Type>),
+    // A type written by name: a generic type parameter or an enum.
+    // Semantic analysis resolves what the name refers to.
+    Named(String),
 }
 
+impl Type {
+    // The vector type with the given scalar element and width, where a
+    // width of 1 is the scalar itself.
+    pub fn vector(element: &Type, width: u8) -> Option<Type> {
+        let ty = match (element, width) {
+            (Type::Float | Type::Int | Type::Bool, 1) => element.clone(),
+            (Type::Float, 2) => Type::Float2,
+            (Type::Float, 3) => Type::Float3,
+            (Type::Float, 4) => Type::Float4,
+            (Type::Int, 2) => Type::Int2,
+            (Type::Int, 3) => Type::Int3,
+            (Type::Int, 4) => Type::Int4,
+            (Type::Bool, 2) => Type::Bool2,
+            (Type::Bool, 3) => Type::Bool3,
+            (Type::Bool, 4) => Type::Bool4,
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    // The scalar type of each component, for scalars and vectors.
+    pub fn element(&self) -> Option<Type> {
+        match self {
+            Type::Float | Type::Float2 | Type::Float3 | Type::Float4 => Some(Type::Float),
+            Type::Int | Type::Int2 | Type::Int3 | Type::Int4 => Some(Type::Int),
+            Type::Bool | Type::Bool2 | Type::Bool3 | Type::Bool4 => Some(Type::Bool),
+            Type::Void | Type::Atomic(_) | Type::Named(_) => None,
+        }
+    }
+
+    // The number of components, for scalars and vectors.
+    pub fn width(&self) -> Option<u8> {
+        match self {
+            Type::Float | Type::Int | Type::Bool => Some(1),
+            Type::Float2 | Type::Int2 | Type::Bool2 => Some(2),
+            Type::Float3 | Type::Int3 | Type::Bool3 => Some(3),
+            Type::Float4 | Type::Int4 | Type::Bool4 => Some(4),
+            Type::Void | Type::Atomic(_) | Type::Named(_) => None,
+        }
+    }
+}
+
+impl Type {
+    // Replaces every type parameter named in `bindings` with the type bound
+    // to it.
+    pub fn substitute(&self, bindings: &HashMap<String, Type>) -> Type {
+        match self {
+            Type::Named(name) => bindings.get(name).cloned().unwrap_or_else(|| self.clone()),
+            _ => self.clone(),
+        }
+    }
+}
+
+// Formats the type as it is spelled in source.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Float => "float",
+            Type::Int => "int",
+            Type::Bool => "bool",
+            Type::Float2 => "float2",
+            Type::Float3 => "float3",
+            Type::Float4 => "float4",
+            Type::Int2 => "int2",
+            Type::Int3 => "int3",
+            Type::Int4 => "int4",
+            Type::Bool2 => "bool2",
+            Type::Bool3 => "bool3",
+            Type::Bool4 => "bool4",
+            Type::Void => "void",
+            Type::Atomic(element) => return write!(f, "atomic<{}>", element),
+            Type::Named(name) => name,
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A whole program: every module loaded for it, and every global, enum and
+// function they define. Definitions are ordered by module, then by their
+// position in the source.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TranslationUnit {
+    pub modules: Vec<ModuleInfo>,
+    pub spec_constants: Vec<SpecConstant>,
+    pub workgroup_variables: Vec<WorkgroupVariable>,
+    pub enums: Vec<EnumDefinition>,
+    pub functions: Vec<FunctionDefinition>,
+}
+
+// One source file of a program. Its index in `TranslationUnit::modules`
+// is its module id, which is also its file id in the source map.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleInfo {
+    pub path: String,
+    // The ids of the modules it imports.
+    pub imports: Vec<usize>,
+}
+
+// A single source file as parsed, before its imports are loaded.
+#[derive(Debug, PartialEq)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub spec_constants: Vec<SpecConstant>,
+    pub workgroup_variables: Vec<WorkgroupVariable>,
+    pub enums: Vec<EnumDefinition>,
+    pub functions: Vec<FunctionDefinition>,
+}
+
+// `import "lighting.osl";`, which makes the public definitions of that
+// file usable from this one.
 #[derive(Debug, PartialEq)]
+pub struct Import {
+    pub path: String,
+    // Location of the path string.
+    pub span: Span,
+}
+
+// A top-level item, as the parser reads them before sorting them into a
+// `Module`.
+pub enum Item {
+    Import(Import),
+    SpecConstant(SpecConstant),
+    WorkgroupVariable(WorkgroupVariable),
+    Enum(EnumDefinition),
+    Function(FunctionDefinition),
+}
+
+// `@specialization(id = 3) const int SAMPLE_COUNT = 8;`, a constant the
+// runtime can override when it creates a pipeline. Unlike the constants of
+// `static if`, its value is not known while compiling, so it is kept in the
+// output under its id, with `default` as the value used if it is not set.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpecConstant {
+    // Whether other modules may use it.
+    pub public: bool,
+    // The id of the module that defines it, set when the module is loaded.
+    pub module: usize,
+    pub id: u32,
+    // Location of the id.
+    pub id_span: Span,
+    pub type_name: Type,
+    pub name: String,
+    pub default: Expression,
+    // Location of the constant's name.
+    pub span: Span,
+}
+
+// `workgroup float partial_sum;`, a variable in memory shared by every
+// invocation of a compute workgroup. It has no initializer: its contents
+// are undefined until an invocation writes them, and other invocations only
+// see the write once they have all passed a barrier.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkgroupVariable {
+    // Whether other modules may use it.
+    pub public: bool,
+    // The id of the module that defines it, set when the module is loaded.
+    pub module: usize,
+    pub type_name: Type,
+    pub name: String,
+    // Location of the variable's name.
+    pub span: Span,
+}
+
+// A C-like enum. Its values are integer constants: each variant is
+// numbered by its position, starting from 0.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumDefinition {
+    // Whether other modules may use it.
+    pub public: bool,
+    // The id of the module that defines it, set when the module is loaded.
+    pub module: usize,
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+    // Location of the enum's name.
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub span: Span,
+}
+
+impl EnumDefinition {
+    // The integer constant the variant lowers to.
+    pub fn discriminant(&self, variant: &str) -> Option<i64> {
+        self.variants
+            .iter()
+            .position(|v| v.name == variant)
+            .map(|index| index as i64)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct FunctionDefinition {
+    // Whether other modules may call it.
+    pub public: bool,
+    // The id of the module that defines it, set when the module is loaded.
+    pub module: usize,
+    // Set when the function is an entry point for a pipeline stage.
+    pub stage: Option<ShaderStage>,
+    // Set by an `@inline(...)` attribute.
+    pub inline: Option<InlineHint>,
     pub return_type: Type,
     pub name: String,
+    // Location of the function's name.
+    pub span: Span,
+    // Type parameters of a generic function, such as `<T: FloatVector>`.
+    pub generics: Vec<GenericParam>,
     pub params: Vec<Parameter>,
     pub body: Vec<Statement>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+impl ShaderStage {
+    // The stage as it is written in an entry point's attribute.
+    pub fn name(self) -> &'static str {
+        match self {
+            ShaderStage::Vertex => "vertex",
+            ShaderStage::Fragment => "fragment",
+            ShaderStage::Compute => "compute",
+        }
+    }
+}
+
+// Overrides the inliner's choice of whether to inline calls to a function.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InlineHint {
+    Always,
+    Never,
+}
+
+impl InlineHint {
+    // The hint named by the argument of `@inline(...)`.
+    pub fn from_name(name: &str) -> Option<InlineHint> {
+        match name {
+            "always" => Some(InlineHint::Always),
+            "never" => Some(InlineHint::Never),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InlineHint::Always => "always",
+            InlineHint::Never => "never",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GenericParam {
+    pub name: String,
+    pub bound: TypeBound,
+    // Location of the parameter's name.
+    pub span: Span,
+}
+
+// The set of types a generic type parameter may stand for. Scalars count as
+// vectors of width 1.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TypeBound {
+    FloatVector,
+    IntVector,
+    BoolVector,
+    // Any float or int scalar or vector.
+    Numeric,
+}
+
+impl TypeBound {
+    pub fn members(self) -> Vec<Type> {
+        let elements = match self {
+            TypeBound::FloatVector => vec![Type::Float],
+            TypeBound::IntVector => vec![Type::Int],
+            TypeBound::BoolVector => vec![Type::Bool],
+            TypeBound::Numeric => vec![Type::Float, Type::Int],
+        };
+        elements
+            .iter()
+            .flat_map(|element| (1..=4).filter_map(|width| Type::vector(element, width)))
+            .collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Parameter {
+    pub qualifier: ParameterQualifier,
     pub type_name: Type,
     pub name: String,
+    // Location of the parameter's name.
+    pub span: Span,
 }
 
-#[derive(Debug, PartialEq)]
+// How an argument is passed. `In` copies the value into the callee,
+// `Out` copies the callee's final value back to the caller, and `InOut`
+// does both. Parameters without a qualifier are `In`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParameterQualifier {
+    In,
+    Out,
+    InOut,
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
     Declaration {
-        type_name: Type,
+        // Only `var` declarations may be assigned after initialization.
+        mutable: bool,
+        // `None` for `let c = 1.0;` until semantic analysis fills in the
+        // type inferred from the initializer.
+        type_name: Option<Type>,
         name: String,
-        initializer: Box<Expression>,
+        // `None` for `float c;`. The variable must then be assigned on
+        // every path before it is read.
+        initializer: Option<Box<Expression>>,
+        // Location of the declared name.
+        span: Span,
     },
+    // A bare `{ ... }` block, which opens a new scope.
+    Block(Vec<Statement>),
     If {
         condition: Box<Expression>,
         if_block: Vec<Statement>,
-        // else_block can be added later
+        // An `else if` is an else block holding a single `If`.
+        else_block: Option<Vec<Statement>>,
+        // Location of the `if` keyword.
+        span: Span,
+    },
+    // `static if (FEATURE) { ... }`, whose condition is evaluated against
+    // the constants supplied by the driver. It is replaced by the chosen
+    // branch before semantic analysis, so the other is never checked.
+    StaticIf {
+        condition: Box<Expression>,
+        if_block: Vec<Statement>,
+        // An `else static if` is an else block holding a single `StaticIf`.
+        else_block: Option<Vec<Statement>>,
+        // Location of the `static` keyword.
+        span: Span,
+    },
+    Switch {
+        selector: Box<Expression>,
+        cases: Vec<SwitchCase>,
+        // Location of the `switch` keyword.
+        span: Span,
+    },
+    // Runs the first arm with a pattern matching the selector. The arms
+    // must cover every value the selector can have.
+    Match {
+        selector: Box<Expression>,
+        arms: Vec<MatchArm>,
+        // Location of the `match` keyword.
+        span: Span,
+    },
+    // Leaves the innermost enclosing switch.
+    Break {
+        // Location of the `break` keyword.
+        span: Span,
+    },
+    // Continues into the next case. Cases never fall through implicitly.
+    Fallthrough {
+        // Location of the `fallthrough` keyword.
+        span: Span,
+    },
+    // Ends the fragment invocation without writing any outputs.
+    Discard {
+        // Location of the `discard` keyword.
+        span: Span,
+    },
+    // Turns the fragment invocation into a helper invocation: it keeps
+    // running so derivatives stay valid, but its outputs are dropped.
+    Demote {
+        // Location of the `demote` keyword.
+        span: Span,
+    },
+    Return {
+        value: Option<Box<Expression>>,
+        // Location of the `return` keyword.
+        span: Span,
     },
     Expression(Box<Expression>),
 }
 
-#[derive(Debug, PartialEq)]
+// One arm of a switch: every label that selects it, and the statements it
+// runs. Several labels may share a body (`case 0: case 1: ...`).
+#[derive(Debug, PartialEq, Clone)]
+pub struct SwitchCase {
+    pub labels: Vec<CaseLabel>,
+    pub body: Vec<Statement>,
+    // Location of the first label.
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CaseLabel {
+    Value(Expression),
+    Default {
+        // Location of the `default` keyword.
+        span: Span,
+    },
+}
+
+// One arm of a match: the patterns that select it, separated by `|` in
+// source, and the block it runs.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub patterns: Vec<Pattern>,
+    pub body: Vec<Statement>,
+    // Location of the patterns.
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    // `_`, which matches any value.
+    Wildcard,
+    Bool(bool),
+    Int(i32),
+    // `start..=end`, which matches both ends.
+    IntRange(i32, i32),
+    EnumVariant { enum_name: String, variant: String },
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Expression {
-    Identifier(String),
-    IntLiteral(i64),
-    FloatLiteral(f64),
-    BoolLiteral(bool),
+    Identifier {
+        name: String,
+        span: Span,
+    },
+    IntLiteral {
+        value: i64,
+        span: Span,
+    },
+    FloatLiteral {
+        value: f64,
+        span: Span,
+    },
+    BoolLiteral {
+        value: bool,
+        span: Span,
+    },
+    // A value of an enum, such as `BlendMode::Add`.
+    EnumVariant {
+        enum_name: String,
+        variant: String,
+        span: Span,
+    },
     BinaryOp {
         op: BinaryOp,
         left: Box<Expression>,
         right: Box<Expression>,
+        // Location of the operator.
+        span: Span,
     },
     Assignment {
         name: String,
         value: Box<Expression>,
+        // Location of the assigned name.
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<Expression>,
+        // Location of the called name.
+        span: Span,
+        // For a call to a generic function, the type chosen for each of its
+        // type parameters. Filled in by semantic analysis.
+        type_args: Vec<Type>,
+    },
+    // A type used as a function, such as `float(i)`, which converts or
+    // builds a value of that type from its arguments.
+    Constructor {
+        type_name: Type,
+        args: Vec<Expression>,
+        // Location of the type name.
+        span: Span,
     },
 }
 
-#[derive(Debug, PartialEq)]
+impl Expression {
+    // The location of the whole expression. A call or constructor covers
+    // only the name it is called by, which is enough to point at it.
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Identifier { span, .. }
+            | Expression::IntLiteral { span, .. }
+            | Expression::FloatLiteral { span, .. }
+            | Expression::BoolLiteral { span, .. }
+            | Expression::EnumVariant { span, .. }
+            | Expression::Call { span, .. }
+            | Expression::Constructor { span, .. } => *span,
+            Expression::BinaryOp { left, right, .. } => Span {
+                start: left.span().start,
+                end: right.span().end,
+            },
+            Expression::Assignment { value, span, .. } => Span {
+                start: span.start,
+                end: value.span().end,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BinaryOp {
     Add,
     Subtract,
@@ -70,3 +565,22 @@ pub enum BinaryOp {
     LessThanOrEqual,
     GreaterThanOrEqual,
 }
+
+// Formats the operator as it is written in source.
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let token = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::LessThan => "<",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::LessThanOrEqual => "<=",
+            BinaryOp::GreaterThanOrEqual => ">=",
+        };
+        write!(f, "{}", token)
+    }
+}
@@ -0,0 +1,180 @@
+// src/call_graph.rs
+// This file builds the call graph of a translation unit: which functions
+// each function calls directly. Analyses that depend on how a function is
+// reached, such as stage-specific rules for entry points, walk this graph.
+// Shaders cannot recurse, so semantic analysis also uses it to find
+// functions that call themselves, directly or through others.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::ast::{CaseLabel, Expression, Span, Statement, TranslationUnit};
+
+pub struct CallGraph<'a> {
+    // The functions each function calls directly, in call order without
+    // duplicates, each with the location of its first call. Calls to
+    // undefined functions are included; semantic analysis reports those
+    // separately.
+    callees: HashMap<&'a str, Vec<(&'a str, Span)>>,
+}
+
+impl<'a> CallGraph<'a> {
+    pub fn build(unit: &'a TranslationUnit) -> Self {
+        let mut callees = HashMap::new();
+        for function in &unit.functions {
+            let mut calls = Vec::new();
+            for stmt in &function.body {
+                collect_statement_calls(stmt, &mut calls);
+            }
+            callees.insert(function.name.as_str(), calls);
+        }
+        CallGraph { callees }
+    }
+
+    pub fn callees(&self, function: &str) -> impl Iterator<Item = &'a str> + '_ {
+        self.callees
+            .get(function)
+            .into_iter()
+            .flatten()
+            .map(|&(callee, _)| callee)
+    }
+
+    // The location of the first call in `caller` to `callee`.
+    pub fn call_site(&self, caller: &str, callee: &str) -> Option<Span> {
+        self.callees
+            .get(caller)?
+            .iter()
+            .find(|&&(name, _)| name == callee)
+            .map(|&(_, span)| span)
+    }
+
+    // The shortest chain of calls from `function` back to itself, starting
+    // and ending with `function`, if it is recursive.
+    pub fn cycle_through(&self, function: &'a str) -> Option<Vec<&'a str>> {
+        // The caller through which each function was first reached.
+        let mut reached_from: HashMap<&str, &str> = HashMap::new();
+        let mut queue = VecDeque::from([function]);
+        while let Some(caller) = queue.pop_front() {
+            for callee in self.callees(caller) {
+                if callee == function {
+                    let mut cycle = vec![function, caller];
+                    let mut current = caller;
+                    while current != function {
+                        current = reached_from[current];
+                        cycle.push(current);
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if !reached_from.contains_key(callee) && self.callees.contains_key(callee) {
+                    reached_from.insert(callee, caller);
+                    queue.push_back(callee);
+                }
+            }
+        }
+        None
+    }
+
+    // Every function reachable from `root` through calls, including `root`.
+    pub fn reachable_from(&self, root: &'a str) -> HashSet<&'a str> {
+        let mut reached = HashSet::new();
+        let mut worklist = vec![root];
+        while let Some(function) = worklist.pop() {
+            if reached.insert(function) {
+                worklist.extend(self.callees(function));
+            }
+        }
+        reached
+    }
+}
+
+fn collect_statement_calls<'a>(stmt: &'a Statement, calls: &mut Vec<(&'a str, Span)>) {
+    match stmt {
+        Statement::Declaration { initializer, .. } => {
+            if let Some(initializer) = initializer {
+                collect_expression_calls(initializer, calls);
+            }
+        }
+        Statement::Block(stmts) => {
+            for stmt in stmts {
+                collect_statement_calls(stmt, calls);
+            }
+        }
+        Statement::If {
+            condition,
+            if_block,
+            else_block,
+            ..
+        }
+        | Statement::StaticIf {
+            condition,
+            if_block,
+            else_block,
+            ..
+        } => {
+            collect_expression_calls(condition, calls);
+            for stmt in if_block.iter().chain(else_block.iter().flatten()) {
+                collect_statement_calls(stmt, calls);
+            }
+        }
+        Statement::Switch {
+            selector, cases, ..
+        } => {
+            collect_expression_calls(selector, calls);
+            for case in cases {
+                for label in &case.labels {
+                    if let CaseLabel::Value(value) = label {
+                        collect_expression_calls(value, calls);
+                    }
+                }
+                for stmt in &case.body {
+                    collect_statement_calls(stmt, calls);
+                }
+            }
+        }
+        Statement::Match { selector, arms, .. } => {
+            collect_expression_calls(selector, calls);
+            for stmt in arms.iter().flat_map(|arm| &arm.body) {
+                collect_statement_calls(stmt, calls);
+            }
+        }
+        Statement::Return {
+            value: Some(value), ..
+        }
+        | Statement::Expression(value) => collect_expression_calls(value, calls),
+        Statement::Return { value: None, .. }
+        | Statement::Break { .. }
+        | Statement::Fallthrough { .. }
+        | Statement::Discard { .. }
+        | Statement::Demote { .. } => {}
+    }
+}
+
+fn collect_expression_calls<'a>(expr: &'a Expression, calls: &mut Vec<(&'a str, Span)>) {
+    match expr {
+        Expression::Constructor { args, .. } => {
+            for arg in args {
+                collect_expression_calls(arg, calls);
+            }
+        }
+        Expression::Call {
+            name, args, span, ..
+        } => {
+            for arg in args {
+                collect_expression_calls(arg, calls);
+            }
+            if calls.iter().all(|&(callee, _)| callee != name) {
+                calls.push((name, *span));
+            }
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            collect_expression_calls(left, calls);
+            collect_expression_calls(right, calls);
+        }
+        Expression::Assignment { value, .. } => collect_expression_calls(value, calls),
+        Expression::Identifier { .. }
+        | Expression::EnumVariant { .. }
+        | Expression::IntLiteral { .. }
+        | Expression::FloatLiteral { .. }
+        | Expression::BoolLiteral { .. } => {}
+    }
+}
@@ -0,0 +1,142 @@
+// src/const_eval.rs
+// This file implements compile-time evaluation of constant expressions.
+// Semantic analysis uses it wherever the language requires a value to be
+// known at compile time, such as `case` labels in a switch statement, and
+// `static if` conditions are evaluated with it against the constants the
+// driver defines.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{BinaryOp, Expression, Type};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConstValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl ConstValue {
+    pub fn ty(self) -> Type {
+        match self {
+            ConstValue::Int(_) => Type::Int,
+            ConstValue::Float(_) => Type::Float,
+            ConstValue::Bool(_) => Type::Bool,
+        }
+    }
+}
+
+// Formats the value as a literal.
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstValue::Int(value) => write!(f, "{}", value),
+            ConstValue::Float(value) => write!(f, "{:?}", value),
+            ConstValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// Evaluates the expression if it is a compile-time constant. Returns `None`
+// if it refers to runtime values, mixes types, or would overflow or divide
+// an integer by zero.
+pub fn evaluate(expr: &Expression) -> Option<ConstValue> {
+    evaluate_in(expr, &HashMap::new())
+}
+
+// Like `evaluate`, but an identifier naming one of `constants` evaluates to
+// its value.
+pub fn evaluate_in(
+    expr: &Expression,
+    constants: &HashMap<String, ConstValue>,
+) -> Option<ConstValue> {
+    match expr {
+        Expression::IntLiteral { value, .. } => Some(ConstValue::Int(*value)),
+        Expression::FloatLiteral { value, .. } => Some(ConstValue::Float(*value)),
+        Expression::BoolLiteral { value, .. } => Some(ConstValue::Bool(*value)),
+        Expression::Identifier { name, .. } => constants.get(name).copied(),
+        Expression::BinaryOp {
+            op, left, right, ..
+        } => evaluate_binary(
+            *op,
+            evaluate_in(left, constants)?,
+            evaluate_in(right, constants)?,
+        ),
+        Expression::Constructor {
+            type_name, args, ..
+        } => match args.as_slice() {
+            [arg] => convert(type_name, evaluate_in(arg, constants)?),
+            _ => None,
+        },
+        Expression::EnumVariant { .. }
+        | Expression::Assignment { .. }
+        | Expression::Call { .. } => None,
+    }
+}
+
+// Evaluates the expression and returns its value only if it is an integer.
+pub fn evaluate_int(expr: &Expression) -> Option<i64> {
+    match evaluate(expr)? {
+        ConstValue::Int(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Converts a scalar value the way a constructor such as `int(2.5)` does.
+// Float to int conversion truncates toward zero.
+fn convert(ty: &Type, value: ConstValue) -> Option<ConstValue> {
+    use ConstValue::{Bool, Float, Int};
+
+    let value = match (ty, value) {
+        (Type::Int, Int(v)) => Int(v),
+        (Type::Int, Float(v)) if v.is_finite() => Int(v.trunc() as i64),
+        (Type::Int, Bool(v)) => Int(v as i64),
+        (Type::Float, Int(v)) => Float(v as f64),
+        (Type::Float, Float(v)) => Float(v),
+        (Type::Float, Bool(v)) => Float(if v { 1.0 } else { 0.0 }),
+        (Type::Bool, Int(v)) => Bool(v != 0),
+        (Type::Bool, Float(v)) => Bool(v != 0.0),
+        (Type::Bool, Bool(v)) => Bool(v),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn evaluate_binary(op: BinaryOp, left: ConstValue, right: ConstValue) -> Option<ConstValue> {
+    use ConstValue::{Bool, Float, Int};
+
+    let value = match (left, right) {
+        (Int(l), Int(r)) => match op {
+            BinaryOp::Add => Int(l.checked_add(r)?),
+            BinaryOp::Subtract => Int(l.checked_sub(r)?),
+            BinaryOp::Multiply => Int(l.checked_mul(r)?),
+            BinaryOp::Divide => Int(l.checked_div(r)?),
+            BinaryOp::Equal => Bool(l == r),
+            BinaryOp::NotEqual => Bool(l != r),
+            BinaryOp::LessThan => Bool(l < r),
+            BinaryOp::GreaterThan => Bool(l > r),
+            BinaryOp::LessThanOrEqual => Bool(l <= r),
+            BinaryOp::GreaterThanOrEqual => Bool(l >= r),
+        },
+        (Float(l), Float(r)) => match op {
+            BinaryOp::Add => Float(l + r),
+            BinaryOp::Subtract => Float(l - r),
+            BinaryOp::Multiply => Float(l * r),
+            BinaryOp::Divide => Float(l / r),
+            BinaryOp::Equal => Bool(l == r),
+            BinaryOp::NotEqual => Bool(l != r),
+            BinaryOp::LessThan => Bool(l < r),
+            BinaryOp::GreaterThan => Bool(l > r),
+            BinaryOp::LessThanOrEqual => Bool(l <= r),
+            BinaryOp::GreaterThanOrEqual => Bool(l >= r),
+        },
+        (Bool(l), Bool(r)) => match op {
+            BinaryOp::Equal => Bool(l == r),
+            BinaryOp::NotEqual => Bool(l != r),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(value)
+}
@@ -0,0 +1,326 @@
+// src/dce.rs
+// This file implements dead code elimination, an optimization pass that
+// removes what cannot affect the output of a function: blocks control
+// never reaches, instructions whose results go unused, and local variables
+// that are only ever stored to, along with their stores.
+//
+// An instruction is kept if it has an effect beyond its result (a store, a
+// call, a barrier, an atomic or a demote) or if a kept instruction or a
+// terminator uses its result; everything else goes. Calls are all kept,
+// as the pass does not look into the functions they call. A store to a
+// local variable whose pointer is only loaded from and stored through is
+// the exception: it is kept only if a kept load reads the variable, so in
+//
+//     var float unused = 3.0;
+//     unused = unused + 1.0;
+//
+// the variable, both stores and the load all go.
+//
+// A block nothing reaches is removed, unless a reachable block names it as
+// the merge block or continue target of its selection or loop. Structured
+// control flow needs those to exist, so they are only emptied, and end in
+// `unreachable` instead.
+//
+// A loop whose continue target control never reaches runs once. If its
+// merge block is only entered from the end of the body, not broken to
+// from inside a nested selection or loop, the loop is dropped and its
+// header becomes a plain block. This undoes the loops the inliner wraps
+// around callees with early returns, once constant propagation has folded
+// the early returns away.
+//
+// A block whose only predecessor branches to it unconditionally is joined
+// to that predecessor, so the chains of branches that inlining, unrolling
+// and folded conditions leave behind become straight-line code. Loop
+// headers, and blocks a header names as its merge block or continue
+// target, are kept apart.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::dominators::Dominators;
+use crate::ir::{BlockId, Function, InstKind, Merge, Module, Terminator, Value};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            if remove_unreachable_blocks(function) {
+                preserved = Preserved::Nothing;
+            }
+            if remove_dead_instructions(function) {
+                preserved = preserved.weakest(Preserved::Cfg);
+            }
+            // The continue targets of the loops dropped are no longer
+            // named, so they go too.
+            if collapse_run_once_loops(function) {
+                remove_unreachable_blocks(function);
+                preserved = Preserved::Nothing;
+            }
+            if join_blocks(function) {
+                preserved = Preserved::Nothing;
+            }
+        }
+        preserved
+    }
+}
+
+// Whether the instruction must stay even if its result goes unused.
+fn has_side_effects(kind: &InstKind) -> bool {
+    match kind {
+        InstKind::Store(..) | InstKind::Call(..) | InstKind::Demote => true,
+        InstKind::Intrinsic(intrinsic, _) => intrinsic.has_side_effects(),
+        _ => false,
+    }
+}
+
+// Removes the blocks control never reaches, or only empties those a
+// reachable header names. Returns whether it changed any block.
+pub fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let reachable: HashSet<BlockId> = function.reverse_postorder().into_iter().collect();
+    if reachable.len() == function.blocks.len() {
+        return false;
+    }
+    let named: HashSet<BlockId> = function
+        .blocks
+        .iter()
+        .filter(|block| reachable.contains(&block.id))
+        .flat_map(|block| match block.merge {
+            Some(Merge::Selection(merge)) => vec![merge],
+            Some(Merge::Loop {
+                merge,
+                continue_target,
+                ..
+            }) => vec![merge, continue_target],
+            None => Vec::new(),
+        })
+        .collect();
+
+    let count = function.blocks.len();
+    function
+        .blocks
+        .retain(|block| reachable.contains(&block.id) || named.contains(&block.id));
+    let mut changed = function.blocks.len() != count;
+    for block in &mut function.blocks {
+        if reachable.contains(&block.id) {
+            // Unreachable predecessors no longer branch here.
+            for instruction in &mut block.instructions {
+                if let InstKind::Phi(incoming) = &mut instruction.kind {
+                    let count = incoming.len();
+                    incoming.retain(|(from, _)| reachable.contains(from));
+                    changed |= incoming.len() != count;
+                }
+            }
+        } else if !block.instructions.is_empty()
+            || block.merge.is_some()
+            || block.terminator != Terminator::Unreachable
+        {
+            block.instructions.clear();
+            block.merge = None;
+            block.terminator = Terminator::Unreachable;
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Drops the loop of each header whose continue target is unreachable and
+// whose merge block has a single predecessor, which branches to it from
+// the loop's body and not from inside a construct nested in it. Returns
+// whether it dropped any.
+fn collapse_run_once_loops(function: &mut Function) -> bool {
+    let dominators = Dominators::compute(function);
+    let predecessors = function.predecessors();
+    let mut collapsed = Vec::new();
+    for header in &function.blocks {
+        let Some(Merge::Loop {
+            merge,
+            continue_target,
+            ..
+        }) = header.merge
+        else {
+            continue;
+        };
+        if !dominators.is_reachable(header.id) || dominators.is_reachable(continue_target) {
+            continue;
+        }
+        let last = match predecessors[&merge].as_slice() {
+            [last] => *last,
+            _ => continue,
+        };
+        if function.block(last).map(|block| &block.terminator) != Some(&Terminator::Branch(merge)) {
+            continue;
+        }
+        let nested = function.blocks.iter().any(|other| {
+            let inner_merge = match other.merge {
+                Some(Merge::Selection(merge)) | Some(Merge::Loop { merge, .. }) => merge,
+                None => return false,
+            };
+            other.id != header.id
+                && dominators.dominates(header.id, other.id)
+                && dominators.dominates(other.id, last)
+                && !dominators.dominates(inner_merge, last)
+        });
+        if !nested {
+            collapsed.push(header.id);
+        }
+    }
+    for &header in &collapsed {
+        if let Some(block) = function.block_mut(header) {
+            block.merge = None;
+        }
+    }
+    !collapsed.is_empty()
+}
+
+// Joins each block entered by a single unconditional branch to the block
+// branching to it. Returns whether it joined any.
+pub fn join_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut index = 1;
+    while index < function.blocks.len() {
+        let id = function.blocks[index].id;
+        let mut predecessors = function
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, block)| block.terminator.successors().contains(&id));
+        let predecessor = match (predecessors.next(), predecessors.next()) {
+            (Some((predecessor, _)), None) if predecessor != index => predecessor,
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+        let named = function.blocks.iter().any(|block| match block.merge {
+            Some(Merge::Selection(merge)) => merge == id,
+            Some(Merge::Loop {
+                merge,
+                continue_target,
+                ..
+            }) => merge == id || continue_target == id,
+            None => false,
+        });
+        // A loop header stays the block its back edges lead to, even once
+        // they are unreachable.
+        let loop_header = matches!(function.blocks[index].merge, Some(Merge::Loop { .. }));
+        if named
+            || loop_header
+            || function.blocks[predecessor].merge.is_some()
+            || function.blocks[predecessor].terminator != Terminator::Branch(id)
+        {
+            index += 1;
+            continue;
+        }
+
+        let mut block = function.blocks.remove(index);
+        let predecessor = if predecessor > index {
+            predecessor - 1
+        } else {
+            predecessor
+        };
+        let into = function.blocks[predecessor].id;
+        // Its phis have the one value the predecessor passes.
+        let mut replacements = Vec::new();
+        block
+            .instructions
+            .retain(|instruction| match &instruction.kind {
+                InstKind::Phi(incoming) => {
+                    if let (Some(result), [(_, value)]) = (instruction.result, incoming.as_slice())
+                    {
+                        replacements.push((result, *value));
+                    }
+                    false
+                }
+                _ => true,
+            });
+        let joined = &mut function.blocks[predecessor];
+        joined.instructions.append(&mut block.instructions);
+        joined.merge = block.merge;
+        joined.terminator = block.terminator;
+        for other in &mut function.blocks {
+            for instruction in &mut other.instructions {
+                if let InstKind::Phi(incoming) = &mut instruction.kind {
+                    for (from, _) in incoming {
+                        if *from == id {
+                            *from = into;
+                        }
+                    }
+                }
+            }
+        }
+        for (from, to) in replacements {
+            function.replace_all_uses(from, to);
+        }
+        changed = true;
+    }
+    changed
+}
+
+// Removes the instructions without side effects whose results nothing
+// kept uses, and the stores to local variables nothing kept loads from.
+// Returns whether it removed any.
+fn remove_dead_instructions(function: &mut Function) -> bool {
+    let locals = function.private_variables();
+    let mut definitions: HashMap<Value, &InstKind> = HashMap::new();
+    // The values stored to each local.
+    let mut stored: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut worklist = Vec::new();
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let Some(result) = instruction.result {
+                definitions.insert(result, &instruction.kind);
+            }
+            match instruction.kind {
+                InstKind::Store(pointer, value) if locals.contains(&pointer) => {
+                    stored.entry(pointer).or_default().push(value);
+                }
+                ref kind if has_side_effects(kind) => worklist.extend(kind.operands()),
+                _ => {}
+            }
+        }
+        worklist.extend(block.terminator.operands());
+    }
+
+    // A store to a local is only kept once a kept load reads the local.
+    let mut live = HashSet::new();
+    let mut read = HashSet::new();
+    while let Some(value) = worklist.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+        let Some(kind) = definitions.get(&value) else {
+            continue;
+        };
+        if let InstKind::Load(pointer) = kind
+            && locals.contains(pointer)
+            && read.insert(*pointer)
+        {
+            worklist.extend(stored.get(pointer).into_iter().flatten().copied());
+        }
+        worklist.extend(kind.operands());
+    }
+
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let count = block.instructions.len();
+        block
+            .instructions
+            .retain(|instruction| match instruction.kind {
+                InstKind::Store(pointer, _) if locals.contains(&pointer) => read.contains(&pointer),
+                ref kind => {
+                    has_side_effects(kind)
+                        || instruction
+                            .result
+                            .is_some_and(|result| live.contains(&result))
+                }
+            });
+        changed |= block.instructions.len() != count;
+    }
+    changed
+}
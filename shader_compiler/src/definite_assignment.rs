@@ -0,0 +1,455 @@
+// src/definite_assignment.rs
+// This file implements definite-assignment analysis: a dataflow pass over a
+// function body that proves every variable is assigned before it is read,
+// and every `out` parameter is assigned before the function returns. When
+// it cannot, it reports one path on which the assignment is missed. It
+// also reports a function returning a value whose end can be reached,
+// where it would return nothing.
+//
+// An immutable variable declared without a value, like Rust's `let x;`,
+// may be assigned once on each path through the function, so it can take
+// its value from whichever branch runs. Assigning it where it may already
+// have been assigned is reported.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    CaseLabel, Expression, FunctionDefinition, ParameterQualifier, Span, Statement, SwitchCase,
+    Type,
+};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::semantic::SemanticError;
+
+// Checks one function, appending a diagnostic for every read of a possibly
+// unassigned variable, every return that can leave an `out` parameter
+// unwritten, every second assignment of an immutable variable, and a
+// missing return.
+// `functions` supplies the qualifiers of called functions.
+pub fn check(
+    function: &FunctionDefinition,
+    functions: &HashMap<&str, &FunctionDefinition>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut checker = Checker {
+        functions,
+        function,
+        variables: Vec::new(),
+        scopes: vec![HashMap::new()],
+        breaks: Vec::new(),
+        reported: HashSet::new(),
+        diagnostics,
+    };
+
+    let mut state = Some(State::default());
+    for param in &function.params {
+        let id = checker.declare(&param.name, param.span, Some(param.qualifier), false);
+        if param.qualifier == ParameterQualifier::Out
+            && let Some(state) = &mut state
+        {
+            state.unassigned.insert(id, Vec::new());
+        }
+    }
+
+    checker.statements(&function.body, &mut state);
+    checker.check_out_parameters(&state, None);
+    checker.check_end(&state);
+}
+
+// A branch taken on the way to a program point, used to explain how a
+// variable can be left unassigned.
+#[derive(Clone)]
+struct Step {
+    message: &'static str,
+    span: Span,
+}
+
+// What is known at one program point: the variables that may still be
+// unassigned, each with a path on which it is, and the immutable variables
+// that may already be assigned, each with an assignment that may have run.
+// A `None` state in place of this means the point is unreachable.
+#[derive(Clone, Default)]
+struct State {
+    unassigned: HashMap<usize, Vec<Step>>,
+    assigned: HashMap<usize, Span>,
+    // A path on which the point is reached.
+    path: Vec<Step>,
+}
+
+// Merges the states of two paths meeting at one point. A variable is
+// unassigned afterwards if it is unassigned on either path, and likewise
+// assigned.
+fn join(a: Option<State>, b: Option<State>) -> Option<State> {
+    match (a, b) {
+        (None, other) | (other, None) => other,
+        (Some(mut a), Some(b)) => {
+            for (id, path) in b.unassigned {
+                a.unassigned.entry(id).or_insert(path);
+            }
+            for (id, span) in b.assigned {
+                a.assigned.entry(id).or_insert(span);
+            }
+            Some(a)
+        }
+    }
+}
+
+// Returns a copy of the state for a path that takes the given branch.
+fn take_branch(state: &Option<State>, message: &'static str, span: Span) -> Option<State> {
+    let mut state = state.clone()?;
+    for path in state.unassigned.values_mut() {
+        path.push(Step { message, span });
+    }
+    state.path.push(Step { message, span });
+    Some(state)
+}
+
+struct Variable<'a> {
+    name: &'a str,
+    span: Span,
+    qualifier: Option<ParameterQualifier>,
+    // An immutable local declared without a value, which may only be
+    // assigned once.
+    deferred: bool,
+}
+
+struct Checker<'a, 'f> {
+    functions: &'f HashMap<&'f str, &'f FunctionDefinition>,
+    function: &'a FunctionDefinition,
+    // Every variable declared in the function. Ids index into this, so a
+    // shadowing declaration gets its own id.
+    variables: Vec<Variable<'a>>,
+    scopes: Vec<HashMap<&'a str, usize>>,
+    // For each enclosing switch, the joined state of every `break` out of it.
+    breaks: Vec<Option<State>>,
+    // Variables already reported, so each is reported at most once.
+    reported: HashSet<usize>,
+    diagnostics: &'f mut Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a, '_> {
+    fn declare(
+        &mut self,
+        name: &'a str,
+        span: Span,
+        qualifier: Option<ParameterQualifier>,
+        deferred: bool,
+    ) -> usize {
+        let id = self.variables.len();
+        self.variables.push(Variable {
+            name,
+            span,
+            qualifier,
+            deferred,
+        });
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, id);
+        }
+        id
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn block(&mut self, stmts: &'a [Statement], state: &mut Option<State>) {
+        self.scopes.push(HashMap::new());
+        self.statements(stmts, state);
+        self.scopes.pop();
+    }
+
+    fn statements(&mut self, stmts: &'a [Statement], state: &mut Option<State>) {
+        for stmt in stmts {
+            self.statement(stmt, state);
+        }
+    }
+
+    fn statement(&mut self, stmt: &'a Statement, state: &mut Option<State>) {
+        match stmt {
+            Statement::Declaration {
+                mutable,
+                name,
+                initializer,
+                span,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer, state);
+                }
+                let deferred = !*mutable && initializer.is_none();
+                let id = self.declare(name, *span, None, deferred);
+                if initializer.is_none()
+                    && let Some(state) = state
+                {
+                    state.unassigned.insert(id, Vec::new());
+                }
+            }
+            Statement::Block(stmts) => self.block(stmts, state),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                span,
+            } => {
+                self.expression(condition, state);
+                let mut then_state =
+                    take_branch(state, "assuming the condition of this `if` is true", *span);
+                self.block(if_block, &mut then_state);
+                let mut else_state =
+                    take_branch(state, "assuming the condition of this `if` is false", *span);
+                if let Some(else_block) = else_block {
+                    self.block(else_block, &mut else_state);
+                }
+                *state = join(then_state, else_state);
+            }
+            Statement::Switch {
+                selector,
+                cases,
+                span,
+            } => {
+                self.expression(selector, state);
+                self.switch(cases, *span, state);
+            }
+            Statement::StaticIf { .. } => {
+                unreachable!("`static if` is resolved before semantic analysis")
+            }
+            Statement::Match { selector, arms, .. } => {
+                self.expression(selector, state);
+                // Semantic analysis rejects a match that does not cover every
+                // value, so control always enters one of the arms.
+                let mut exits = None;
+                for arm in arms {
+                    let mut arm_state =
+                        take_branch(state, "assuming this match arm is taken", arm.span);
+                    self.block(&arm.body, &mut arm_state);
+                    exits = join(exits, arm_state);
+                }
+                *state = exits;
+            }
+            Statement::Break { .. } => {
+                if let Some(breaks) = self.breaks.last_mut() {
+                    *breaks = join(breaks.take(), state.take());
+                }
+                *state = None;
+            }
+            // A correctly placed `fallthrough` is handled by `switch`; any
+            // other is an error reported by semantic analysis.
+            Statement::Fallthrough { .. } => *state = None,
+            Statement::Discard { .. } => *state = None,
+            Statement::Demote { .. } => {}
+            Statement::Return { value, span } => {
+                if let Some(value) = value {
+                    self.expression(value, state);
+                }
+                self.check_out_parameters(state, Some(*span));
+                *state = None;
+            }
+            Statement::Expression(expr) => self.expression(expr, state),
+        }
+    }
+
+    fn switch(&mut self, cases: &'a [SwitchCase], span: Span, state: &mut Option<State>) {
+        self.breaks.push(None);
+        let mut exits = None;
+        // The state carried into the next case by a `fallthrough`.
+        let mut carried = None;
+        for (i, case) in cases.iter().enumerate() {
+            let taken = take_branch(state, "assuming this case is taken", case.span);
+            let mut case_state = join(taken, carried.take());
+
+            let has_next = i + 1 < cases.len();
+            let (body, falls_through) = match case.body.split_last() {
+                Some((Statement::Fallthrough { .. }, rest)) if has_next => (rest, true),
+                _ => (case.body.as_slice(), false),
+            };
+            self.block(body, &mut case_state);
+            if falls_through {
+                carried = case_state;
+            } else {
+                exits = join(exits, case_state);
+            }
+        }
+        exits = join(exits, self.breaks.pop().flatten());
+
+        let has_default = cases
+            .iter()
+            .flat_map(|case| &case.labels)
+            .any(|label| matches!(label, CaseLabel::Default { .. }));
+        if !has_default {
+            let unmatched = take_branch(
+                state,
+                "assuming this `switch` matches none of its cases",
+                span,
+            );
+            exits = join(exits, unmatched);
+        }
+        *state = exits;
+    }
+
+    fn expression(&mut self, expr: &'a Expression, state: &mut Option<State>) {
+        match expr {
+            Expression::Identifier { name, span } => self.read(name, *span, state),
+            Expression::Assignment { name, value, span } => {
+                self.expression(value, state);
+                self.assign(name, *span, state);
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left, state);
+                self.expression(right, state);
+            }
+            Expression::Constructor { args, .. } => {
+                for arg in args {
+                    self.expression(arg, state);
+                }
+            }
+            Expression::Call { name, args, .. } => {
+                let params = self.functions.get(name.as_str()).map(|f| &f.params);
+                // `out` and `inout` arguments are written when the call
+                // returns, after every argument has been evaluated.
+                let mut written = Vec::new();
+                for (i, arg) in args.iter().enumerate() {
+                    let qualifier = params
+                        .and_then(|params| params.get(i))
+                        .map_or(ParameterQualifier::In, |p| p.qualifier);
+                    match (qualifier, arg) {
+                        (ParameterQualifier::Out, Expression::Identifier { name, span }) => {
+                            written.push((name, *span));
+                        }
+                        (ParameterQualifier::InOut, Expression::Identifier { name, span }) => {
+                            self.read(name, *span, state);
+                            written.push((name, *span));
+                        }
+                        _ => self.expression(arg, state),
+                    }
+                }
+                for (name, span) in written {
+                    self.assign(name, span, state);
+                }
+            }
+            Expression::EnumVariant { .. }
+            | Expression::IntLiteral { .. }
+            | Expression::FloatLiteral { .. }
+            | Expression::BoolLiteral { .. } => {}
+        }
+    }
+
+    // Records an assignment at `span` to `name`, reporting it if `name` is
+    // immutable and may already have been assigned.
+    fn assign(&mut self, name: &str, span: Span, state: &mut Option<State>) {
+        let (Some(id), Some(state)) = (self.lookup(name), state) else {
+            return;
+        };
+        state.unassigned.remove(&id);
+        if !self.variables[id].deferred {
+            return;
+        }
+        let Some(&first) = state.assigned.get(&id) else {
+            state.assigned.insert(id, span);
+            return;
+        };
+        let diagnostic = Diagnostic::new(
+            Severity::Error,
+            SemanticError::ImmutableAssignment(name.to_string()).to_string(),
+        )
+        .with_span(span)
+        .with_note(
+            format!("`{}` is declared immutable here", name),
+            Some(self.variables[id].span),
+        )
+        .with_note("it may already have been assigned here", Some(first))
+        .with_help(format!(
+            "declare `{}` with `var` to assign it more than once",
+            name
+        ));
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn read(&mut self, name: &str, span: Span, state: &Option<State>) {
+        // Undeclared variables are reported by semantic analysis.
+        let Some(id) = self.lookup(name) else {
+            return;
+        };
+        let Some(path) = state.as_ref().and_then(|state| state.unassigned.get(&id)) else {
+            return;
+        };
+        if !self.reported.insert(id) {
+            return;
+        }
+
+        let variable = &self.variables[id];
+        let declared = if variable.qualifier == Some(ParameterQualifier::Out) {
+            format!(
+                "`{}` is an `out` parameter, so it starts out unassigned",
+                name
+            )
+        } else {
+            format!("`{}` is declared here without a value", name)
+        };
+        let diagnostic = Diagnostic::new(
+            Severity::Error,
+            SemanticError::PossiblyUninitialized(name.to_string()).to_string(),
+        )
+        .with_span(span)
+        .with_note(declared, Some(variable.span));
+        let diagnostic = path.iter().fold(diagnostic, |diagnostic, step| {
+            diagnostic.with_note(step.message, Some(step.span))
+        });
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Reports every `out` parameter that may be unassigned when the function
+    // returns, either at `return_span` or by reaching the end of its body.
+    fn check_out_parameters(&mut self, state: &Option<State>, return_span: Option<Span>) {
+        let Some(state) = state else {
+            return;
+        };
+        for (id, variable) in self.variables.iter().enumerate() {
+            if variable.qualifier != Some(ParameterQualifier::Out) {
+                continue;
+            }
+            let Some(path) = state.unassigned.get(&id) else {
+                continue;
+            };
+
+            let error = SemanticError::OutParameterNotWritten {
+                function: self.function.name.clone(),
+                parameter: variable.name.to_string(),
+            };
+            let mut diagnostic =
+                Diagnostic::new(Severity::Error, error.to_string()).with_span(variable.span);
+            for step in path {
+                diagnostic = diagnostic.with_note(step.message, Some(step.span));
+            }
+            diagnostic = match return_span {
+                Some(span) => diagnostic.with_note("the function returns here", Some(span)),
+                None => diagnostic.with_note("the end of the function is reached", None),
+            };
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // Reports reaching the end of a function that returns a value.
+    fn check_end(&mut self, state: &Option<State>) {
+        let Some(state) = state else {
+            return;
+        };
+        if self.function.return_type == Type::Void {
+            return;
+        }
+        let error = SemanticError::MissingReturn {
+            function: self.function.name.clone(),
+            return_type: self.function.return_type.clone(),
+        };
+        let mut diagnostic =
+            Diagnostic::new(Severity::Error, error.to_string()).with_span(self.function.span);
+        for step in &state.path {
+            diagnostic = diagnostic.with_note(step.message, Some(step.span));
+        }
+        diagnostic = diagnostic
+            .with_note("the end of the function is reached", None)
+            .with_help("return a value on every path through the function");
+        self.diagnostics.push(diagnostic);
+    }
+}
@@ -0,0 +1,126 @@
+// src/diagnostics.rs
+// This file defines the diagnostics the compiler reports to the user and
+// renders them against the source text, so each message can show the
+// file and line it refers to.
+
+use std::fmt::Write;
+
+use crate::ast::Span;
+use crate::source_map::SourceMap;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // Where the problem is, if it can be tied to one place in the source.
+    pub span: Option<Span>,
+    // Extra context, such as another location involved in the problem.
+    pub notes: Vec<Note>,
+    // A suggestion for how to fix the problem.
+    pub help: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Option<Span>) -> Self {
+        self.notes.push(Note {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    pub fn with_help(mut self, message: impl Into<String>) -> Self {
+        self.help = Some(message.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Formats the diagnostic for the terminal, quoting the source line of
+    // each location involved.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", label, self.message);
+        if let Some(span) = self.span {
+            render_span(&mut out, sources, span);
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "note: {}", note.message);
+            if let Some(span) = note.span {
+                render_span(&mut out, sources, span);
+            }
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "help: {}", help);
+        }
+        out
+    }
+}
+
+// Writes the file, line and column of the span, followed by the source
+// line it starts on with the spanned text underlined.
+fn render_span(out: &mut String, sources: &SourceMap, span: Span) {
+    let Some((file, start)) = sources.lookup(span.start) else {
+        return;
+    };
+    let source = file.text.as_str();
+    let end = span.end.saturating_sub(file.base);
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let line_number = source[..start].matches('\n').count() + 1;
+    let column = source[line_start..start].chars().count() + 1;
+    let width = source[start..end.clamp(start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+
+    let gutter = " ".repeat(line_number.to_string().len());
+    let _ = writeln!(
+        out,
+        "{}--> {}:{}:{}",
+        gutter, file.path, line_number, column
+    );
+    let _ = writeln!(out, "{} |", gutter);
+    let _ = writeln!(out, "{} | {}", line_number, &source[line_start..line_end]);
+    let _ = writeln!(
+        out,
+        "{} | {}{}",
+        gutter,
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    );
+}
@@ -0,0 +1,158 @@
+// src/dominators.rs
+// This file computes the dominator tree of a function's control flow
+// graph. Block `a` dominates block `b` if every path from the entry to `b`
+// passes through `a`. Only blocks reachable from the entry take part;
+// nothing dominates an unreachable block, and it dominates nothing.
+//
+// The tree is built with the iterative algorithm of Cooper, Harvey and
+// Kennedy, "A Simple, Fast Dominance Algorithm".
+
+use std::collections::HashMap;
+
+use crate::ir::{BlockId, Function};
+
+#[derive(Debug, Clone)]
+pub struct Dominators {
+    // The immediate dominator of each reachable block. The entry block is
+    // its own.
+    idom: HashMap<BlockId, BlockId>,
+    // The position of each reachable block in reverse postorder.
+    order: HashMap<BlockId, usize>,
+}
+
+impl Dominators {
+    pub fn compute(function: &Function) -> Self {
+        let postorder = function.reverse_postorder();
+        let order: HashMap<BlockId, usize> = postorder
+            .iter()
+            .enumerate()
+            .map(|(index, &block)| (block, index))
+            .collect();
+        let predecessors = function.predecessors();
+        let mut idom = HashMap::new();
+        let Some(&entry) = postorder.first() else {
+            return Dominators { idom, order };
+        };
+        idom.insert(entry, entry);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in &postorder[1..] {
+                // Predecessors not yet processed, or unreachable, are
+                // skipped; at least one was processed before this block.
+                let mut new_idom = None;
+                for &predecessor in &predecessors[&block] {
+                    if !idom.contains_key(&predecessor) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => intersect(&idom, &order, predecessor, current),
+                    });
+                }
+                let new_idom = new_idom.expect("a reachable block has a processed predecessor");
+                if idom.get(&block) != Some(&new_idom) {
+                    idom.insert(block, new_idom);
+                    changed = true;
+                }
+            }
+        }
+        Dominators { idom, order }
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.order.contains_key(&block)
+    }
+
+    // The closest block other than `block` itself that dominates it, or
+    // `None` for the entry and unreachable blocks.
+    pub fn immediate_dominator(&self, block: BlockId) -> Option<BlockId> {
+        self.idom.get(&block).copied().filter(|&idom| idom != block)
+    }
+
+    // Whether `a` dominates `b`. Every reachable block dominates itself.
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        let mut block = b;
+        loop {
+            if block == a {
+                return true;
+            }
+            match self.immediate_dominator(block) {
+                Some(idom) => block = idom,
+                None => return false,
+            }
+        }
+    }
+
+    // The blocks each reachable block immediately dominates, in reverse
+    // postorder.
+    pub fn children(&self) -> HashMap<BlockId, Vec<BlockId>> {
+        let mut blocks: Vec<BlockId> = self.order.keys().copied().collect();
+        blocks.sort_by_key(|block| self.order[block]);
+        let mut children: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        for block in blocks {
+            children.entry(block).or_default();
+            if let Some(idom) = self.immediate_dominator(block) {
+                children.entry(idom).or_default().push(block);
+            }
+        }
+        children
+    }
+
+    // The dominance frontier of each reachable block: the blocks it does
+    // not strictly dominate but has a predecessor of. They are where its
+    // path meets paths that bypass it, so where a value it defines may
+    // need a phi.
+    pub fn frontiers(&self, function: &Function) -> HashMap<BlockId, Vec<BlockId>> {
+        let mut frontiers: HashMap<BlockId, Vec<BlockId>> = self
+            .order
+            .keys()
+            .map(|&block| (block, Vec::new()))
+            .collect();
+        for (block, predecessors) in function.predecessors() {
+            let Some(idom) = self.immediate_dominator(block) else {
+                continue;
+            };
+            let reachable: Vec<BlockId> = predecessors
+                .into_iter()
+                .filter(|&predecessor| self.is_reachable(predecessor))
+                .collect();
+            if reachable.len() < 2 {
+                continue;
+            }
+            for mut runner in reachable {
+                while runner != idom {
+                    let frontier = frontiers.entry(runner).or_default();
+                    if !frontier.contains(&block) {
+                        frontier.push(block);
+                    }
+                    runner = self.idom[&runner];
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+// The closest common dominator of two processed blocks, found by walking
+// up the tree from whichever is later in reverse postorder.
+fn intersect(
+    idom: &HashMap<BlockId, BlockId>,
+    order: &HashMap<BlockId, usize>,
+    mut a: BlockId,
+    mut b: BlockId,
+) -> BlockId {
+    while a != b {
+        while order[&a] > order[&b] {
+            a = idom[&a];
+        }
+        while order[&b] > order[&a] {
+            b = idom[&b];
+        }
+    }
+    a
+}
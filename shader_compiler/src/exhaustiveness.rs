@@ -0,0 +1,164 @@
+// src/exhaustiveness.rs
+// This file checks the arms of a match statement against the values its
+// selector can have: that together they cover every value, and that no
+// pattern only matches values an earlier pattern already has. Every
+// selector type a match accepts is treated as a range of integers, so both
+// questions come down to which intervals the patterns cover.
+
+use crate::ast::{EnumDefinition, MatchArm, PatternKind, Span};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::semantic::SemanticError;
+
+// The values a match selector can have.
+pub enum Domain<'a> {
+    Bool,
+    Int,
+    // An enum's values are its discriminants, `0..=variants - 1`.
+    Enum(&'a EnumDefinition),
+}
+
+impl Domain<'_> {
+    fn universe(&self) -> (i128, i128) {
+        match self {
+            Domain::Bool => (0, 1),
+            Domain::Int => (i32::MIN as i128, i32::MAX as i128),
+            Domain::Enum(definition) => (0, definition.variants.len() as i128 - 1),
+        }
+    }
+
+    // The values the pattern matches, or `None` if it does not belong to
+    // this domain. Such patterns are reported by semantic analysis.
+    fn interval(&self, pattern: &PatternKind) -> Option<(i128, i128)> {
+        let interval = match (self, pattern) {
+            (_, PatternKind::Wildcard) => self.universe(),
+            (Domain::Bool, PatternKind::Bool(value)) => (*value as i128, *value as i128),
+            (Domain::Int, PatternKind::Int(value)) => (*value as i128, *value as i128),
+            (Domain::Int, PatternKind::IntRange(start, end)) if start <= end => {
+                (*start as i128, *end as i128)
+            }
+            (Domain::Enum(definition), PatternKind::EnumVariant { enum_name, variant })
+                if *enum_name == definition.name =>
+            {
+                let value = definition.discriminant(variant)? as i128;
+                (value, value)
+            }
+            _ => return None,
+        };
+        Some(interval)
+    }
+
+    // Spells the values in `start..=end` as patterns would.
+    fn describe(&self, start: i128, end: i128) -> Vec<String> {
+        match self {
+            Domain::Bool => (start..=end)
+                .map(|value| (value == 1).to_string())
+                .collect(),
+            Domain::Int if start == end => vec![start.to_string()],
+            Domain::Int => vec![format!("{}..={}", start, end)],
+            Domain::Enum(definition) => (start..=end)
+                .map(|value| {
+                    format!(
+                        "{}::{}",
+                        definition.name, definition.variants[value as usize].name
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+// The values matched so far, as sorted, disjoint, non-adjacent intervals.
+#[derive(Default)]
+struct Coverage {
+    intervals: Vec<(i128, i128)>,
+}
+
+impl Coverage {
+    fn contains(&self, (start, end): (i128, i128)) -> bool {
+        self.intervals
+            .iter()
+            .any(|&(covered_start, covered_end)| covered_start <= start && end <= covered_end)
+    }
+
+    fn insert(&mut self, (mut start, mut end): (i128, i128)) {
+        let mut kept = Vec::new();
+        for &(covered_start, covered_end) in &self.intervals {
+            if covered_end + 1 < start || end + 1 < covered_start {
+                kept.push((covered_start, covered_end));
+            } else {
+                start = start.min(covered_start);
+                end = end.max(covered_end);
+            }
+        }
+        kept.push((start, end));
+        kept.sort();
+        self.intervals = kept;
+    }
+
+    // The intervals of `universe` that nothing covers.
+    fn missing(&self, (start, end): (i128, i128)) -> Vec<(i128, i128)> {
+        let mut missing = Vec::new();
+        let mut next = start;
+        for &(covered_start, covered_end) in &self.intervals {
+            if next < covered_start {
+                missing.push((next, covered_start - 1));
+            }
+            next = next.max(covered_end + 1);
+        }
+        if next <= end {
+            missing.push((next, end));
+        }
+        missing
+    }
+}
+
+// The number of missing values listed before the rest are summarized.
+const MAX_LISTED: usize = 3;
+
+// Reports a match whose arms leave values of `domain` unhandled, and warns
+// about each pattern that an earlier one makes unreachable.
+pub fn check(domain: Domain, arms: &[MatchArm], span: Span, diagnostics: &mut Vec<Diagnostic>) {
+    let mut coverage = Coverage::default();
+    for pattern in arms.iter().flat_map(|arm| &arm.patterns) {
+        let Some(interval) = domain.interval(&pattern.kind) else {
+            continue;
+        };
+        if coverage.contains(interval) {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    SemanticError::UnreachablePattern.to_string(),
+                )
+                .with_span(pattern.span)
+                .with_help("every value it matches is matched by an earlier pattern"),
+            );
+        }
+        coverage.insert(interval);
+    }
+
+    let missing: Vec<String> = coverage
+        .missing(domain.universe())
+        .into_iter()
+        .flat_map(|(start, end)| domain.describe(start, end))
+        .collect();
+    if missing.is_empty() {
+        return;
+    }
+
+    let mut listed: Vec<String> = missing
+        .iter()
+        .take(MAX_LISTED)
+        .map(|pattern| format!("`{}`", pattern))
+        .collect();
+    if missing.len() > MAX_LISTED {
+        listed.push(format!("and {} more", missing.len() - MAX_LISTED));
+    }
+    diagnostics.push(
+        Diagnostic::new(
+            Severity::Error,
+            SemanticError::NonExhaustiveMatch(listed.join(", ")).to_string(),
+        )
+        .with_span(span)
+        .with_help("add arms for the missing values, or a `_` arm to handle them all"),
+    );
+}
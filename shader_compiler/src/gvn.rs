@@ -0,0 +1,370 @@
+// src/gvn.rs
+// This file implements global value numbering (GVN), an optimization pass
+// that finds instructions computing a value already computed, and uses the
+// earlier value instead. Two instructions compute the same value if they
+// apply the same operation to the same operands, so an expression is
+// numbered by its opcode, result type and operand values, with the
+// operands of commutative operators and intrinsics, such as `dot`, in a
+// fixed order.
+//
+// Blocks are visited down the dominator tree, and an expression seen in a
+// block is available in every block it dominates, so
+//
+//     let d = dot(n, l);
+//     if (d > 0.0) { color = color * dot(n, l); }
+//
+// computes `dot(n, l)` once. An expression computed at the start of every
+// branch of an `if` or `switch` is not available in any of them from the
+// others, so it is hoisted into the block that branches, once its operands
+// are available there.
+//
+// Only pure instructions are numbered: constants, arithmetic, conversions,
+// vector construction and the intrinsics without side effects. Loads read
+// memory that stores, calls and atomics may change in between; stores,
+// calls, barriers, atomics and demotes must all run as written. Subgroup
+// intrinsics are not numbered either, as their results depend on which
+// invocations are active where they run, which can differ between two
+// places computing the same expression. The language has no images yet, so
+// there are no image stores to keep.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Type;
+use crate::const_eval::ConstValue;
+use crate::dominators::Dominators;
+use crate::intrinsics::{BuiltIn, Intrinsic};
+use crate::ir::{BinaryOp, BlockId, Function, InstKind, IrType, Module, Terminator, Value};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Gvn;
+
+impl Pass for Gvn {
+    fn name(&self) -> &'static str {
+        "gvn"
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            let dominators = analyses.dominators(function);
+            let numbered = number_values(function, &dominators);
+            let hoisted = hoist_from_branches(function);
+            if numbered || hoisted {
+                analyses.invalidate_function(&function.name, Preserved::Cfg);
+                preserved = Preserved::Cfg;
+            }
+        }
+        preserved
+    }
+}
+
+// What a pure instruction computes.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+enum Expression {
+    // A constant's type and bits, so that 0.0 and -0.0 differ.
+    Constant(Type, u64),
+    SpecConstant(usize),
+    BuiltIn(BuiltIn),
+    Global(usize),
+    Binary(BinaryOp, Value, Value),
+    Convert(Type, Value),
+    Splat(Type, Value),
+    Construct(Type, Vec<Value>),
+    Intrinsic(Intrinsic, Vec<Value>),
+    // Phis in the same block choosing the same value on every edge.
+    Phi(BlockId, Vec<(BlockId, Value)>),
+}
+
+// The expression an instruction computes, or `None` if it is not pure.
+fn expression(
+    function: &Function,
+    result: Value,
+    kind: &InstKind,
+    block: BlockId,
+) -> Option<Expression> {
+    let ty = || match function.value_type(result) {
+        IrType::Value(ty) => Some(ty.clone()),
+        IrType::Pointer(..) => None,
+    };
+    let expression = match kind {
+        InstKind::Constant(value) => {
+            let bits = match *value {
+                ConstValue::Int(value) => value as u64,
+                ConstValue::Float(value) => value.to_bits(),
+                ConstValue::Bool(value) => value as u64,
+            };
+            Expression::Constant(value.ty(), bits)
+        }
+        InstKind::SpecConstant(index) => Expression::SpecConstant(*index),
+        InstKind::BuiltIn(built_in) => Expression::BuiltIn(*built_in),
+        InstKind::Global(index) => Expression::Global(*index),
+        InstKind::Binary(op, left, right) => {
+            let (left, right) = if is_commutative(*op) && right < left {
+                (*right, *left)
+            } else {
+                (*left, *right)
+            };
+            Expression::Binary(*op, left, right)
+        }
+        InstKind::Convert(value) => Expression::Convert(ty()?, *value),
+        InstKind::Splat(value) => Expression::Splat(ty()?, *value),
+        InstKind::Construct(args) => Expression::Construct(ty()?, args.clone()),
+        InstKind::Intrinsic(intrinsic, args)
+            if !intrinsic.has_side_effects() && !intrinsic.is_varying() =>
+        {
+            let mut args = args.clone();
+            if is_symmetric(*intrinsic) {
+                args.sort();
+            }
+            Expression::Intrinsic(*intrinsic, args)
+        }
+        InstKind::Phi(incoming) => {
+            let mut incoming = incoming.clone();
+            incoming.sort();
+            Expression::Phi(block, incoming)
+        }
+        _ => return None,
+    };
+    Some(expression)
+}
+
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Add
+            | BinaryOp::Multiply
+            | BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::And
+            | BinaryOp::Or
+    )
+}
+
+// Whether the intrinsic gives the same result with its two arguments
+// swapped.
+fn is_symmetric(intrinsic: Intrinsic) -> bool {
+    matches!(
+        intrinsic,
+        Intrinsic::Dot | Intrinsic::Distance | Intrinsic::Min | Intrinsic::Max
+    )
+}
+
+// Replaces each instruction computing a value already available with that
+// value. Returns whether it replaced any.
+fn number_values(function: &mut Function, dominators: &Dominators) -> bool {
+    let indices = function.block_indices();
+    let children = dominators.children();
+    // The value each instruction was replaced by.
+    let mut replacements: HashMap<Value, Value> = HashMap::new();
+    let mut available: HashMap<Expression, Value> = HashMap::new();
+
+    enum Walk {
+        Enter(BlockId),
+        // Leaving a block makes its expressions unavailable again.
+        Exit(Vec<Expression>),
+    }
+    let Some(entry) = function.blocks.first().map(|block| block.id) else {
+        return false;
+    };
+    let mut walk = vec![Walk::Enter(entry)];
+    while let Some(step) = walk.pop() {
+        let block = match step {
+            Walk::Enter(block) => block,
+            Walk::Exit(added) => {
+                for expression in added {
+                    available.remove(&expression);
+                }
+                continue;
+            }
+        };
+        let index = indices[&block];
+        let mut added = Vec::new();
+        let mut instructions = std::mem::take(&mut function.blocks[index].instructions);
+        instructions.retain_mut(|instruction| {
+            for operand in instruction.kind.operands_mut() {
+                if let Some(&value) = replacements.get(operand) {
+                    *operand = value;
+                }
+            }
+            let Some(result) = instruction.result else {
+                return true;
+            };
+            if let Some(value) = trivial_phi(&instruction.kind, result, block, dominators, function)
+            {
+                replacements.insert(result, value);
+                return false;
+            }
+            let Some(expression) = expression(function, result, &instruction.kind, block) else {
+                return true;
+            };
+            match available.get(&expression) {
+                Some(&value) => {
+                    replacements.insert(result, value);
+                    false
+                }
+                None => {
+                    available.insert(expression.clone(), result);
+                    added.push(expression);
+                    true
+                }
+            }
+        });
+        function.blocks[index].instructions = instructions;
+
+        walk.push(Walk::Exit(added));
+        for &child in children[&block].iter().rev() {
+            walk.push(Walk::Enter(child));
+        }
+    }
+
+    if replacements.is_empty() {
+        return false;
+    }
+    // Phis reached along back edges, and terminators, still use the
+    // replaced values.
+    for (from, to) in replacements.clone() {
+        let mut to = to;
+        while let Some(&next) = replacements.get(&to) {
+            to = next;
+        }
+        function.replace_all_uses(from, to);
+    }
+    true
+}
+
+// The value a phi always takes, if every edge brings the same one (or the
+// phi itself, around a loop) and it is defined before the phi's block.
+fn trivial_phi(
+    kind: &InstKind,
+    result: Value,
+    block: BlockId,
+    dominators: &Dominators,
+    function: &Function,
+) -> Option<Value> {
+    let InstKind::Phi(incoming) = kind else {
+        return None;
+    };
+    let mut values = incoming
+        .iter()
+        .map(|&(_, value)| value)
+        .filter(|&value| value != result);
+    let value = values.next()?;
+    if values.any(|other| other != value) {
+        return None;
+    }
+    let defined = function
+        .blocks
+        .iter()
+        .find(|b| b.instructions.iter().any(|i| i.result == Some(value)))
+        .map(|b| b.id);
+    let available = match defined {
+        Some(defined) => defined != block && dominators.dominates(defined, block),
+        // A parameter.
+        None => function.params.iter().any(|param| param.value == value),
+    };
+    available.then_some(value)
+}
+
+// Moves the expressions computed in every successor of a branch into the
+// branching block, keeping one copy. Returns whether it moved any.
+fn hoist_from_branches(function: &mut Function) -> bool {
+    let predecessors = function.predecessors();
+    let mut changed = false;
+    for index in 0..function.blocks.len() {
+        let mut successors = match &function.blocks[index].terminator {
+            Terminator::CondBranch { .. } | Terminator::Switch { .. } => {
+                function.blocks[index].terminator.successors()
+            }
+            _ => continue,
+        };
+        let mut seen = HashSet::new();
+        successors.retain(|&successor| seen.insert(successor));
+        // Each successor must only be entered from this block, or the
+        // expression would not be available on its other edges.
+        let header = function.blocks[index].id;
+        if successors.len() < 2
+            || successors
+                .iter()
+                .any(|successor| predecessors[successor] != [header])
+        {
+            continue;
+        }
+        while hoist_one(function, index, &successors) {
+            changed = true;
+        }
+    }
+    changed
+}
+
+// Hoists the first expression found in every successor whose operands are
+// all defined outside them. Returns whether it found one.
+fn hoist_one(function: &mut Function, header: usize, successors: &[BlockId]) -> bool {
+    let indices = function.block_indices();
+    let defined_in = |function: &Function, block: BlockId| -> HashSet<Value> {
+        function.blocks[indices[&block]]
+            .instructions
+            .iter()
+            .filter_map(|instruction| instruction.result)
+            .collect()
+    };
+
+    let first = successors[0];
+    let first_defined = defined_in(function, first);
+    let candidates: Vec<(Value, Expression)> = function.blocks[indices[&first]]
+        .instructions
+        .iter()
+        .filter(|instruction| !matches!(instruction.kind, InstKind::Phi(_)))
+        .filter(|instruction| {
+            instruction
+                .kind
+                .operands()
+                .iter()
+                .all(|operand| !first_defined.contains(operand))
+        })
+        .filter_map(|instruction| {
+            let result = instruction.result?;
+            Some((
+                result,
+                expression(function, result, &instruction.kind, first)?,
+            ))
+        })
+        .collect();
+
+    for (value, expression) in candidates {
+        let mut copies = Vec::new();
+        for &successor in &successors[1..] {
+            let copy = function.blocks[indices[&successor]]
+                .instructions
+                .iter()
+                .find_map(|instruction| {
+                    let result = instruction.result?;
+                    let other = self::expression(function, result, &instruction.kind, successor)?;
+                    (other == expression).then_some(result)
+                });
+            match copy {
+                Some(copy) => copies.push((successor, copy)),
+                None => break,
+            }
+        }
+        if copies.len() != successors.len() - 1 {
+            continue;
+        }
+
+        let first_index = indices[&first];
+        let position = function.blocks[first_index]
+            .instructions
+            .iter()
+            .position(|instruction| instruction.result == Some(value))
+            .expect("the candidate is in the block");
+        let instruction = function.blocks[first_index].instructions.remove(position);
+        function.blocks[header].instructions.push(instruction);
+        for (successor, copy) in copies {
+            function.blocks[indices[&successor]]
+                .instructions
+                .retain(|instruction| instruction.result != Some(copy));
+            function.replace_all_uses(copy, value);
+        }
+        return true;
+    }
+    false
+}
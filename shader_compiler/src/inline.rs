@@ -0,0 +1,451 @@
+// src/inline.rs
+// This file implements the inliner, an optimization pass that replaces
+// calls with a copy of the body of the function they call. Drivers differ
+// widely in how well they handle calls, so small helpers are better
+// inlined by the compiler, and doing it before constant propagation lets
+// that fold the arguments into the copy.
+//
+// Functions are visited callees first, so a body is copied with the calls
+// in it already inlined. A call is inlined if the callee is marked
+// `@inline(always)`, if it is the only call to the callee, or if the
+// callee's size is within the pipeline's threshold; `@inline(never)`
+// keeps every call. A call is never inlined into a function the callee
+// itself calls, directly or not, so recursion is left as it is.
+//
+// The block holding the call is split after it, and the callee's returns
+// branch to the second half, where a phi collects the returned value.
+// `out` and `inout` parameters are pointers to the caller's variables, so
+// the copy loads and stores through the same pointers the call passed.
+// The callee's variables move to the caller's entry block.
+//
+// A return inside one of the callee's selections cannot simply branch out
+// of it, as a structured selection may only be left through its merge
+// block. Such a body is wrapped in a loop that runs once, which every
+// return breaks out of, the way a `do { ... } while (false)` would. Once
+// constant propagation has folded the early returns away, dce drops the
+// loop again. A return inside a loop would have to break out of two loops
+// at once, so those callees are not inlined, and neither are calls in a
+// loop header, whose block cannot be split.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{InlineHint, Type};
+use crate::ir::{
+    Block, BlockId, Function, InstKind, Instruction, LoopControl, Merge, Module, Terminator, Value,
+};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Inliner {
+    // The largest callee, in instructions and blocks, whose calls are all
+    // inlined.
+    pub threshold: usize,
+}
+
+impl Pass for Inliner {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved {
+        let callees = call_graph(module);
+        let reachable = transitive_callees(&callees);
+        let mut call_sites: HashMap<String, usize> = HashMap::new();
+        for called in callees.values() {
+            for callee in called {
+                *call_sites.entry(callee.clone()).or_default() += 1;
+            }
+        }
+
+        let mut preserved = Preserved::All;
+        for name in bottom_up(module, &callees) {
+            let Some(index) = module.functions.iter().position(|f| f.name == name) else {
+                continue;
+            };
+            let mut changed = false;
+            // Where to look for the next call: a block index and an
+            // instruction index in it. Inlined bodies are skipped, as the
+            // calls left in them were declined when the callee was visited.
+            let mut position = (0, 0);
+            while let Some((block, call, callee)) =
+                next_call(&module.functions[index], position, &reachable)
+            {
+                let callee = module
+                    .functions
+                    .iter()
+                    .find(|f| f.name == callee)
+                    .cloned()
+                    .expect("the call graph only holds functions in the module");
+                let caller = &mut module.functions[index];
+                let shape = body_shape(&callee, analyses);
+                let returns = callee.return_type == Type::Void
+                    || callee
+                        .blocks
+                        .iter()
+                        .any(|b| matches!(b.terminator, Terminator::Return(_)));
+                let inline = returns
+                    && shape != BodyShape::ReturnsInLoop
+                    && !matches!(caller.blocks[block].merge, Some(Merge::Loop { .. }))
+                    && match callee.inline {
+                        Some(InlineHint::Always) => true,
+                        Some(InlineHint::Never) => false,
+                        None => call_sites[&callee.name] == 1 || size(&callee) <= self.threshold,
+                    };
+                if inline {
+                    position = inline_call(caller, block, call, &callee, shape);
+                    changed = true;
+                } else {
+                    position = (block, call + 1);
+                }
+            }
+            if changed {
+                analyses.invalidate_function(&name, Preserved::Nothing);
+                preserved = Preserved::Nothing;
+            }
+        }
+        preserved
+    }
+}
+
+// The first call from `position` on that may be inlined: one to another
+// function of the module that does not call the caller back. Returns its
+// block index, its index in the block and the callee.
+fn next_call(
+    caller: &Function,
+    position: (usize, usize),
+    reachable: &HashMap<String, HashSet<String>>,
+) -> Option<(usize, usize, String)> {
+    let (first_block, first_call) = position;
+    for (block, contents) in caller.blocks.iter().enumerate().skip(first_block) {
+        let start = if block == first_block { first_call } else { 0 };
+        for (call, instruction) in contents.instructions.iter().enumerate().skip(start) {
+            if let InstKind::Call(callee, _) = &instruction.kind
+                && *callee != caller.name
+                && reachable
+                    .get(callee)
+                    .is_some_and(|called| !called.contains(&caller.name))
+            {
+                return Some((block, call, callee.clone()));
+            }
+        }
+    }
+    None
+}
+
+// How the returns of a callee sit in its control flow.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum BodyShape {
+    // Every return can branch straight to the code after the call.
+    Plain,
+    // Some return is inside a selection, so the body must be wrapped in a
+    // loop for the returns to break out of.
+    ReturnsInSelection,
+    ReturnsInLoop,
+}
+
+fn body_shape(callee: &Function, analyses: &mut Analyses) -> BodyShape {
+    let dominators = analyses.dominators(callee);
+    let returns: Vec<BlockId> = callee
+        .blocks
+        .iter()
+        .filter(|block| matches!(block.terminator, Terminator::Return(_)))
+        .map(|block| block.id)
+        .collect();
+    // A return is inside a construct if its header dominates the return
+    // but its merge block does not.
+    let mut shape = if returns.len() > 1 {
+        BodyShape::ReturnsInSelection
+    } else {
+        BodyShape::Plain
+    };
+    for header in &callee.blocks {
+        let (merge, is_loop) = match header.merge {
+            Some(Merge::Selection(merge)) => (merge, false),
+            Some(Merge::Loop { merge, .. }) => (merge, true),
+            None => continue,
+        };
+        let inside = returns.iter().any(|&block| {
+            dominators.dominates(header.id, block) && !dominators.dominates(merge, block)
+        });
+        if inside && is_loop {
+            return BodyShape::ReturnsInLoop;
+        } else if inside {
+            shape = BodyShape::ReturnsInSelection;
+        }
+    }
+    shape
+}
+
+// The cost of a copy of the function.
+fn size(function: &Function) -> usize {
+    function
+        .blocks
+        .iter()
+        .map(|block| block.instructions.len() + 1)
+        .sum()
+}
+
+// The callee of every call in each function.
+fn call_graph(module: &Module) -> HashMap<String, Vec<String>> {
+    let mut graph = HashMap::new();
+    for function in &module.functions {
+        let mut callees: Vec<String> = Vec::new();
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                if let InstKind::Call(callee, _) = &instruction.kind {
+                    callees.push(callee.clone());
+                }
+            }
+        }
+        graph.insert(function.name.clone(), callees);
+    }
+    graph
+}
+
+// The functions each function calls, directly or through others.
+fn transitive_callees(graph: &HashMap<String, Vec<String>>) -> HashMap<String, HashSet<String>> {
+    let mut reachable = HashMap::new();
+    for name in graph.keys() {
+        let mut seen = HashSet::new();
+        let mut worklist: Vec<&String> = graph[name].iter().collect();
+        while let Some(callee) = worklist.pop() {
+            if seen.insert(callee.clone())
+                && let Some(called) = graph.get(callee)
+            {
+                worklist.extend(called);
+            }
+        }
+        reachable.insert(name.clone(), seen);
+    }
+    reachable
+}
+
+// Every function, each after the functions it calls, except along calls
+// that recurse.
+fn bottom_up(module: &Module, graph: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for function in &module.functions {
+        if !visited.insert(function.name.as_str()) {
+            continue;
+        }
+        // Each entry is a function and how many of its calls have been
+        // visited.
+        let mut stack = vec![(function.name.as_str(), 0)];
+        while let Some((name, next)) = stack.pop() {
+            match graph[name].get(next) {
+                Some(callee) => {
+                    stack.push((name, next + 1));
+                    if graph.contains_key(callee) && visited.insert(callee) {
+                        stack.push((callee, 0));
+                    }
+                }
+                None => order.push(name.to_string()),
+            }
+        }
+    }
+    order
+}
+
+fn fresh_block(function: &mut Function) -> BlockId {
+    let id = BlockId(function.next_block);
+    function.next_block += 1;
+    id
+}
+
+// Replaces the call at `call` in the block at index `block` with a copy of
+// the callee's body. Returns where to look for the next call: the block
+// and instruction that follow the copy.
+fn inline_call(
+    caller: &mut Function,
+    block: usize,
+    call: usize,
+    callee: &Function,
+    shape: BodyShape,
+) -> (usize, usize) {
+    let Instruction {
+        result,
+        kind: InstKind::Call(_, args),
+    } = caller.blocks[block].instructions[call].clone()
+    else {
+        unreachable!("the instruction is a call");
+    };
+    let mut values: HashMap<Value, Value> = callee
+        .params
+        .iter()
+        .map(|param| param.value)
+        .zip(args)
+        .collect();
+    for callee_block in &callee.blocks {
+        for instruction in &callee_block.instructions {
+            if let Some(value) = instruction.result {
+                let copy = caller.new_value(callee.value_type(value).clone());
+                values.insert(value, copy);
+            }
+        }
+    }
+    let mut blocks: HashMap<BlockId, BlockId> = HashMap::new();
+    let mut variables = Vec::new();
+
+    // A body of one block needs no blocks of its own: its instructions
+    // take the call's place.
+    if let [body] = callee.blocks.as_slice()
+        && let Terminator::Return(returned) = body.terminator
+    {
+        let mut copied = Vec::new();
+        for instruction in &body.instructions {
+            let instruction = copy_instruction(instruction, &values, &blocks);
+            if matches!(instruction.kind, InstKind::Variable(_)) {
+                variables.push(instruction);
+            } else {
+                copied.push(instruction);
+            }
+        }
+        let next = call + copied.len() + if block == 0 { variables.len() } else { 0 };
+        caller.blocks[block]
+            .instructions
+            .splice(call..call + 1, copied);
+        caller.blocks[0].instructions.splice(0..0, variables);
+        if let (Some(result), Some(returned)) = (result, returned) {
+            caller.replace_all_uses(result, values[&returned]);
+        }
+        return (block, next);
+    }
+
+    // The code after the call moves to a new block, which takes over the
+    // edges of the block holding the call.
+    let split = caller.blocks[block].id;
+    let rest = caller.blocks[block].instructions.split_off(call + 1);
+    caller.blocks[block].instructions.pop();
+    let continuation = Block {
+        id: fresh_block(caller),
+        instructions: rest,
+        merge: caller.blocks[block].merge.take(),
+        terminator: std::mem::replace(
+            &mut caller.blocks[block].terminator,
+            Terminator::Unreachable,
+        ),
+    };
+    let successors = continuation.terminator.successors();
+    for successor in &mut caller.blocks {
+        if !successors.contains(&successor.id) {
+            continue;
+        }
+        for instruction in &mut successor.instructions {
+            if let InstKind::Phi(incoming) = &mut instruction.kind {
+                for (from, _) in incoming {
+                    if *from == split {
+                        *from = continuation.id;
+                    }
+                }
+            }
+        }
+    }
+
+    for callee_block in &callee.blocks {
+        blocks.insert(callee_block.id, fresh_block(caller));
+    }
+    let mut inlined = Vec::new();
+    let mut returned = Vec::new();
+    for callee_block in &callee.blocks {
+        let id = blocks[&callee_block.id];
+        let mut instructions = Vec::new();
+        for instruction in &callee_block.instructions {
+            let instruction = copy_instruction(instruction, &values, &blocks);
+            if matches!(instruction.kind, InstKind::Variable(_)) {
+                variables.push(instruction);
+            } else {
+                instructions.push(instruction);
+            }
+        }
+        let merge = callee_block
+            .merge
+            .map(|merge| merge.map_blocks(|block| blocks[&block]));
+        let mut terminator = callee_block.terminator.clone();
+        if let Terminator::Return(value) = terminator {
+            returned.extend(value.map(|value| (id, values[&value])));
+            terminator = Terminator::Branch(continuation.id);
+        } else {
+            for operand in terminator.operands_mut() {
+                *operand = values[operand];
+            }
+            for successor in terminator.successors_mut() {
+                *successor = blocks[successor];
+            }
+        }
+        inlined.push(Block {
+            id,
+            instructions,
+            merge,
+            terminator,
+        });
+    }
+
+    let entry = blocks[&callee.blocks[0].id];
+    if shape == BodyShape::ReturnsInSelection {
+        let header = fresh_block(caller);
+        let continue_target = fresh_block(caller);
+        inlined.insert(
+            0,
+            Block {
+                id: header,
+                instructions: Vec::new(),
+                merge: Some(Merge::Loop {
+                    merge: continuation.id,
+                    continue_target,
+                    control: LoopControl::None,
+                }),
+                terminator: Terminator::Branch(entry),
+            },
+        );
+        // Nothing continues the loop, so its continue target is never
+        // reached.
+        inlined.push(Block {
+            id: continue_target,
+            instructions: Vec::new(),
+            merge: None,
+            terminator: Terminator::Unreachable,
+        });
+        caller.blocks[block].terminator = Terminator::Branch(header);
+    } else {
+        caller.blocks[block].terminator = Terminator::Branch(entry);
+    }
+
+    let continuation_index = block + inlined.len() + 1;
+    inlined.push(continuation);
+    caller.blocks.splice(block + 1..block + 1, inlined);
+    caller.blocks[0].instructions.splice(0..0, variables);
+
+    if let Some(result) = result {
+        match returned.as_slice() {
+            [(_, value)] => caller.replace_all_uses(result, *value),
+            _ => caller.blocks[continuation_index].instructions.insert(
+                0,
+                Instruction {
+                    result: Some(result),
+                    kind: InstKind::Phi(returned),
+                },
+            ),
+        }
+    }
+    (continuation_index, 0)
+}
+
+// Copies an instruction of the callee, renaming its values and blocks to
+// their copies in the caller.
+fn copy_instruction(
+    instruction: &Instruction,
+    values: &HashMap<Value, Value>,
+    blocks: &HashMap<BlockId, BlockId>,
+) -> Instruction {
+    let mut instruction = instruction.clone();
+    instruction.result = instruction.result.map(|value| values[&value]);
+    for operand in instruction.kind.operands_mut() {
+        *operand = values[operand];
+    }
+    if let InstKind::Phi(incoming) = &mut instruction.kind {
+        for (from, _) in incoming {
+            *from = blocks[from];
+        }
+    }
+    instruction
+}
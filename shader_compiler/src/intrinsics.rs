@@ -0,0 +1,315 @@
+// src/intrinsics.rs
+// This file defines the built-in functions (intrinsics) of the language.
+// Calls to them look like ordinary calls; semantic analysis resolves a call
+// to an intrinsic when no user function has that name, and uses the
+// signatures here to check the arguments and compute the result type. It
+// also defines the built-in variables, the read-only inputs the pipeline
+// provides to each invocation.
+
+use crate::ast::Type;
+use crate::target::Capability;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Intrinsic {
+    Abs,
+    Min,
+    Max,
+    Clamp,
+    Sqrt,
+    Floor,
+    Ceil,
+    Fract,
+    Sin,
+    Cos,
+    Tan,
+    Exp,
+    Log,
+    Pow,
+    Mix,
+    Step,
+    Smoothstep,
+    Dot,
+    Cross,
+    Length,
+    Distance,
+    Normalize,
+    // Waits until every invocation of the workgroup reaches it.
+    Barrier,
+    // Makes this invocation's writes to memory visible to the others,
+    // without waiting for them.
+    MemoryBarrier,
+    // Both: waits for the workgroup, then makes every invocation's writes
+    // to workgroup memory visible to all of them.
+    WorkgroupBarrier,
+    // Each updates an atomic variable, given as the first argument, and
+    // returns the value it held before.
+    AtomicAdd,
+    AtomicMin,
+    AtomicMax,
+    AtomicExchange,
+    // Stores the third argument only if the variable holds the second.
+    AtomicCompareExchange,
+    // Each combines or exchanges a value across the active invocations of
+    // the subgroup.
+    SubgroupAdd,
+    // A mask of the invocations whose argument is true, one bit per
+    // invocation in the four components.
+    SubgroupBallot,
+    // The value from the invocation with the given id.
+    SubgroupBroadcast,
+    SubgroupShuffle,
+    // True in exactly one active invocation.
+    SubgroupElect,
+}
+
+impl Intrinsic {
+    pub fn from_name(name: &str) -> Option<Intrinsic> {
+        let intrinsic = match name {
+            "abs" => Intrinsic::Abs,
+            "min" => Intrinsic::Min,
+            "max" => Intrinsic::Max,
+            "clamp" => Intrinsic::Clamp,
+            "sqrt" => Intrinsic::Sqrt,
+            "floor" => Intrinsic::Floor,
+            "ceil" => Intrinsic::Ceil,
+            "fract" => Intrinsic::Fract,
+            "sin" => Intrinsic::Sin,
+            "cos" => Intrinsic::Cos,
+            "tan" => Intrinsic::Tan,
+            "exp" => Intrinsic::Exp,
+            "log" => Intrinsic::Log,
+            "pow" => Intrinsic::Pow,
+            "mix" => Intrinsic::Mix,
+            "step" => Intrinsic::Step,
+            "smoothstep" => Intrinsic::Smoothstep,
+            "dot" => Intrinsic::Dot,
+            "cross" => Intrinsic::Cross,
+            "length" => Intrinsic::Length,
+            "distance" => Intrinsic::Distance,
+            "normalize" => Intrinsic::Normalize,
+            "barrier" => Intrinsic::Barrier,
+            "memory_barrier" => Intrinsic::MemoryBarrier,
+            "workgroup_barrier" => Intrinsic::WorkgroupBarrier,
+            "atomic_add" => Intrinsic::AtomicAdd,
+            "atomic_min" => Intrinsic::AtomicMin,
+            "atomic_max" => Intrinsic::AtomicMax,
+            "atomic_exchange" => Intrinsic::AtomicExchange,
+            "atomic_compare_exchange" => Intrinsic::AtomicCompareExchange,
+            "subgroup_add" => Intrinsic::SubgroupAdd,
+            "subgroup_ballot" => Intrinsic::SubgroupBallot,
+            "subgroup_broadcast" => Intrinsic::SubgroupBroadcast,
+            "subgroup_shuffle" => Intrinsic::SubgroupShuffle,
+            "subgroup_elect" => Intrinsic::SubgroupElect,
+            _ => return None,
+        };
+        Some(intrinsic)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Intrinsic::Abs => "abs",
+            Intrinsic::Min => "min",
+            Intrinsic::Max => "max",
+            Intrinsic::Clamp => "clamp",
+            Intrinsic::Sqrt => "sqrt",
+            Intrinsic::Floor => "floor",
+            Intrinsic::Ceil => "ceil",
+            Intrinsic::Fract => "fract",
+            Intrinsic::Sin => "sin",
+            Intrinsic::Cos => "cos",
+            Intrinsic::Tan => "tan",
+            Intrinsic::Exp => "exp",
+            Intrinsic::Log => "log",
+            Intrinsic::Pow => "pow",
+            Intrinsic::Mix => "mix",
+            Intrinsic::Step => "step",
+            Intrinsic::Smoothstep => "smoothstep",
+            Intrinsic::Dot => "dot",
+            Intrinsic::Cross => "cross",
+            Intrinsic::Length => "length",
+            Intrinsic::Distance => "distance",
+            Intrinsic::Normalize => "normalize",
+            Intrinsic::Barrier => "barrier",
+            Intrinsic::MemoryBarrier => "memory_barrier",
+            Intrinsic::WorkgroupBarrier => "workgroup_barrier",
+            Intrinsic::AtomicAdd => "atomic_add",
+            Intrinsic::AtomicMin => "atomic_min",
+            Intrinsic::AtomicMax => "atomic_max",
+            Intrinsic::AtomicExchange => "atomic_exchange",
+            Intrinsic::AtomicCompareExchange => "atomic_compare_exchange",
+            Intrinsic::SubgroupAdd => "subgroup_add",
+            Intrinsic::SubgroupBallot => "subgroup_ballot",
+            Intrinsic::SubgroupBroadcast => "subgroup_broadcast",
+            Intrinsic::SubgroupShuffle => "subgroup_shuffle",
+            Intrinsic::SubgroupElect => "subgroup_elect",
+        }
+    }
+
+    // The capability the target must have for the intrinsic to be used.
+    pub fn capability(self) -> Option<Capability> {
+        match self {
+            Intrinsic::SubgroupAdd
+            | Intrinsic::SubgroupBallot
+            | Intrinsic::SubgroupBroadcast
+            | Intrinsic::SubgroupShuffle
+            | Intrinsic::SubgroupElect => Some(Capability::Subgroups),
+            _ => None,
+        }
+    }
+
+    // Whether its result can differ between the invocations of a
+    // workgroup even when its arguments do not.
+    pub fn is_varying(self) -> bool {
+        self.capability() == Some(Capability::Subgroups)
+    }
+
+    // Whether its first argument is the atomic variable it operates on.
+    pub fn is_atomic(self) -> bool {
+        matches!(
+            self,
+            Intrinsic::AtomicAdd
+                | Intrinsic::AtomicMin
+                | Intrinsic::AtomicMax
+                | Intrinsic::AtomicExchange
+                | Intrinsic::AtomicCompareExchange
+        )
+    }
+
+    // Whether every invocation of the workgroup must reach the call, which
+    // also makes it usable only in compute shaders.
+    pub fn synchronizes_workgroup(self) -> bool {
+        matches!(self, Intrinsic::Barrier | Intrinsic::WorkgroupBarrier)
+    }
+
+    // Whether a call does more than compute its result, so it must stay
+    // even if the result goes unused.
+    pub fn has_side_effects(self) -> bool {
+        self.is_atomic()
+            || matches!(
+                self,
+                Intrinsic::Barrier | Intrinsic::MemoryBarrier | Intrinsic::WorkgroupBarrier
+            )
+    }
+
+    // Returns the result type of a call with the given argument types, or
+    // `None` if no overload of the intrinsic accepts them. Every overload
+    // takes all of its arguments at one scalar or vector type, and most
+    // apply component-wise, returning that same type.
+    pub fn result_type(self, args: &[Type]) -> Option<Type> {
+        if matches!(
+            self,
+            Intrinsic::Barrier | Intrinsic::MemoryBarrier | Intrinsic::WorkgroupBarrier
+        ) {
+            return args.is_empty().then_some(Type::Void);
+        }
+        // The operands of an atomic intrinsic have the atomic's element
+        // type, and so does the previous value it returns.
+        if self.is_atomic() {
+            let arity = if self == Intrinsic::AtomicCompareExchange {
+                3
+            } else {
+                2
+            };
+            let Some(Type::Atomic(element)) = args.first() else {
+                return None;
+            };
+            let operands = args[1..].iter().all(|arg| arg == element.as_ref());
+            return (args.len() == arity && operands).then(|| element.as_ref().clone());
+        }
+        // These take a value and the id of the invocation to read it from.
+        match (self, args) {
+            (Intrinsic::SubgroupElect, []) => return Some(Type::Bool),
+            (Intrinsic::SubgroupBallot, [Type::Bool]) => return Some(Type::Int4),
+            (Intrinsic::SubgroupBroadcast | Intrinsic::SubgroupShuffle, [value, Type::Int]) => {
+                return value.element().map(|_| value.clone());
+            }
+            (
+                Intrinsic::SubgroupElect
+                | Intrinsic::SubgroupBallot
+                | Intrinsic::SubgroupBroadcast
+                | Intrinsic::SubgroupShuffle,
+                _,
+            ) => return None,
+            _ => {}
+        }
+        let first = args.first()?;
+        if args.iter().any(|ty| ty != first) {
+            return None;
+        }
+        let element = first.element()?;
+        let float = element == Type::Float;
+        let numeric = float || element == Type::Int;
+
+        let (arity, accepted, result) = match self {
+            Intrinsic::Abs => (1, numeric, first.clone()),
+            Intrinsic::Min | Intrinsic::Max => (2, numeric, first.clone()),
+            Intrinsic::Clamp => (3, numeric, first.clone()),
+            Intrinsic::Sqrt
+            | Intrinsic::Floor
+            | Intrinsic::Ceil
+            | Intrinsic::Fract
+            | Intrinsic::Sin
+            | Intrinsic::Cos
+            | Intrinsic::Tan
+            | Intrinsic::Exp
+            | Intrinsic::Log
+            | Intrinsic::Normalize => (1, float, first.clone()),
+            Intrinsic::Pow | Intrinsic::Step => (2, float, first.clone()),
+            Intrinsic::Mix | Intrinsic::Smoothstep => (3, float, first.clone()),
+            // These reduce vectors to a scalar.
+            Intrinsic::SubgroupAdd => (1, numeric, first.clone()),
+            Intrinsic::Length => (1, float, element),
+            Intrinsic::Dot | Intrinsic::Distance => (2, float, element),
+            Intrinsic::Cross => (2, *first == Type::Float3, first.clone()),
+            _ => unreachable!("barriers and atomics are handled above"),
+        };
+        (args.len() == arity && accepted).then_some(result)
+    }
+}
+
+// The inputs of a compute invocation, which identify it within the
+// dispatch. They are only available in compute shaders.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum BuiltIn {
+    GlobalInvocationId,
+    LocalInvocationId,
+    LocalInvocationIndex,
+    WorkgroupId,
+    NumWorkgroups,
+}
+
+impl BuiltIn {
+    pub fn from_name(name: &str) -> Option<BuiltIn> {
+        let built_in = match name {
+            "global_invocation_id" => BuiltIn::GlobalInvocationId,
+            "local_invocation_id" => BuiltIn::LocalInvocationId,
+            "local_invocation_index" => BuiltIn::LocalInvocationIndex,
+            "workgroup_id" => BuiltIn::WorkgroupId,
+            "num_workgroups" => BuiltIn::NumWorkgroups,
+            _ => return None,
+        };
+        Some(built_in)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltIn::GlobalInvocationId => "global_invocation_id",
+            BuiltIn::LocalInvocationId => "local_invocation_id",
+            BuiltIn::LocalInvocationIndex => "local_invocation_index",
+            BuiltIn::WorkgroupId => "workgroup_id",
+            BuiltIn::NumWorkgroups => "num_workgroups",
+        }
+    }
+
+    pub fn ty(self) -> Type {
+        match self {
+            BuiltIn::LocalInvocationIndex => Type::Int,
+            _ => Type::Int3,
+        }
+    }
+
+    // Whether it has the same value in every invocation of a workgroup.
+    pub fn is_uniform(self) -> bool {
+        matches!(self, BuiltIn::WorkgroupId | BuiltIn::NumWorkgroups)
+    }
+}
@@ -0,0 +1,598 @@
+// src/ir.rs
+// This file defines the intermediate representation (IR) that the checked
+// AST is lowered to, and that optimizations and backends work on instead
+// of re-walking the AST. A function is a control flow graph of basic
+// blocks; each block is a list of instructions ending in one terminator.
+// Every value is defined exactly once (static single assignment) and has
+// a type. Local variables start out in memory, read with `Load` and
+// written with `Store`, until promotion turns them into SSA values.
+//
+// Control flow is structured the way SPIR-V requires: a block that
+// branches to several successors names the merge block where those paths
+// meet again, and a loop header also names its continue target.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{InlineHint, ShaderStage, Type};
+use crate::const_eval::ConstValue;
+use crate::intrinsics::{BuiltIn, Intrinsic};
+
+// An SSA value, numbered within its function.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct Value(pub u32);
+
+// A basic block, numbered within its function.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct BlockId(pub u32);
+
+// Where the memory a pointer points into lives.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum StorageClass {
+    // Private to the invocation and the function: local variables and
+    // `out`/`inout` parameters.
+    Function,
+    // Shared by the invocations of a compute workgroup.
+    Workgroup,
+}
+
+impl StorageClass {
+    pub fn from_name(name: &str) -> Option<StorageClass> {
+        match name {
+            "function" => Some(StorageClass::Function),
+            "workgroup" => Some(StorageClass::Workgroup),
+            _ => None,
+        }
+    }
+
+    // The name it has in textual IR.
+    pub fn name(self) -> &'static str {
+        match self {
+            StorageClass::Function => "function",
+            StorageClass::Workgroup => "workgroup",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum IrType {
+    // A scalar, vector or atomic. Enums are lowered to their `int`
+    // discriminants, so no `Type::Named` remains.
+    Value(Type),
+    Pointer(Type, StorageClass),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    pub spec_constants: Vec<SpecConstant>,
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpecConstant {
+    pub id: u32,
+    pub name: String,
+    pub default: ConstValue,
+}
+
+// A variable outside any function, addressed with `InstKind::Global`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Global {
+    pub name: String,
+    pub ty: Type,
+    pub storage: StorageClass,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub stage: Option<ShaderStage>,
+    // The `@inline(...)` attribute of the function it was lowered from.
+    pub inline: Option<InlineHint>,
+    pub params: Vec<Param>,
+    pub return_type: Type,
+    // In layout order; the first is the entry block.
+    pub blocks: Vec<Block>,
+    // The type of every value, indexed by its number.
+    pub value_types: Vec<IrType>,
+    // The next block number to hand out.
+    pub next_block: u32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    pub name: String,
+    // `out` and `inout` parameters are pointers to the caller's variable.
+    pub value: Value,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub id: BlockId,
+    // Phis come first.
+    pub instructions: Vec<Instruction>,
+    // Set on a block that starts a selection or a loop.
+    pub merge: Option<Merge>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Merge {
+    // The paths out of the block meet again at `merge`.
+    Selection(BlockId),
+    // The block is a loop header: the loop exits to `merge`, and each
+    // iteration ends by branching through `continue_target` back to it.
+    // Nothing in the language lowers to a loop yet, but hand-written IR and
+    // the passes that work on loops use this.
+    Loop {
+        merge: BlockId,
+        continue_target: BlockId,
+        control: LoopControl,
+    },
+}
+
+impl Merge {
+    // The same merge, with each block it names renamed by `rename`.
+    pub fn map_blocks(self, mut rename: impl FnMut(BlockId) -> BlockId) -> Merge {
+        match self {
+            Merge::Selection(merge) => Merge::Selection(rename(merge)),
+            Merge::Loop {
+                merge,
+                continue_target,
+                control,
+            } => Merge::Loop {
+                merge: rename(merge),
+                continue_target: rename(continue_target),
+                control,
+            },
+        }
+    }
+}
+
+// Whether a loop should be unrolled, as asked for by an `@unroll` or
+// `@loop` hint. Nothing in the language lowers to a loop yet, so only
+// hand-written IR carries one.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum LoopControl {
+    // The optimizer decides.
+    #[default]
+    None,
+    Unroll,
+    DontUnroll,
+}
+
+impl LoopControl {
+    pub fn from_name(name: &str) -> Option<LoopControl> {
+        match name {
+            "unroll" => Some(LoopControl::Unroll),
+            "dont_unroll" => Some(LoopControl::DontUnroll),
+            _ => None,
+        }
+    }
+
+    // The name written after `loop_merge`, or `None` if nothing is.
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            LoopControl::None => None,
+            LoopControl::Unroll => Some("unroll"),
+            LoopControl::DontUnroll => Some("dont_unroll"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Instruction {
+    // `None` for instructions that produce nothing, such as a store.
+    pub result: Option<Value>,
+    pub kind: InstKind,
+}
+
+// Operators on scalars and vectors. Arithmetic works component-wise on
+// operands of one type; comparisons produce a single bool, so vectors
+// compare equal only if every component does.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessThanOrEqual,
+    GreaterThanOrEqual,
+    // On bools only.
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn from_name(name: &str) -> Option<BinaryOp> {
+        let op = match name {
+            "add" => BinaryOp::Add,
+            "sub" => BinaryOp::Subtract,
+            "mul" => BinaryOp::Multiply,
+            "div" => BinaryOp::Divide,
+            "eq" => BinaryOp::Equal,
+            "ne" => BinaryOp::NotEqual,
+            "lt" => BinaryOp::LessThan,
+            "gt" => BinaryOp::GreaterThan,
+            "le" => BinaryOp::LessThanOrEqual,
+            "ge" => BinaryOp::GreaterThanOrEqual,
+            "and" => BinaryOp::And,
+            "or" => BinaryOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // The opcode it has in textual IR.
+    pub fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Subtract => "sub",
+            BinaryOp::Multiply => "mul",
+            BinaryOp::Divide => "div",
+            BinaryOp::Equal => "eq",
+            BinaryOp::NotEqual => "ne",
+            BinaryOp::LessThan => "lt",
+            BinaryOp::GreaterThan => "gt",
+            BinaryOp::LessThanOrEqual => "le",
+            BinaryOp::GreaterThanOrEqual => "ge",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum InstKind {
+    // A scalar constant. Vector constants are built with `Construct`.
+    Constant(ConstValue),
+    // The value of a specialization constant, by index in the module.
+    SpecConstant(usize),
+    BuiltIn(BuiltIn),
+    // A pointer to a global, by index in the module.
+    Global(usize),
+    // A function-local variable, with the name it has in source. It must
+    // be in the entry block.
+    Variable(String),
+    Load(Value),
+    // Stores the second value through the first.
+    Store(Value, Value),
+    Binary(BinaryOp, Value, Value),
+    // Converts a scalar or vector to the result's element type, keeping
+    // its width.
+    Convert(Value),
+    // Repeats a scalar in every component of the result.
+    Splat(Value),
+    // Concatenates the components of scalars and vectors into the result.
+    Construct(Vec<Value>),
+    Call(String, Vec<Value>),
+    // Atomics take a pointer to the variable as their first argument.
+    Intrinsic(Intrinsic, Vec<Value>),
+    // Turns a fragment invocation into a helper invocation.
+    Demote,
+    // The value flowing in from each predecessor block.
+    Phi(Vec<(BlockId, Value)>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Terminator {
+    Branch(BlockId),
+    CondBranch {
+        condition: Value,
+        if_true: BlockId,
+        if_false: BlockId,
+    },
+    // Jumps to the block of the first case equal to the int selector, or
+    // to `default`.
+    Switch {
+        selector: Value,
+        cases: Vec<(i64, BlockId)>,
+        default: BlockId,
+    },
+    Return(Option<Value>),
+    // Ends the fragment invocation.
+    Discard,
+    // Marks a block control never reaches, such as the merge block of a
+    // selection whose every path returns.
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Branch(target) => vec![*target],
+            Terminator::CondBranch {
+                if_true, if_false, ..
+            } => vec![*if_true, *if_false],
+            Terminator::Switch { cases, default, .. } => {
+                let mut successors: Vec<BlockId> = cases.iter().map(|&(_, block)| block).collect();
+                successors.push(*default);
+                successors
+            }
+            Terminator::Return(_) | Terminator::Discard | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    // Every value the terminator uses.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::CondBranch { condition, .. } => vec![*condition],
+            Terminator::Switch { selector, .. } => vec![*selector],
+            Terminator::Return(Some(value)) => vec![*value],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::CondBranch { condition, .. } => vec![condition],
+            Terminator::Switch { selector, .. } => vec![selector],
+            Terminator::Return(Some(value)) => vec![value],
+            _ => Vec::new(),
+        }
+    }
+
+    // Every block the terminator can branch to, for renaming them.
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Branch(target) => vec![target],
+            Terminator::CondBranch {
+                if_true, if_false, ..
+            } => vec![if_true, if_false],
+            Terminator::Switch { cases, default, .. } => {
+                let mut successors: Vec<&mut BlockId> =
+                    cases.iter_mut().map(|(_, block)| block).collect();
+                successors.push(default);
+                successors
+            }
+            Terminator::Return(_) | Terminator::Discard | Terminator::Unreachable => Vec::new(),
+        }
+    }
+}
+
+impl InstKind {
+    // The name of the instruction in textual IR.
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            InstKind::Constant(_) => "const",
+            InstKind::SpecConstant(_) => "spec_constant",
+            InstKind::BuiltIn(_) => "builtin",
+            InstKind::Global(_) => "global",
+            InstKind::Variable(_) => "variable",
+            InstKind::Load(_) => "load",
+            InstKind::Store(..) => "store",
+            InstKind::Binary(op, ..) => op.name(),
+            InstKind::Convert(_) => "convert",
+            InstKind::Splat(_) => "splat",
+            InstKind::Construct(_) => "construct",
+            InstKind::Call(..) => "call",
+            InstKind::Intrinsic(..) => "intrinsic",
+            InstKind::Demote => "demote",
+            InstKind::Phi(_) => "phi",
+        }
+    }
+
+    // Every value the instruction uses.
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            InstKind::Load(value) | InstKind::Convert(value) | InstKind::Splat(value) => {
+                vec![*value]
+            }
+            InstKind::Store(pointer, value) => vec![*pointer, *value],
+            InstKind::Binary(_, left, right) => vec![*left, *right],
+            InstKind::Construct(args) | InstKind::Call(_, args) | InstKind::Intrinsic(_, args) => {
+                args.clone()
+            }
+            InstKind::Phi(incoming) => incoming.iter().map(|&(_, value)| value).collect(),
+            InstKind::Constant(_)
+            | InstKind::SpecConstant(_)
+            | InstKind::BuiltIn(_)
+            | InstKind::Global(_)
+            | InstKind::Variable(_)
+            | InstKind::Demote => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            InstKind::Load(value) | InstKind::Convert(value) | InstKind::Splat(value) => {
+                vec![value]
+            }
+            InstKind::Store(pointer, value) => vec![pointer, value],
+            InstKind::Binary(_, left, right) => vec![left, right],
+            InstKind::Construct(args) | InstKind::Call(_, args) | InstKind::Intrinsic(_, args) => {
+                args.iter_mut().collect()
+            }
+            InstKind::Phi(incoming) => incoming.iter_mut().map(|(_, value)| value).collect(),
+            InstKind::Constant(_)
+            | InstKind::SpecConstant(_)
+            | InstKind::BuiltIn(_)
+            | InstKind::Global(_)
+            | InstKind::Variable(_)
+            | InstKind::Demote => Vec::new(),
+        }
+    }
+
+    // Whether the instruction computes its result from its operands alone,
+    // the same wherever it runs, so it can be moved or run more or less
+    // often. Loads read memory that may change; subgroup intrinsics depend
+    // on which invocations are active where they run.
+    pub fn is_pure(&self) -> bool {
+        match self {
+            InstKind::Constant(_)
+            | InstKind::SpecConstant(_)
+            | InstKind::BuiltIn(_)
+            | InstKind::Global(_)
+            | InstKind::Binary(..)
+            | InstKind::Convert(_)
+            | InstKind::Splat(_)
+            | InstKind::Construct(_) => true,
+            InstKind::Intrinsic(intrinsic, _) => {
+                !intrinsic.has_side_effects() && !intrinsic.is_varying()
+            }
+            InstKind::Variable(_)
+            | InstKind::Load(_)
+            | InstKind::Store(..)
+            | InstKind::Call(..)
+            | InstKind::Demote
+            | InstKind::Phi(_) => false,
+        }
+    }
+}
+
+impl Function {
+    // Creates an empty function; `lower` and the IR parser fill it in.
+    pub fn new(name: String, stage: Option<ShaderStage>, return_type: Type) -> Self {
+        Function {
+            name,
+            stage,
+            inline: None,
+            params: Vec::new(),
+            return_type,
+            blocks: Vec::new(),
+            value_types: Vec::new(),
+            next_block: 0,
+        }
+    }
+
+    pub fn new_value(&mut self, ty: IrType) -> Value {
+        self.value_types.push(ty);
+        Value(self.value_types.len() as u32 - 1)
+    }
+
+    pub fn value_type(&self, value: Value) -> &IrType {
+        &self.value_types[value.0 as usize]
+    }
+
+    // Appends an empty block, ending in `Unreachable` until it is given a
+    // terminator.
+    pub fn new_block(&mut self) -> BlockId {
+        let id = BlockId(self.next_block);
+        self.next_block += 1;
+        self.blocks.push(Block {
+            id,
+            instructions: Vec::new(),
+            merge: None,
+            terminator: Terminator::Unreachable,
+        });
+        id
+    }
+
+    // The position of each block in `blocks`.
+    pub fn block_indices(&self) -> HashMap<BlockId, usize> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.id, index))
+            .collect()
+    }
+
+    pub fn block(&self, id: BlockId) -> Option<&Block> {
+        self.blocks.iter().find(|block| block.id == id)
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> Option<&mut Block> {
+        self.blocks.iter_mut().find(|block| block.id == id)
+    }
+
+    // The blocks reachable from the entry, each before its successors
+    // except along back edges.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let Some(entry) = self.blocks.first() else {
+            return Vec::new();
+        };
+        let successors: HashMap<BlockId, Vec<BlockId>> = self
+            .blocks
+            .iter()
+            .map(|block| (block.id, block.terminator.successors()))
+            .collect();
+        let mut visited = HashSet::from([entry.id]);
+        let mut postorder = Vec::new();
+        // Each entry is a block and how many of its successors have been
+        // visited.
+        let mut stack = vec![(entry.id, 0)];
+        while let Some((block, next)) = stack.pop() {
+            match successors[&block].get(next) {
+                Some(&successor) => {
+                    stack.push((block, next + 1));
+                    if successors.contains_key(&successor) && visited.insert(successor) {
+                        stack.push((successor, 0));
+                    }
+                }
+                None => postorder.push(block),
+            }
+        }
+        postorder.reverse();
+        postorder
+    }
+
+    // The blocks that branch to each block.
+    pub fn predecessors(&self) -> HashMap<BlockId, Vec<BlockId>> {
+        let mut predecessors: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        for block in &self.blocks {
+            predecessors.entry(block.id).or_default();
+            for successor in block.terminator.successors() {
+                let entry = predecessors.entry(successor).or_default();
+                if !entry.contains(&block.id) {
+                    entry.push(block.id);
+                }
+            }
+        }
+        predecessors
+    }
+
+    // The local variables whose pointers are only loaded from and stored
+    // through, so every read and write of them is a load or store in this
+    // function.
+    pub fn private_variables(&self) -> HashSet<Value> {
+        let mut variables = HashSet::new();
+        for block in &self.blocks {
+            for instruction in &block.instructions {
+                if let (Some(result), InstKind::Variable(_)) =
+                    (instruction.result, &instruction.kind)
+                {
+                    variables.insert(result);
+                }
+            }
+        }
+        for block in &self.blocks {
+            for instruction in &block.instructions {
+                let escaping = match &instruction.kind {
+                    InstKind::Load(_) => Vec::new(),
+                    InstKind::Store(_, value) => vec![*value],
+                    kind => kind.operands(),
+                };
+                for operand in escaping {
+                    variables.remove(&operand);
+                }
+            }
+            for operand in block.terminator.operands() {
+                variables.remove(&operand);
+            }
+        }
+        variables
+    }
+
+    // Makes every instruction and terminator that uses `from` use `to`
+    // instead.
+    pub fn replace_all_uses(&mut self, from: Value, to: Value) {
+        for block in &mut self.blocks {
+            for instruction in &mut block.instructions {
+                for operand in instruction.kind.operands_mut() {
+                    if *operand == from {
+                        *operand = to;
+                    }
+                }
+            }
+            for operand in block.terminator.operands_mut() {
+                if *operand == from {
+                    *operand = to;
+                }
+            }
+        }
+    }
+}
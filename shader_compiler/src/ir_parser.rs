@@ -0,0 +1,964 @@
+// src/ir_parser.rs
+// This file parses textual IR (`.osir`), as written by `ir_printer` or by
+// hand, back into an IR module. Values and blocks keep the numbers they
+// are written with, so printing the result gives back the same text up to
+// layout and comments, which start with `;` and run to the end of the
+// line.
+//
+// The parser checks what it needs to build a module: the syntax, that
+// every name refers to something declared above it, that each value and
+// block is defined once and that every value and block used is defined
+// somewhere in its function. Whether the module is valid IR, such as
+// whether definitions dominate their uses, is left to the verifier.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{InlineHint, ShaderStage, Span, Type};
+use crate::const_eval::ConstValue;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::intrinsics::{BuiltIn, Intrinsic};
+use crate::ir::{
+    BinaryOp, Block, BlockId, Function, Global, InstKind, Instruction, IrType, LoopControl, Merge,
+    Module, Param, SpecConstant, StorageClass, Terminator, Value,
+};
+use crate::ir_printer::{block_name, function_name, ir_type, value};
+
+// Values and blocks are numbered below this. A function holds a type for
+// every value number up to the largest it uses, so a stray huge number
+// would otherwise allocate a table that large.
+const MAX_NUMBER: u32 = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub enum IrParseError {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    // `found` describes the token that was there instead.
+    Expected { expected: String, found: String },
+    InvalidNumber(String),
+    // A value or block numbered `MAX_NUMBER` or above.
+    NumberTooLarge(String),
+    UnknownType(String),
+    UnknownOpcode(String),
+    UnknownIntrinsic(String),
+    UnknownBuiltIn(String),
+    UnknownStorageClass(String),
+    UnknownSpecConstant(String),
+    UnknownGlobal(String),
+    InvalidConstant { literal: String, ty: String },
+    // An instruction that produces a value written without a result, or
+    // the other way around.
+    MissingResult(String),
+    UnexpectedResult(String),
+    // A spec constant, global or function declared twice.
+    DuplicateName(String),
+    DuplicateValue(Value),
+    DuplicateBlock(BlockId),
+    UndefinedValue(Value),
+    UndefinedBlock(BlockId),
+    EmptyFunction(String),
+}
+
+impl fmt::Display for IrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrParseError::UnexpectedCharacter(c) => write!(f, "unexpected character `{}`", c),
+            IrParseError::UnterminatedString => write!(f, "unterminated string"),
+            IrParseError::Expected { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            IrParseError::InvalidNumber(text) => write!(f, "invalid number `{}`", text),
+            IrParseError::NumberTooLarge(text) => write!(
+                f,
+                "`{}` is too large; values and blocks are numbered below {}",
+                text, MAX_NUMBER
+            ),
+            IrParseError::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            IrParseError::UnknownOpcode(name) => write!(f, "unknown instruction `{}`", name),
+            IrParseError::UnknownIntrinsic(name) => write!(f, "unknown intrinsic `{}`", name),
+            IrParseError::UnknownBuiltIn(name) => write!(f, "unknown built-in `{}`", name),
+            IrParseError::UnknownStorageClass(name) => {
+                write!(f, "unknown storage class `{}`", name)
+            }
+            IrParseError::UnknownSpecConstant(name) => {
+                write!(f, "no specialization constant named `{}`", name)
+            }
+            IrParseError::UnknownGlobal(name) => write!(f, "no global named `{}`", name),
+            IrParseError::InvalidConstant { literal, ty } => {
+                write!(f, "`{}` is not a constant of type `{}`", literal, ty)
+            }
+            IrParseError::MissingResult(opcode) => {
+                write!(f, "`{}` produces a value, but has no result", opcode)
+            }
+            IrParseError::UnexpectedResult(opcode) => {
+                write!(f, "`{}` produces no value, but has a result", opcode)
+            }
+            IrParseError::DuplicateName(name) => write!(f, "`{}` is declared twice", name),
+            IrParseError::DuplicateValue(v) => write!(f, "{} is defined twice", value(*v)),
+            IrParseError::DuplicateBlock(block) => {
+                write!(f, "{} is defined twice", block_name(*block))
+            }
+            IrParseError::UndefinedValue(v) => write!(f, "{} is never defined", value(*v)),
+            IrParseError::UndefinedBlock(block) => {
+                write!(f, "{} is never defined", block_name(*block))
+            }
+            IrParseError::EmptyFunction(name) => {
+                write!(f, "function `{}` has no blocks", name)
+            }
+        }
+    }
+}
+
+// Parses the text of one `.osir` file whose first byte is at offset `base`
+// in the source map.
+pub fn parse(text: &str, base: usize) -> Result<Module, Vec<Diagnostic>> {
+    let tokens = lex(text, base).map_err(|diagnostic| vec![diagnostic])?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        module: Module {
+            spec_constants: Vec::new(),
+            globals: Vec::new(),
+            functions: Vec::new(),
+        },
+        diagnostics: Vec::new(),
+    };
+    if let Err(diagnostic) = parser.parse_module() {
+        parser.diagnostics.push(diagnostic);
+    }
+    if parser.diagnostics.is_empty() {
+        Ok(parser.module)
+    } else {
+        Err(parser.diagnostics)
+    }
+}
+
+fn error(error: IrParseError, span: Span) -> Diagnostic {
+    Diagnostic::new(Severity::Error, error.to_string()).with_span(span)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Ident(String),
+    // `%3`
+    Value(u32),
+    // `@name` or `@"name"`
+    Function(String),
+    // An int or float literal, kept as written until its type is known.
+    Number(String),
+    Punct(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "`{}`", name),
+            Token::Value(number) => write!(f, "`%{}`", number),
+            Token::Function(name) => write!(f, "`{}`", function_name(name)),
+            Token::Number(text) => write!(f, "`{}`", text),
+            Token::Punct(punct) => write!(f, "`{}`", punct),
+            Token::End => write!(f, "end of file"),
+        }
+    }
+}
+
+const PUNCTUATION: [&str; 12] = ["->", ":", ",", "=", "(", ")", "{", "}", "[", "]", "<", ">"];
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn lex(text: &str, base: usize) -> Result<Vec<(Token, Span)>, Diagnostic> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &text[start..];
+        let span_to = |end: usize| Span {
+            start: base + start,
+            end: base + end,
+        };
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == ';' {
+            let end = rest.find('\n').map_or(text.len(), |i| start + i);
+            while chars.next_if(|&(i, _)| i < end).is_some() {}
+            continue;
+        }
+        let starts_number = c.is_ascii_digit()
+            || (c == '-' && rest[1..].starts_with(|next: char| next.is_ascii_alphanumeric()));
+        let (token, end) = if starts_number {
+            // Digits, letters for `inf` and exponents, `.`, and a sign
+            // right after an exponent's `e`.
+            let mut end = start + 1;
+            let mut previous = c;
+            for (i, next) in text[end..].char_indices() {
+                let exponent_sign = (next == '-' || next == '+') && matches!(previous, 'e' | 'E');
+                if !(is_identifier_char(next) || next == '.' || exponent_sign) {
+                    break;
+                }
+                end = start + 1 + i + next.len_utf8();
+                previous = next;
+            }
+            (Token::Number(text[start..end].to_string()), end)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|next: char| !is_identifier_char(next))
+                .map_or(text.len(), |i| start + i);
+            (Token::Ident(text[start..end].to_string()), end)
+        } else if c == '%' {
+            let end = rest[1..]
+                .find(|next: char| !next.is_ascii_digit())
+                .map_or(text.len(), |i| start + 1 + i);
+            let digits = &text[start + 1..end];
+            let number = digits.parse().map_err(|_| {
+                let found = if digits.is_empty() {
+                    "%"
+                } else {
+                    &text[start..end]
+                };
+                error(IrParseError::InvalidNumber(found.to_string()), span_to(end))
+            })?;
+            if number >= MAX_NUMBER {
+                let found = text[start..end].to_string();
+                return Err(error(IrParseError::NumberTooLarge(found), span_to(end)));
+            }
+            (Token::Value(number), end)
+        } else if c == '@' {
+            if let Some(quoted) = rest[1..].strip_prefix('"') {
+                let Some(length) = quoted
+                    .find(['"', '\n'])
+                    .filter(|&i| quoted[i..].starts_with('"'))
+                else {
+                    return Err(error(IrParseError::UnterminatedString, span_to(start + 2)));
+                };
+                (
+                    Token::Function(quoted[..length].to_string()),
+                    start + 2 + length + 1,
+                )
+            } else {
+                let end = rest[1..]
+                    .find(|next: char| !is_identifier_char(next))
+                    .map_or(text.len(), |i| start + 1 + i);
+                if end == start + 1 {
+                    return Err(error(IrParseError::UnexpectedCharacter('@'), span_to(end)));
+                }
+                (Token::Function(text[start + 1..end].to_string()), end)
+            }
+        } else if let Some(punct) = PUNCTUATION.into_iter().find(|p| rest.starts_with(p)) {
+            (Token::Punct(punct), start + punct.len())
+        } else {
+            return Err(error(
+                IrParseError::UnexpectedCharacter(c),
+                span_to(start + c.len_utf8()),
+            ));
+        };
+        tokens.push((token, span_to(end)));
+        while chars.next_if(|&(i, _)| i < end).is_some() {}
+    }
+    let end = base + text.len();
+    tokens.push((Token::End, Span { start: end, end }));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Span)>,
+    position: usize,
+    module: Module,
+    // Errors that do not stop parsing. A syntax error ends it, and is
+    // returned through `Result` instead.
+    diagnostics: Vec<Diagnostic>,
+}
+
+// What is known about the function being parsed.
+struct FunctionState {
+    function: Function,
+    // Where each value and block is defined.
+    values: HashMap<Value, (IrType, Span)>,
+    blocks: HashMap<BlockId, Span>,
+    // Every value and block used, and where.
+    value_uses: Vec<(Value, Span)>,
+    block_uses: Vec<(BlockId, Span)>,
+}
+
+impl FunctionState {
+    fn use_value(&mut self, value: Value, span: Span) -> Value {
+        self.value_uses.push((value, span));
+        value
+    }
+
+    fn use_block(&mut self, block: BlockId, span: Span) -> BlockId {
+        self.block_uses.push((block, span));
+        block
+    }
+}
+
+type ParseResult<T> = Result<T, Diagnostic>;
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position].0
+    }
+
+    fn peek_span(&self) -> Span {
+        self.tokens[self.position].1
+    }
+
+    fn advance(&mut self) -> (Token, Span) {
+        let token = self.tokens[self.position].clone();
+        if token.0 != Token::End {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn expected(&self, expected: &str) -> Diagnostic {
+        error(
+            IrParseError::Expected {
+                expected: expected.to_string(),
+                found: self.peek().to_string(),
+            },
+            self.peek_span(),
+        )
+    }
+
+    fn eat_punct(&mut self, punct: &'static str) -> bool {
+        if *self.peek() == Token::Punct(punct) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, punct: &'static str) -> ParseResult<()> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{}`", punct)))
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Token::Ident(name) if name == keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> ParseResult<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{}`", keyword)))
+        }
+    }
+
+    fn expect_ident(&mut self, expected: &str) -> ParseResult<(String, Span)> {
+        match self.peek().clone() {
+            Token::Ident(name) => Ok((name, self.advance().1)),
+            _ => Err(self.expected(expected)),
+        }
+    }
+
+    fn expect_number<T: std::str::FromStr>(&mut self, expected: &str) -> ParseResult<T> {
+        match self.peek().clone() {
+            Token::Number(text) => {
+                let span = self.advance().1;
+                text.parse()
+                    .map_err(|_| error(IrParseError::InvalidNumber(text), span))
+            }
+            _ => Err(self.expected(expected)),
+        }
+    }
+
+    fn parse_module(&mut self) -> ParseResult<()> {
+        loop {
+            match self.peek().clone() {
+                Token::End => return Ok(()),
+                Token::Ident(keyword) if keyword == "spec_constant" => {
+                    self.advance();
+                    self.parse_spec_constant()?;
+                }
+                Token::Ident(keyword) if keyword == "global" => {
+                    self.advance();
+                    self.parse_global()?;
+                }
+                _ => self.parse_function()?,
+            }
+        }
+    }
+
+    // spec_constant 1 COUNT: int = 4
+    fn parse_spec_constant(&mut self) -> ParseResult<()> {
+        let id = self.expect_number("a specialization constant id")?;
+        let (name, span) = self.expect_ident("a name")?;
+        self.expect_punct(":")?;
+        let ty = self.parse_type()?;
+        self.expect_punct("=")?;
+        let default = self.parse_constant(&ty)?;
+        if self.is_declared(&name) {
+            self.diagnostics
+                .push(error(IrParseError::DuplicateName(name.clone()), span));
+        }
+        self.module
+            .spec_constants
+            .push(SpecConstant { id, name, default });
+        Ok(())
+    }
+
+    // global workgroup partial: float
+    fn parse_global(&mut self) -> ParseResult<()> {
+        let storage = self.parse_storage_class()?;
+        let (name, span) = self.expect_ident("a name")?;
+        self.expect_punct(":")?;
+        let ty = self.parse_value_type()?;
+        if self.is_declared(&name) {
+            self.diagnostics
+                .push(error(IrParseError::DuplicateName(name.clone()), span));
+        }
+        self.module.globals.push(Global { name, ty, storage });
+        Ok(())
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.module.spec_constants.iter().any(|c| c.name == name)
+            || self.module.globals.iter().any(|g| g.name == name)
+    }
+
+    // [stage] [inline(always)] fn @name(%0 a: float, ...) -> type { blocks }
+    fn parse_function(&mut self) -> ParseResult<()> {
+        let stage = match self.peek().clone() {
+            Token::Ident(name) if name != "fn" && name != "inline" => {
+                let stage = [
+                    ShaderStage::Vertex,
+                    ShaderStage::Fragment,
+                    ShaderStage::Compute,
+                ]
+                .into_iter()
+                .find(|&stage| stage.name() == name);
+                if stage.is_none() {
+                    return Err(self.expected("`spec_constant`, `global`, a stage or `fn`"));
+                }
+                self.advance();
+                stage
+            }
+            _ => None,
+        };
+        let mut inline = None;
+        if self.eat_keyword("inline") {
+            self.expect_punct("(")?;
+            inline = match self.peek() {
+                Token::Ident(name) => InlineHint::from_name(name),
+                _ => None,
+            };
+            if inline.is_none() {
+                return Err(self.expected("`always` or `never`"));
+            }
+            self.advance();
+            self.expect_punct(")")?;
+        }
+        self.expect_keyword("fn")?;
+        let (name, name_span) = match self.peek().clone() {
+            Token::Function(name) => (name, self.advance().1),
+            _ => return Err(self.expected("a function name")),
+        };
+        if self.module.functions.iter().any(|f| f.name == name) {
+            self.diagnostics
+                .push(error(IrParseError::DuplicateName(name.clone()), name_span));
+        }
+
+        self.expect_punct("(")?;
+        let mut params = Vec::new();
+        if !self.eat_punct(")") {
+            loop {
+                let (value, span) = self.expect_value()?;
+                let (param_name, _) = self.expect_ident("a parameter name")?;
+                self.expect_punct(":")?;
+                let ty = self.parse_type()?;
+                params.push((param_name, value, ty, span));
+                if self.eat_punct(")") {
+                    break;
+                }
+                self.expect_punct(",")?;
+            }
+        }
+        self.expect_punct("->")?;
+        let return_type = self.parse_value_type()?;
+
+        let mut state = FunctionState {
+            function: Function {
+                inline,
+                ..Function::new(name.clone(), stage, return_type)
+            },
+            values: HashMap::new(),
+            blocks: HashMap::new(),
+            value_uses: Vec::new(),
+            block_uses: Vec::new(),
+        };
+        for (param_name, value, ty, span) in params {
+            self.define_value(&mut state, value, ty, span);
+            state.function.params.push(Param {
+                name: param_name,
+                value,
+            });
+        }
+        self.expect_punct("{")?;
+        while !self.eat_punct("}") {
+            self.parse_block(&mut state)?;
+        }
+        if state.function.blocks.is_empty() {
+            self.diagnostics
+                .push(error(IrParseError::EmptyFunction(name), name_span));
+        }
+        self.finish_function(state);
+        Ok(())
+    }
+
+    // Checks that every value and block used is defined, and fills in the
+    // value types and block count.
+    fn finish_function(&mut self, mut state: FunctionState) {
+        let mut undefined = Vec::new();
+        for &(value, span) in &state.value_uses {
+            if !state.values.contains_key(&value) && !undefined.contains(&value) {
+                undefined.push(value);
+                self.diagnostics
+                    .push(error(IrParseError::UndefinedValue(value), span));
+            }
+        }
+        let mut undefined = Vec::new();
+        for &(block, span) in &state.block_uses {
+            if !state.blocks.contains_key(&block) && !undefined.contains(&block) {
+                undefined.push(block);
+                self.diagnostics
+                    .push(error(IrParseError::UndefinedBlock(block), span));
+            }
+        }
+
+        // Numbers nothing defines, as after a pass removed their
+        // instructions, are given a placeholder type.
+        let count = state.values.keys().map(|v| v.0 + 1).max().unwrap_or(0);
+        state.function.value_types = (0..count)
+            .map(|number| match state.values.remove(&Value(number)) {
+                Some((ty, _)) => ty,
+                None => IrType::Value(Type::Void),
+            })
+            .collect();
+        state.function.next_block = state.blocks.keys().map(|b| b.0 + 1).max().unwrap_or(0);
+        self.module.functions.push(state.function);
+    }
+
+    fn define_value(&mut self, state: &mut FunctionState, value: Value, ty: IrType, span: Span) {
+        if let Some((_, first)) = state.values.get(&value) {
+            let diagnostic = error(IrParseError::DuplicateValue(value), span)
+                .with_note("first defined here", Some(*first));
+            self.diagnostics.push(diagnostic);
+        } else {
+            state.values.insert(value, (ty, span));
+        }
+    }
+
+    // bb0: instructions, an optional merge and a terminator.
+    fn parse_block(&mut self, state: &mut FunctionState) -> ParseResult<()> {
+        let (id, span) = self.expect_block("a block label")?;
+        self.expect_punct(":")?;
+        if let Some(first) = state.blocks.get(&id) {
+            let diagnostic = error(IrParseError::DuplicateBlock(id), span)
+                .with_note("first defined here", Some(*first));
+            self.diagnostics.push(diagnostic);
+        } else {
+            state.blocks.insert(id, span);
+        }
+
+        let mut instructions = Vec::new();
+        loop {
+            match self.peek().clone() {
+                Token::Value(_) => {
+                    let (result, span) = self.expect_value()?;
+                    self.expect_punct(":")?;
+                    let ty = self.parse_type()?;
+                    self.expect_punct("=")?;
+                    let kind = self.parse_instruction(state, Some(&ty))?;
+                    self.define_value(state, result, ty, span);
+                    instructions.push(Instruction {
+                        result: Some(result),
+                        kind,
+                    });
+                }
+                Token::Ident(name) if is_block_end(&name) => break,
+                Token::Ident(_) => {
+                    let kind = self.parse_instruction(state, None)?;
+                    instructions.push(Instruction { result: None, kind });
+                }
+                _ => return Err(self.expected("an instruction or a terminator")),
+            }
+        }
+        let merge = self.parse_merge(state)?;
+        let terminator = self.parse_terminator(state)?;
+        state.function.blocks.push(Block {
+            id,
+            instructions,
+            merge,
+            terminator,
+        });
+        Ok(())
+    }
+
+    // Parses what follows the `=` of an instruction with a result of type
+    // `result`, or a whole instruction without one.
+    fn parse_instruction(
+        &mut self,
+        state: &mut FunctionState,
+        result: Option<&IrType>,
+    ) -> ParseResult<InstKind> {
+        let (opcode, span) = self.expect_ident("an instruction")?;
+        // Calls and intrinsics have a result unless they return void.
+        if result.is_none() && produces_value(&opcode) {
+            return Err(error(IrParseError::MissingResult(opcode), span));
+        }
+        if result.is_some() && matches!(opcode.as_str(), "store" | "demote") {
+            return Err(error(IrParseError::UnexpectedResult(opcode), span));
+        }
+
+        let kind = match opcode.as_str() {
+            "const" => {
+                let ty = result.expect("checked above");
+                InstKind::Constant(self.parse_constant(ty)?)
+            }
+            "spec_constant" => {
+                let (name, span) = self.expect_ident("a specialization constant")?;
+                match self
+                    .module
+                    .spec_constants
+                    .iter()
+                    .position(|c| c.name == name)
+                {
+                    Some(index) => InstKind::SpecConstant(index),
+                    None => return Err(error(IrParseError::UnknownSpecConstant(name), span)),
+                }
+            }
+            "builtin" => {
+                let (name, span) = self.expect_ident("a built-in")?;
+                match BuiltIn::from_name(&name) {
+                    Some(built_in) => InstKind::BuiltIn(built_in),
+                    None => return Err(error(IrParseError::UnknownBuiltIn(name), span)),
+                }
+            }
+            "global" => {
+                let (name, span) = self.expect_ident("a global")?;
+                match self.module.globals.iter().position(|g| g.name == name) {
+                    Some(index) => InstKind::Global(index),
+                    None => return Err(error(IrParseError::UnknownGlobal(name), span)),
+                }
+            }
+            "variable" => InstKind::Variable(self.expect_ident("a variable name")?.0),
+            "load" => InstKind::Load(self.parse_operand(state)?),
+            "store" => {
+                let pointer = self.parse_operand(state)?;
+                self.expect_punct(",")?;
+                InstKind::Store(pointer, self.parse_operand(state)?)
+            }
+            "convert" => InstKind::Convert(self.parse_operand(state)?),
+            "splat" => InstKind::Splat(self.parse_operand(state)?),
+            "construct" => {
+                let mut args = vec![self.parse_operand(state)?];
+                while self.eat_punct(",") {
+                    args.push(self.parse_operand(state)?);
+                }
+                InstKind::Construct(args)
+            }
+            "call" => {
+                let name = match self.peek().clone() {
+                    Token::Function(name) => {
+                        self.advance();
+                        name
+                    }
+                    _ => return Err(self.expected("a function name")),
+                };
+                InstKind::Call(name, self.parse_arguments(state)?)
+            }
+            "intrinsic" => {
+                let (name, span) = self.expect_ident("an intrinsic")?;
+                let Some(intrinsic) = Intrinsic::from_name(&name) else {
+                    return Err(error(IrParseError::UnknownIntrinsic(name), span));
+                };
+                InstKind::Intrinsic(intrinsic, self.parse_arguments(state)?)
+            }
+            "demote" => InstKind::Demote,
+            "phi" => {
+                let mut incoming = Vec::new();
+                loop {
+                    self.expect_punct("[")?;
+                    let value = self.parse_operand(state)?;
+                    self.expect_punct(",")?;
+                    let block = self.parse_block_operand(state)?;
+                    self.expect_punct("]")?;
+                    incoming.push((block, value));
+                    if !self.eat_punct(",") {
+                        break;
+                    }
+                }
+                InstKind::Phi(incoming)
+            }
+            _ => match BinaryOp::from_name(&opcode) {
+                Some(op) => {
+                    let left = self.parse_operand(state)?;
+                    self.expect_punct(",")?;
+                    InstKind::Binary(op, left, self.parse_operand(state)?)
+                }
+                None => return Err(error(IrParseError::UnknownOpcode(opcode), span)),
+            },
+        };
+        Ok(kind)
+    }
+
+    fn parse_merge(&mut self, state: &mut FunctionState) -> ParseResult<Option<Merge>> {
+        if self.eat_keyword("selection_merge") {
+            let merge = self.parse_block_operand(state)?;
+            return Ok(Some(Merge::Selection(merge)));
+        }
+        if self.eat_keyword("loop_merge") {
+            let merge = self.parse_block_operand(state)?;
+            self.expect_punct(",")?;
+            self.expect_keyword("continue")?;
+            let continue_target = self.parse_block_operand(state)?;
+            let mut control = LoopControl::None;
+            if self.eat_punct(",") {
+                control = match self.peek() {
+                    Token::Ident(name) => LoopControl::from_name(name),
+                    _ => None,
+                }
+                .ok_or_else(|| self.expected("`unroll` or `dont_unroll`"))?;
+                self.advance();
+            }
+            return Ok(Some(Merge::Loop {
+                merge,
+                continue_target,
+                control,
+            }));
+        }
+        Ok(None)
+    }
+
+    fn parse_terminator(&mut self, state: &mut FunctionState) -> ParseResult<Terminator> {
+        let (name, span) = self.expect_ident("a terminator")?;
+        let terminator = match name.as_str() {
+            "br" => Terminator::Branch(self.parse_block_operand(state)?),
+            "br_if" => {
+                let condition = self.parse_operand(state)?;
+                self.expect_punct(",")?;
+                let if_true = self.parse_block_operand(state)?;
+                self.expect_punct(",")?;
+                let if_false = self.parse_block_operand(state)?;
+                Terminator::CondBranch {
+                    condition,
+                    if_true,
+                    if_false,
+                }
+            }
+            "switch" => {
+                let selector = self.parse_operand(state)?;
+                self.expect_punct("[")?;
+                let mut cases = Vec::new();
+                if !self.eat_punct("]") {
+                    loop {
+                        let label = self.expect_number("a case label")?;
+                        self.expect_punct(":")?;
+                        cases.push((label, self.parse_block_operand(state)?));
+                        if self.eat_punct("]") {
+                            break;
+                        }
+                        self.expect_punct(",")?;
+                    }
+                }
+                self.expect_keyword("default")?;
+                let default = self.parse_block_operand(state)?;
+                Terminator::Switch {
+                    selector,
+                    cases,
+                    default,
+                }
+            }
+            "return" => match self.peek() {
+                Token::Value(_) => Terminator::Return(Some(self.parse_operand(state)?)),
+                _ => Terminator::Return(None),
+            },
+            "discard" => Terminator::Discard,
+            "unreachable" => Terminator::Unreachable,
+            _ => {
+                return Err(error(
+                    IrParseError::Expected {
+                        expected: "a terminator".to_string(),
+                        found: format!("`{}`", name),
+                    },
+                    span,
+                ));
+            }
+        };
+        Ok(terminator)
+    }
+
+    // `(%1, %2)`
+    fn parse_arguments(&mut self, state: &mut FunctionState) -> ParseResult<Vec<Value>> {
+        self.expect_punct("(")?;
+        let mut args = Vec::new();
+        if !self.eat_punct(")") {
+            loop {
+                args.push(self.parse_operand(state)?);
+                if self.eat_punct(")") {
+                    break;
+                }
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(args)
+    }
+
+    fn expect_value(&mut self) -> ParseResult<(Value, Span)> {
+        match *self.peek() {
+            Token::Value(number) => Ok((Value(number), self.advance().1)),
+            _ => Err(self.expected("a value")),
+        }
+    }
+
+    fn parse_operand(&mut self, state: &mut FunctionState) -> ParseResult<Value> {
+        let (value, span) = self.expect_value()?;
+        Ok(state.use_value(value, span))
+    }
+
+    fn expect_block(&mut self, expected: &str) -> ParseResult<(BlockId, Span)> {
+        if let Token::Ident(name) = self.peek()
+            && let Some(number) = name.strip_prefix("bb").and_then(|n| n.parse::<u32>().ok())
+        {
+            if number >= MAX_NUMBER {
+                let found = name.clone();
+                return Err(error(IrParseError::NumberTooLarge(found), self.advance().1));
+            }
+            return Ok((BlockId(number), self.advance().1));
+        }
+        Err(self.expected(expected))
+    }
+
+    fn parse_block_operand(&mut self, state: &mut FunctionState) -> ParseResult<BlockId> {
+        let (block, span) = self.expect_block("a block")?;
+        Ok(state.use_block(block, span))
+    }
+
+    fn parse_storage_class(&mut self) -> ParseResult<StorageClass> {
+        let (name, span) = self.expect_ident("a storage class")?;
+        StorageClass::from_name(&name)
+            .ok_or_else(|| error(IrParseError::UnknownStorageClass(name), span))
+    }
+
+    // A value type, or `ptr<storage, type>`.
+    fn parse_type(&mut self) -> ParseResult<IrType> {
+        if self.eat_keyword("ptr") {
+            self.expect_punct("<")?;
+            let storage = self.parse_storage_class()?;
+            self.expect_punct(",")?;
+            let ty = self.parse_value_type()?;
+            self.expect_punct(">")?;
+            return Ok(IrType::Pointer(ty, storage));
+        }
+        Ok(IrType::Value(self.parse_value_type()?))
+    }
+
+    fn parse_value_type(&mut self) -> ParseResult<Type> {
+        let (name, span) = self.expect_ident("a type")?;
+        if name == "atomic" {
+            self.expect_punct("<")?;
+            let element = self.parse_value_type()?;
+            self.expect_punct(">")?;
+            return Ok(Type::Atomic(Box::new(element)));
+        }
+        let types = [
+            Type::Float,
+            Type::Int,
+            Type::Bool,
+            Type::Float2,
+            Type::Float3,
+            Type::Float4,
+            Type::Int2,
+            Type::Int3,
+            Type::Int4,
+            Type::Bool2,
+            Type::Bool3,
+            Type::Bool4,
+            Type::Void,
+        ];
+        types
+            .into_iter()
+            .find(|ty| ty.to_string() == name)
+            .ok_or_else(|| error(IrParseError::UnknownType(name), span))
+    }
+
+    // A scalar literal of type `ty`.
+    fn parse_constant(&mut self, ty: &IrType) -> ParseResult<ConstValue> {
+        let (literal, span) = match self.peek().clone() {
+            Token::Number(text) | Token::Ident(text) => (text, self.advance().1),
+            _ => return Err(self.expected("a constant")),
+        };
+        // Ints are 32-bit, as in the source language, though constants
+        // are held as `i64`.
+        let constant = match ty {
+            IrType::Value(Type::Int) => literal
+                .parse::<i32>()
+                .ok()
+                .map(|v| ConstValue::Int(v.into())),
+            IrType::Value(Type::Float) => literal.parse().ok().map(ConstValue::Float),
+            IrType::Value(Type::Bool) => literal.parse().ok().map(ConstValue::Bool),
+            _ => None,
+        };
+        constant.ok_or_else(|| {
+            let out_of_range = *ty == IrType::Value(Type::Int) && literal.parse::<i64>().is_ok();
+            let diagnostic = error(
+                IrParseError::InvalidConstant {
+                    literal,
+                    ty: ir_type(ty),
+                },
+                span,
+            );
+            if out_of_range {
+                diagnostic.with_help(format!("an `int` is between {} and {}", i32::MIN, i32::MAX))
+            } else {
+                diagnostic
+            }
+        })
+    }
+}
+
+// Whether `name` starts a block's merge or terminator.
+fn is_block_end(name: &str) -> bool {
+    matches!(
+        name,
+        "selection_merge"
+            | "loop_merge"
+            | "br"
+            | "br_if"
+            | "switch"
+            | "return"
+            | "discard"
+            | "unreachable"
+    )
+}
+
+// Whether the instruction `name` always produces a value.
+fn produces_value(name: &str) -> bool {
+    matches!(
+        name,
+        "const"
+            | "spec_constant"
+            | "builtin"
+            | "global"
+            | "variable"
+            | "load"
+            | "convert"
+            | "splat"
+            | "construct"
+            | "phi"
+    ) || BinaryOp::from_name(name).is_some()
+}
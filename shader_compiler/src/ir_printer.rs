@@ -0,0 +1,240 @@
+// src/ir_printer.rs
+// This file prints IR in its textual form (`.osir`), which `ir_parser`
+// reads back. Printing, parsing and printing again gives the same text, so
+// hand-written IR can stand in for lowered source when testing a pass.
+//
+//     spec_constant 1 COUNT: int = 4
+//     global workgroup partial: float
+//
+//     fn scale(%0 factor: float, %1 value: ptr<function, float>) -> void {
+//     bb0:
+//         %2: float = load %1
+//         %3: float = mul %2, %0
+//         store %1, %3
+//         return
+//     }
+
+use std::fmt::{self, Write};
+
+use crate::ir::{
+    Block, BlockId, Function, InstKind, Instruction, IrType, Merge, Module, Terminator, Value,
+};
+
+pub fn print(module: &Module) -> String {
+    let mut out = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = print_module(&mut out, module);
+    out
+}
+
+fn print_module(out: &mut String, module: &Module) -> fmt::Result {
+    for constant in &module.spec_constants {
+        writeln!(
+            out,
+            "spec_constant {} {}: {} = {}",
+            constant.id,
+            constant.name,
+            constant.default.ty(),
+            constant.default
+        )?;
+    }
+    for global in &module.globals {
+        writeln!(
+            out,
+            "global {} {}: {}",
+            global.storage.name(),
+            global.name,
+            global.ty
+        )?;
+    }
+    for function in &module.functions {
+        if !out.is_empty() {
+            writeln!(out)?;
+        }
+        print_function(out, module, function)?;
+    }
+    Ok(())
+}
+
+fn print_function(out: &mut String, module: &Module, function: &Function) -> fmt::Result {
+    if let Some(stage) = function.stage {
+        write!(out, "{} ", stage.name())?;
+    }
+    if let Some(hint) = function.inline {
+        write!(out, "inline({}) ", hint.name())?;
+    }
+    write!(out, "fn {}(", function_name(&function.name))?;
+    for (i, param) in function.params.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(
+            out,
+            "{} {}: {}",
+            value(param.value),
+            param.name,
+            ir_type(function.value_type(param.value))
+        )?;
+    }
+    writeln!(out, ") -> {} {{", function.return_type)?;
+    for block in &function.blocks {
+        print_block(out, module, function, block)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn print_block(
+    out: &mut String,
+    module: &Module,
+    function: &Function,
+    block: &Block,
+) -> fmt::Result {
+    writeln!(out, "{}:", block_name(block.id))?;
+    for instruction in &block.instructions {
+        write!(out, "    ")?;
+        print_instruction(out, module, function, instruction)?;
+        writeln!(out)?;
+    }
+    match block.merge {
+        Some(Merge::Selection(merge)) => {
+            writeln!(out, "    selection_merge {}", block_name(merge))?
+        }
+        Some(Merge::Loop {
+            merge,
+            continue_target,
+            control,
+        }) => {
+            write!(
+                out,
+                "    loop_merge {}, continue {}",
+                block_name(merge),
+                block_name(continue_target)
+            )?;
+            if let Some(name) = control.name() {
+                write!(out, ", {}", name)?;
+            }
+            writeln!(out)?
+        }
+        None => {}
+    }
+    write!(out, "    ")?;
+    print_terminator(out, &block.terminator)?;
+    writeln!(out)
+}
+
+fn print_instruction(
+    out: &mut String,
+    module: &Module,
+    function: &Function,
+    instruction: &Instruction,
+) -> fmt::Result {
+    if let Some(result) = instruction.result {
+        write!(
+            out,
+            "{}: {} = ",
+            value(result),
+            ir_type(function.value_type(result))
+        )?;
+    }
+    let kind = &instruction.kind;
+    write!(out, "{}", kind.opcode())?;
+    match kind {
+        InstKind::Constant(constant) => write!(out, " {}", constant),
+        InstKind::SpecConstant(index) => {
+            write!(out, " {}", module.spec_constants[*index].name)
+        }
+        InstKind::BuiltIn(built_in) => write!(out, " {}", built_in.name()),
+        InstKind::Global(index) => write!(out, " {}", module.globals[*index].name),
+        InstKind::Variable(name) => write!(out, " {}", name),
+        InstKind::Call(name, args) => {
+            write!(out, " {}({})", function_name(name), value_list(args))
+        }
+        InstKind::Intrinsic(intrinsic, args) => {
+            write!(out, " {}({})", intrinsic.name(), value_list(args))
+        }
+        InstKind::Demote => Ok(()),
+        InstKind::Phi(incoming) => {
+            let incoming: Vec<String> = incoming
+                .iter()
+                .map(|&(block, incoming)| format!("[{}, {}]", value(incoming), block_name(block)))
+                .collect();
+            write!(out, " {}", incoming.join(", "))
+        }
+        InstKind::Load(_)
+        | InstKind::Store(..)
+        | InstKind::Binary(..)
+        | InstKind::Convert(_)
+        | InstKind::Splat(_)
+        | InstKind::Construct(_) => write!(out, " {}", value_list(&kind.operands())),
+    }
+}
+
+fn print_terminator(out: &mut String, terminator: &Terminator) -> fmt::Result {
+    match terminator {
+        Terminator::Branch(target) => write!(out, "br {}", block_name(*target)),
+        Terminator::CondBranch {
+            condition,
+            if_true,
+            if_false,
+        } => write!(
+            out,
+            "br_if {}, {}, {}",
+            value(*condition),
+            block_name(*if_true),
+            block_name(*if_false)
+        ),
+        Terminator::Switch {
+            selector,
+            cases,
+            default,
+        } => {
+            let cases: Vec<String> = cases
+                .iter()
+                .map(|&(label, target)| format!("{}: {}", label, block_name(target)))
+                .collect();
+            write!(
+                out,
+                "switch {} [{}] default {}",
+                value(*selector),
+                cases.join(", "),
+                block_name(*default)
+            )
+        }
+        Terminator::Return(None) => write!(out, "return"),
+        Terminator::Return(Some(returned)) => write!(out, "return {}", value(*returned)),
+        Terminator::Discard => write!(out, "discard"),
+        Terminator::Unreachable => write!(out, "unreachable"),
+    }
+}
+
+pub fn value(value: Value) -> String {
+    format!("%{}", value.0)
+}
+
+pub fn block_name(block: BlockId) -> String {
+    format!("bb{}", block.0)
+}
+
+fn value_list(values: &[Value]) -> String {
+    let values: Vec<String> = values.iter().map(|&v| value(v)).collect();
+    values.join(", ")
+}
+
+pub fn ir_type(ty: &IrType) -> String {
+    match ty {
+        IrType::Value(ty) => ty.to_string(),
+        IrType::Pointer(ty, storage) => format!("ptr<{}, {}>", storage.name(), ty),
+    }
+}
+
+// Function names are written `@name`, quoted if they are not identifiers,
+// as monomorphized instances such as `@"scale<float3>"` are not.
+pub fn function_name(name: &str) -> String {
+    let identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if identifier {
+        format!("@{}", name)
+    } else {
+        format!("@\"{}\"", name)
+    }
+}
@@ -0,0 +1,150 @@
+// src/ir_tests.rs
+// This file runs the IR tests in `tests/ir`. Each test is a `.osir` file
+// whose first line names the passes to run over it, in order:
+//
+//     ; passes: mem2reg sccp
+//
+// and a `.expected` file next to it holding the IR they leave. The module
+// is verified before the first pass and after each one. A test whose first
+// line is `; verify` instead holds IR the verifier must reject, and its
+// `.expected` file the violations, one per line, and one whose first line
+// is `; parse` holds text the parser must reject, and its `.expected` file
+// the diagnostics.
+//
+// Running the tests with `BLESS=1` writes each `.expected` file from what
+// the test produced, for reviewing the difference before committing it.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::dce::Dce;
+use crate::gvn::Gvn;
+use crate::inline::Inliner;
+use crate::ir_parser;
+use crate::ir_printer;
+use crate::ir_verifier;
+use crate::licm::Licm;
+use crate::mem2reg::Mem2Reg;
+use crate::pass_manager::{self, Analyses, Pass};
+use crate::sccp::Sccp;
+use crate::source_map::SourceMap;
+use crate::strip::Strip;
+use crate::unroll::Unroll;
+
+// The pass with the given name, configured as at -O1.
+fn pass(name: &str) -> Box<dyn Pass> {
+    match name {
+        "inline" => Box::new(Inliner { threshold: 16 }),
+        "mem2reg" => Box::new(Mem2Reg),
+        "sccp" => Box::new(Sccp),
+        "unroll" => Box::new(Unroll { threshold: 64 }),
+        "licm" => Box::new(Licm),
+        "gvn" => Box::new(Gvn),
+        "dce" => Box::new(Dce),
+        "strip" => Box::new(Strip { entry_point: None }),
+        _ => panic!("unknown pass `{}`", name),
+    }
+}
+
+// What running the test at `path`, whose text is `text`, produces, to
+// compare with its `.expected` file.
+fn output(path: &str, text: &str) -> String {
+    let (header, _) = text.split_once('\n').unwrap_or((text, ""));
+    let parsed = ir_parser::parse(text, 0);
+    if header.trim() == "; parse" {
+        let Err(diagnostics) = parsed else {
+            panic!("the parser accepted the test");
+        };
+        let mut sources = SourceMap::default();
+        sources.add(path, text);
+        return diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(&sources))
+            .collect();
+    }
+    let mut module = match parsed {
+        Ok(module) => module,
+        Err(diagnostics) => panic!("the test does not parse: {:?}", diagnostics),
+    };
+    let violations = ir_verifier::verify(&module);
+    if header.trim() == "; verify" {
+        assert!(!violations.is_empty(), "the verifier accepted the test");
+        return violations
+            .iter()
+            .map(|violation| format!("{}\n", violation))
+            .collect();
+    }
+
+    let passes = header
+        .strip_prefix("; passes:")
+        .expect("the test starts with none of `; passes:`, `; verify` and `; parse`");
+    if let Err(error) = pass_manager::verify_after("the parser", &module) {
+        panic!("{}", error);
+    }
+    let mut analyses = Analyses::default();
+    for name in passes.split_whitespace() {
+        let mut pass = pass(name);
+        let preserved = pass.run(&mut module, &mut analyses);
+        analyses.invalidate(preserved);
+        if let Err(error) = pass_manager::verify_after(pass.name(), &module) {
+            panic!("{}", error);
+        }
+    }
+    ir_printer::print(&module)
+}
+
+fn run(name: &str) {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/ir");
+    let input = dir.join(format!("{}.osir", name));
+    let expected_path = dir.join(format!("{}.expected", name));
+    let text = fs::read_to_string(&input)
+        .unwrap_or_else(|error| panic!("cannot read `{}`: {}", input.display(), error));
+    let actual = output(&format!("tests/ir/{}.osir", name), &text);
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(&expected_path, &actual).expect("cannot write the expected output");
+        return;
+    }
+    let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+    assert!(
+        actual == expected,
+        "`{}` does not match `{}`:\n{}",
+        input.display(),
+        expected_path.display(),
+        actual
+    );
+}
+
+macro_rules! ir_tests {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                run(stringify!($name));
+            }
+        )*
+    };
+}
+
+ir_tests! {
+    dce_unreachable_blocks,
+    gvn_hoist_both_arms,
+    inline_early_return,
+    inline_early_return_folded,
+    inline_out_param,
+    licm_invariant,
+    mem2reg_dead_after_if,
+    mem2reg_loop,
+    mem2reg_selection,
+    parse_int_range,
+    parse_numbering_gaps,
+    sccp_constant_branch,
+    strip_unused,
+    unroll_exit_phi,
+    verify_back_edge,
+    verify_duplicate_case,
+    verify_missing_merge,
+    verify_not_dominated,
+    verify_phi_predecessors,
+    verify_selection_exit,
+    verify_type_mismatch,
+}
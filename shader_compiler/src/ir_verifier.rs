@@ -0,0 +1,897 @@
+// src/ir_verifier.rs
+// This file checks that a module is valid IR: the invariants lowering
+// establishes and every pass must keep. Breaking one is a bug in the
+// compiler rather than in the program, so the verifier's findings are not
+// diagnostics; they name the function and block instead of a source span.
+//
+// A valid function
+// - has an entry block no branch targets, and blocks with distinct ids;
+// - defines each value once, with a type, and defines every value it
+//   uses in a block that dominates the use, or earlier in the same block;
+// - has phis only at the start of a block, with one incoming value per
+//   predecessor;
+// - gives every instruction and terminator operands of the types it takes,
+//   and a result of the type it produces;
+// - does not reach `unreachable`, which is what a block left without a
+//   terminator ends in;
+// - structures its control flow as SPIR-V requires: each block that
+//   branches several ways names a merge block, which it dominates and
+//   which is no other block's merge, every back edge goes to a loop
+//   header from within its continue construct, and a construct is only
+//   left through its merge block, or by breaking out of or continuing the
+//   innermost loop or switch around it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::Type;
+use crate::dominators::Dominators;
+use crate::ir::{
+    BinaryOp, BlockId, Function, InstKind, IrType, Merge, Module, StorageClass, Terminator, Value,
+};
+use crate::ir_printer::{block_name, function_name, ir_type, value};
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyError {
+    NoBlocks,
+    DuplicateBlock(BlockId),
+    // A branch or merge names a block the function does not have.
+    MissingBlock(BlockId),
+    EntryHasPredecessors,
+    DuplicateValue(Value),
+    UntypedValue(Value),
+    UndefinedValue(Value),
+    NotDominated(Value),
+    MisplacedPhi(Value),
+    PhiPredecessors(Value),
+    MisplacedVariable(Value),
+    UnknownSpecConstant(usize),
+    UnknownGlobal(usize),
+    UnknownFunction(String),
+    // An instruction given operands it does not take, or declared to
+    // produce a type it does not.
+    InvalidInstruction {
+        opcode: String,
+        operands: Vec<String>,
+        result: Option<String>,
+    },
+    MissingResult(String),
+    UnexpectedResult(String),
+    InvalidTerminator {
+        terminator: &'static str,
+        operand: String,
+    },
+    // Two cases of a switch with the same value.
+    DuplicateCase(i64),
+    ReturnMismatch {
+        expected: Type,
+        found: Option<String>,
+    },
+    ReachableUnreachable,
+    MissingMerge,
+    // A merge on a block that does not end in the branch it goes with.
+    MisplacedMerge,
+    SharedMerge {
+        merge: BlockId,
+        other_header: BlockId,
+    },
+    MergeNotDominated(BlockId),
+    BackEdgeToNonHeader(BlockId),
+    BackEdgeOutsideContinue(BlockId),
+    MultipleBackEdges,
+    // A branch out of the construct `header` starts, to a block that is
+    // neither its merge nor a break or continue it may take.
+    InvalidExit {
+        header: BlockId,
+        target: BlockId,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::NoBlocks => write!(f, "the function has no blocks"),
+            VerifyError::DuplicateBlock(block) => {
+                write!(f, "{} is defined twice", block_name(*block))
+            }
+            VerifyError::MissingBlock(block) => {
+                write!(f, "{} does not exist", block_name(*block))
+            }
+            VerifyError::EntryHasPredecessors => write!(f, "the entry block is branched to"),
+            VerifyError::DuplicateValue(v) => write!(f, "{} is defined twice", value(*v)),
+            VerifyError::UntypedValue(v) => write!(f, "{} has no type", value(*v)),
+            VerifyError::UndefinedValue(v) => write!(f, "{} is used but never defined", value(*v)),
+            VerifyError::NotDominated(v) => {
+                write!(
+                    f,
+                    "{} is used where its definition does not dominate",
+                    value(*v)
+                )
+            }
+            VerifyError::MisplacedPhi(v) => {
+                write!(
+                    f,
+                    "phi {} follows an instruction that is not a phi",
+                    value(*v)
+                )
+            }
+            VerifyError::PhiPredecessors(v) => write!(
+                f,
+                "phi {} does not have one incoming value per predecessor",
+                value(*v)
+            ),
+            VerifyError::MisplacedVariable(v) => {
+                write!(f, "variable {} is not in the entry block", value(*v))
+            }
+            VerifyError::UnknownSpecConstant(index) => {
+                write!(f, "there is no specialization constant {}", index)
+            }
+            VerifyError::UnknownGlobal(index) => write!(f, "there is no global {}", index),
+            VerifyError::UnknownFunction(name) => {
+                write!(f, "there is no function {}", function_name(name))
+            }
+            VerifyError::InvalidInstruction {
+                opcode,
+                operands,
+                result,
+            } => {
+                let operands: Vec<String> = operands.iter().map(|ty| format!("`{}`", ty)).collect();
+                write!(f, "`{}` cannot take ", opcode)?;
+                if operands.is_empty() {
+                    write!(f, "no operands")?;
+                } else {
+                    write!(f, "{}", operands.join(", "))?;
+                }
+                match result {
+                    Some(result) => write!(f, " and produce `{}`", result),
+                    None => Ok(()),
+                }
+            }
+            VerifyError::MissingResult(opcode) => {
+                write!(f, "`{}` produces a value, but has no result", opcode)
+            }
+            VerifyError::UnexpectedResult(opcode) => {
+                write!(f, "`{}` produces no value, but has a result", opcode)
+            }
+            VerifyError::InvalidTerminator {
+                terminator,
+                operand,
+            } => write!(f, "`{}` cannot take `{}`", terminator, operand),
+            VerifyError::DuplicateCase(label) => {
+                write!(f, "the switch has more than one case {}", label)
+            }
+            VerifyError::ReturnMismatch { expected, found } => match found {
+                Some(found) => write!(
+                    f,
+                    "returns `{}` from a function returning `{}`",
+                    found, expected
+                ),
+                None => write!(
+                    f,
+                    "returns nothing from a function returning `{}`",
+                    expected
+                ),
+            },
+            VerifyError::ReachableUnreachable => {
+                write!(f, "the block is reachable, but ends in `unreachable`")
+            }
+            VerifyError::MissingMerge => {
+                write!(
+                    f,
+                    "the block branches several ways, but names no merge block"
+                )
+            }
+            VerifyError::MisplacedMerge => {
+                write!(f, "the block's merge does not go with its terminator")
+            }
+            VerifyError::SharedMerge {
+                merge,
+                other_header,
+            } => write!(
+                f,
+                "{} is also the merge block of {}",
+                block_name(*merge),
+                block_name(*other_header)
+            ),
+            VerifyError::MergeNotDominated(block) => {
+                write!(f, "the block does not dominate {}", block_name(*block))
+            }
+            VerifyError::BackEdgeToNonHeader(block) => write!(
+                f,
+                "the block branches back to {}, which is not a loop header",
+                block_name(*block)
+            ),
+            VerifyError::BackEdgeOutsideContinue(header) => write!(
+                f,
+                "the block branches back to {} from outside its continue construct",
+                block_name(*header)
+            ),
+            VerifyError::MultipleBackEdges => write!(f, "the loop has more than one back edge"),
+            VerifyError::InvalidExit { header, target } => write!(
+                f,
+                "the block leaves the construct of {} for {}, which is not its merge block",
+                block_name(*header),
+                block_name(*target)
+            ),
+        }
+    }
+}
+
+// A broken invariant, and where it is broken.
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub function: String,
+    pub block: Option<BlockId>,
+    pub error: VerifyError,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in {}", function_name(&self.function))?;
+        if let Some(block) = self.block {
+            write!(f, ", {}", block_name(block))?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+// Returns every broken invariant, or nothing if the module is valid.
+pub fn verify(module: &Module) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for function in &module.functions {
+        let mut verifier = FunctionVerifier {
+            module,
+            function,
+            block: None,
+            definitions: HashMap::new(),
+            violations: Vec::new(),
+        };
+        verifier.verify();
+        violations.append(&mut verifier.violations);
+    }
+    violations
+}
+
+// A structured construct: the blocks its header dominates, up to its
+// merge block.
+struct Construct {
+    header: BlockId,
+    merge: BlockId,
+    kind: ConstructKind,
+}
+
+#[derive(Clone, Copy)]
+enum ConstructKind {
+    Selection,
+    Switch,
+    Loop { continue_target: BlockId },
+}
+
+// Where a value is defined: in a block at an instruction index, or, for
+// a parameter, before the entry block.
+#[derive(Clone, Copy)]
+enum Definition {
+    Param,
+    Instruction(BlockId, usize),
+}
+
+struct FunctionVerifier<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    // The block being checked, to cite in violations.
+    block: Option<BlockId>,
+    definitions: HashMap<Value, Definition>,
+    violations: Vec<Violation>,
+}
+
+impl<'a> FunctionVerifier<'a> {
+    fn report(&mut self, error: VerifyError) {
+        self.violations.push(Violation {
+            function: self.function.name.clone(),
+            block: self.block,
+            error,
+        });
+    }
+
+    fn verify(&mut self) {
+        let function = self.function;
+        let Some(entry) = function.blocks.first() else {
+            self.report(VerifyError::NoBlocks);
+            return;
+        };
+        self.check_blocks();
+        let predecessors = function.predecessors();
+        if !predecessors[&entry.id].is_empty() {
+            self.block = Some(entry.id);
+            self.report(VerifyError::EntryHasPredecessors);
+        }
+        self.collect_definitions();
+
+        let dominators = Dominators::compute(function);
+        for block in &function.blocks {
+            self.block = Some(block.id);
+            let reachable = dominators.is_reachable(block.id);
+            let mut phis_done = false;
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                let result = instruction.result;
+                match (&instruction.kind, result) {
+                    (InstKind::Phi(incoming), Some(result)) => {
+                        if phis_done {
+                            self.report(VerifyError::MisplacedPhi(result));
+                        }
+                        let mut blocks: Vec<BlockId> = incoming.iter().map(|&(b, _)| b).collect();
+                        let mut expected = predecessors[&block.id].clone();
+                        blocks.sort();
+                        expected.sort();
+                        if blocks != expected {
+                            self.report(VerifyError::PhiPredecessors(result));
+                        }
+                        // An incoming value is used at the end of the
+                        // block it comes from.
+                        for &(from, incoming) in incoming {
+                            let end = function.block(from).map_or(0, |b| b.instructions.len());
+                            let at = dominators.is_reachable(from).then_some((from, end));
+                            self.check_use(&dominators, incoming, at);
+                        }
+                    }
+                    (kind, _) => {
+                        phis_done = true;
+                        if let (InstKind::Variable(_), Some(result)) = (kind, result)
+                            && block.id != entry.id
+                        {
+                            self.report(VerifyError::MisplacedVariable(result));
+                        }
+                        for operand in kind.operands() {
+                            let at = reachable.then_some((block.id, index));
+                            self.check_use(&dominators, operand, at);
+                        }
+                    }
+                }
+                self.check_instruction(&instruction.kind, result);
+            }
+            for operand in block.terminator.operands() {
+                let at = reachable.then_some((block.id, block.instructions.len()));
+                self.check_use(&dominators, operand, at);
+            }
+            self.check_terminator(&block.terminator);
+            if reachable && block.terminator == Terminator::Unreachable {
+                self.report(VerifyError::ReachableUnreachable);
+            }
+        }
+        self.check_structure(&dominators);
+    }
+
+    // Checks that block ids are distinct and that every block named
+    // exists.
+    fn check_blocks(&mut self) {
+        let mut seen = HashMap::new();
+        for block in &self.function.blocks {
+            if seen.insert(block.id, ()).is_some() {
+                self.block = Some(block.id);
+                self.report(VerifyError::DuplicateBlock(block.id));
+            }
+        }
+        for block in &self.function.blocks {
+            self.block = Some(block.id);
+            let mut named = block.terminator.successors();
+            match block.merge {
+                Some(Merge::Selection(merge)) => named.push(merge),
+                Some(Merge::Loop {
+                    merge,
+                    continue_target,
+                    ..
+                }) => named.extend([merge, continue_target]),
+                None => {}
+            }
+            for instruction in &block.instructions {
+                if let InstKind::Phi(incoming) = &instruction.kind {
+                    named.extend(incoming.iter().map(|&(b, _)| b));
+                }
+            }
+            for target in named {
+                if !seen.contains_key(&target) {
+                    self.report(VerifyError::MissingBlock(target));
+                }
+            }
+        }
+    }
+
+    fn collect_definitions(&mut self) {
+        let function = self.function;
+        self.block = None;
+        for param in &function.params {
+            self.define(param.value, Definition::Param);
+        }
+        for block in &function.blocks {
+            self.block = Some(block.id);
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some(result) = instruction.result {
+                    self.define(result, Definition::Instruction(block.id, index));
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, value: Value, definition: Definition) {
+        if self.definitions.insert(value, definition).is_some() {
+            self.report(VerifyError::DuplicateValue(value));
+        }
+        if value.0 as usize >= self.function.value_types.len() {
+            self.report(VerifyError::UntypedValue(value));
+        }
+    }
+
+    // Checks that `value` is defined, and if the use is `at` an
+    // instruction index of a reachable block, that the definition
+    // dominates it.
+    fn check_use(&mut self, dominators: &Dominators, value: Value, at: Option<(BlockId, usize)>) {
+        let Some(&definition) = self.definitions.get(&value) else {
+            self.report(VerifyError::UndefinedValue(value));
+            return;
+        };
+        let Some((block, index)) = at else {
+            return;
+        };
+        let dominated = match definition {
+            Definition::Param => true,
+            Definition::Instruction(defined_in, defined_at) if defined_in == block => {
+                defined_at < index
+            }
+            Definition::Instruction(defined_in, _) => dominators.dominates(defined_in, block),
+        };
+        if !dominated {
+            self.report(VerifyError::NotDominated(value));
+        }
+    }
+
+    // The type of a value, or `None` if it was never given one; that is
+    // reported where it is defined.
+    fn type_of(&self, value: Value) -> Option<&'a IrType> {
+        self.function.value_types.get(value.0 as usize)
+    }
+
+    fn check_instruction(&mut self, kind: &InstKind, result: Option<Value>) {
+        if let Some(error) = self.unknown_reference(kind) {
+            self.report(error);
+            return;
+        }
+        let Some(operands) = kind
+            .operands()
+            .into_iter()
+            .map(|operand| self.type_of(operand))
+            .collect::<Option<Vec<&IrType>>>()
+        else {
+            return;
+        };
+        let declared = match result {
+            Some(result) => match self.type_of(result) {
+                Some(ty) => Some(ty),
+                None => return,
+            },
+            None => None,
+        };
+        let opcode = match kind {
+            InstKind::Call(name, _) => format!("call {}", function_name(name)),
+            InstKind::Intrinsic(intrinsic, _) => intrinsic.name().to_string(),
+            kind => kind.opcode().to_string(),
+        };
+        let error = match (self.result_type(kind, &operands, declared), declared) {
+            (Some(IrType::Value(Type::Void)), None) => return,
+            (Some(IrType::Value(Type::Void)), Some(_)) => VerifyError::UnexpectedResult(opcode),
+            (Some(_), None) => VerifyError::MissingResult(opcode),
+            (Some(expected), Some(declared)) if expected == *declared => return,
+            (_, declared) => VerifyError::InvalidInstruction {
+                opcode,
+                operands: operands.iter().map(|ty| ir_type(ty)).collect(),
+                result: declared.map(ir_type),
+            },
+        };
+        self.report(error);
+    }
+
+    // A spec constant, global or function the instruction names that the
+    // module does not have.
+    fn unknown_reference(&self, kind: &InstKind) -> Option<VerifyError> {
+        match kind {
+            InstKind::SpecConstant(index) if *index >= self.module.spec_constants.len() => {
+                Some(VerifyError::UnknownSpecConstant(*index))
+            }
+            InstKind::Global(index) if *index >= self.module.globals.len() => {
+                Some(VerifyError::UnknownGlobal(*index))
+            }
+            InstKind::Call(name, _) if !self.module.functions.iter().any(|f| &f.name == name) => {
+                Some(VerifyError::UnknownFunction(name.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // The type the instruction produces given its operand types, `void`
+    // if it produces nothing, or `None` if it does not take them.
+    // Conversions, splats, constructs, variables and phis can produce
+    // several types, so they produce the `declared` one if they can.
+    fn result_type(
+        &self,
+        kind: &InstKind,
+        operands: &[&IrType],
+        declared: Option<&IrType>,
+    ) -> Option<IrType> {
+        let declared_value = match declared {
+            Some(IrType::Value(ty)) => Some(ty),
+            _ => None,
+        };
+        let ty = match (kind, operands) {
+            (InstKind::Constant(constant), _) => IrType::Value(constant.ty()),
+            (InstKind::SpecConstant(index), _) => {
+                IrType::Value(self.module.spec_constants[*index].default.ty())
+            }
+            (InstKind::BuiltIn(built_in), _) => IrType::Value(built_in.ty()),
+            (InstKind::Global(index), _) => {
+                let global = &self.module.globals[*index];
+                IrType::Pointer(global.ty.clone(), global.storage)
+            }
+            (InstKind::Variable(_), _) => match declared {
+                Some(IrType::Pointer(ty, StorageClass::Function)) if ty.element().is_some() => {
+                    IrType::Pointer(ty.clone(), StorageClass::Function)
+                }
+                _ => return None,
+            },
+            // Atomics are only accessed through atomic intrinsics.
+            (InstKind::Load(_), [IrType::Pointer(ty, _)]) if ty.element().is_some() => {
+                IrType::Value(ty.clone())
+            }
+            (InstKind::Store(..), [IrType::Pointer(ty, _), IrType::Value(stored)])
+                if ty.element().is_some() && ty == stored =>
+            {
+                IrType::Value(Type::Void)
+            }
+            (InstKind::Binary(op, ..), [IrType::Value(left), IrType::Value(right)])
+                if left == right =>
+            {
+                let element = left.element();
+                let numeric = matches!(element, Some(Type::Float | Type::Int));
+                match op {
+                    BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide
+                        if numeric =>
+                    {
+                        IrType::Value(left.clone())
+                    }
+                    BinaryOp::Equal | BinaryOp::NotEqual if element.is_some() => {
+                        IrType::Value(Type::Bool)
+                    }
+                    BinaryOp::LessThan
+                    | BinaryOp::GreaterThan
+                    | BinaryOp::LessThanOrEqual
+                    | BinaryOp::GreaterThanOrEqual
+                        if numeric =>
+                    {
+                        IrType::Value(Type::Bool)
+                    }
+                    BinaryOp::And | BinaryOp::Or if element == Some(Type::Bool) => {
+                        IrType::Value(left.clone())
+                    }
+                    _ => return None,
+                }
+            }
+            (InstKind::Convert(_), [IrType::Value(from)]) => match declared_value {
+                Some(to) if to.element().is_some() && to.width() == from.width() => {
+                    IrType::Value(to.clone())
+                }
+                _ => return None,
+            },
+            (InstKind::Splat(_), [IrType::Value(scalar)]) => match declared_value {
+                Some(to)
+                    if scalar.width() == Some(1)
+                        && to.element().as_ref() == Some(scalar)
+                        && to.width() > Some(1) =>
+                {
+                    IrType::Value(to.clone())
+                }
+                _ => return None,
+            },
+            (InstKind::Construct(_), _) => {
+                let to = declared_value?;
+                let mut width = 0;
+                for operand in operands {
+                    let IrType::Value(ty) = operand else {
+                        return None;
+                    };
+                    if ty.element().is_none() || ty.element() != to.element() {
+                        return None;
+                    }
+                    width += ty.width()?;
+                }
+                if Some(width) != to.width() {
+                    return None;
+                }
+                IrType::Value(to.clone())
+            }
+            (InstKind::Call(name, _), _) => {
+                let callee = self.module.functions.iter().find(|f| &f.name == name)?;
+                let params = callee
+                    .params
+                    .iter()
+                    .map(|param| callee.value_types.get(param.value.0 as usize));
+                if callee.params.len() != operands.len()
+                    || !params.zip(operands).all(|(param, &arg)| param == Some(arg))
+                {
+                    return None;
+                }
+                IrType::Value(callee.return_type.clone())
+            }
+            (InstKind::Intrinsic(intrinsic, _), _) => {
+                // An atomic is passed as a pointer to it.
+                let args: Option<Vec<Type>> = operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ty)| match ty {
+                        IrType::Value(ty) => Some(ty.clone()),
+                        IrType::Pointer(ty @ Type::Atomic(_), _) if i == 0 => Some(ty.clone()),
+                        IrType::Pointer(..) => None,
+                    })
+                    .collect();
+                IrType::Value(intrinsic.result_type(&args?)?)
+            }
+            (InstKind::Demote, _) => IrType::Value(Type::Void),
+            (InstKind::Phi(_), _) => match declared {
+                Some(declared @ IrType::Value(_)) if operands.iter().all(|&ty| ty == declared) => {
+                    declared.clone()
+                }
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(ty)
+    }
+
+    fn check_terminator(&mut self, terminator: &Terminator) {
+        if let Terminator::Switch { cases, .. } = terminator {
+            let mut seen = HashSet::new();
+            let mut duplicates = HashSet::new();
+            for &(label, _) in cases {
+                if !seen.insert(label) && duplicates.insert(label) {
+                    self.report(VerifyError::DuplicateCase(label));
+                }
+            }
+        }
+        let operand = terminator
+            .operands()
+            .first()
+            .map(|&operand| self.type_of(operand));
+        let (name, expected) = match terminator {
+            Terminator::CondBranch { .. } => ("br_if", Type::Bool),
+            Terminator::Switch { .. } => ("switch", Type::Int),
+            Terminator::Return(_) => {
+                let expected = &self.function.return_type;
+                let found = match operand {
+                    // Untyped; reported where it is defined.
+                    Some(None) => return,
+                    Some(Some(ty)) => Some(ty),
+                    None => None,
+                };
+                let matches = match found {
+                    Some(IrType::Value(ty)) => ty == expected,
+                    Some(IrType::Pointer(..)) => false,
+                    None => *expected == Type::Void,
+                };
+                if !matches {
+                    self.report(VerifyError::ReturnMismatch {
+                        expected: expected.clone(),
+                        found: found.map(ir_type),
+                    });
+                }
+                return;
+            }
+            _ => return,
+        };
+        if let Some(Some(ty)) = operand
+            && *ty != IrType::Value(expected)
+        {
+            self.report(VerifyError::InvalidTerminator {
+                terminator: name,
+                operand: ir_type(ty),
+            });
+        }
+    }
+
+    // Checks the rules SPIR-V sets for structured control flow.
+    fn check_structure(&mut self, dominators: &Dominators) {
+        let function = self.function;
+        let mut merges: HashMap<BlockId, BlockId> = HashMap::new();
+        let mut loops: HashMap<BlockId, (BlockId, BlockId)> = HashMap::new();
+        let mut constructs = Vec::new();
+        for block in &function.blocks {
+            self.block = Some(block.id);
+            let (merge, fits) = match block.merge {
+                None => continue,
+                Some(Merge::Selection(merge)) => {
+                    let kind = match block.terminator {
+                        Terminator::Switch { .. } => ConstructKind::Switch,
+                        _ => ConstructKind::Selection,
+                    };
+                    constructs.push(Construct {
+                        header: block.id,
+                        merge,
+                        kind,
+                    });
+                    (
+                        merge,
+                        matches!(
+                            block.terminator,
+                            Terminator::CondBranch { .. } | Terminator::Switch { .. }
+                        ),
+                    )
+                }
+                Some(Merge::Loop {
+                    merge,
+                    continue_target,
+                    ..
+                }) => {
+                    loops.insert(block.id, (merge, continue_target));
+                    constructs.push(Construct {
+                        header: block.id,
+                        merge,
+                        kind: ConstructKind::Loop { continue_target },
+                    });
+                    if dominators.is_reachable(continue_target)
+                        && !dominators.dominates(block.id, continue_target)
+                    {
+                        self.report(VerifyError::MergeNotDominated(continue_target));
+                    }
+                    (
+                        merge,
+                        matches!(
+                            block.terminator,
+                            Terminator::Branch(_) | Terminator::CondBranch { .. }
+                        ),
+                    )
+                }
+            };
+            if !fits {
+                self.report(VerifyError::MisplacedMerge);
+            }
+            if let Some(&other_header) = merges.get(&merge) {
+                self.report(VerifyError::SharedMerge {
+                    merge,
+                    other_header,
+                });
+            } else {
+                merges.insert(merge, block.id);
+            }
+            if dominators.is_reachable(merge) && !dominators.dominates(block.id, merge) {
+                self.report(VerifyError::MergeNotDominated(merge));
+            }
+        }
+
+        // A block branching several ways without a merge must be leaving
+        // a loop: breaking out of it, continuing it, or ending its
+        // iteration.
+        for block in &function.blocks {
+            self.block = Some(block.id);
+            let leaves_loop = |targets: &[BlockId]| {
+                loops.iter().any(|(&header, &(merge, continue_target))| {
+                    dominators.dominates(header, block.id)
+                        && targets.iter().any(|&target| {
+                            target == merge || target == continue_target || target == header
+                        })
+                })
+            };
+            match (&block.terminator, block.merge) {
+                (Terminator::Switch { .. }, None) => self.report(VerifyError::MissingMerge),
+                (
+                    Terminator::CondBranch {
+                        if_true, if_false, ..
+                    },
+                    None,
+                ) if !leaves_loop(&[*if_true, *if_false]) => self.report(VerifyError::MissingMerge),
+                _ => {}
+            }
+        }
+
+        // An edge to a block that dominates its source is a back edge.
+        let mut back_edges: HashMap<BlockId, usize> = HashMap::new();
+        for block in &function.blocks {
+            self.block = Some(block.id);
+            for target in block.terminator.successors() {
+                if !dominators.dominates(target, block.id) {
+                    continue;
+                }
+                match loops.get(&target) {
+                    None => self.report(VerifyError::BackEdgeToNonHeader(target)),
+                    Some(&(_, continue_target)) => {
+                        if !dominators.dominates(continue_target, block.id) {
+                            self.report(VerifyError::BackEdgeOutsideContinue(target));
+                        }
+                        *back_edges.entry(target).or_default() += 1;
+                    }
+                }
+            }
+        }
+        let mut headers: Vec<BlockId> = back_edges
+            .into_iter()
+            .filter(|&(_, count)| count > 1)
+            .map(|(header, _)| header)
+            .collect();
+        headers.sort();
+        for header in headers {
+            self.block = Some(header);
+            self.report(VerifyError::MultipleBackEdges);
+        }
+        self.check_exits(dominators, &constructs);
+    }
+
+    // Checks that every branch out of a construct goes to its merge block.
+    // A selection or switch may also be left by breaking out of the
+    // innermost loop or switch around it, or by continuing the innermost
+    // loop, as an early return does from inlined code.
+    fn check_exits(&mut self, dominators: &Dominators, constructs: &[Construct]) {
+        let contains = |construct: &Construct, block: BlockId| {
+            dominators.dominates(construct.header, block)
+                && !dominators.dominates(construct.merge, block)
+        };
+        // The innermost construct around `inner`, other than the one it
+        // starts, that `wanted` accepts. The constructs around a block
+        // are nested, so the innermost one's header is dominated by all
+        // the others'.
+        let innermost = |inner: &Construct, wanted: fn(ConstructKind) -> bool| {
+            constructs
+                .iter()
+                .filter(|c| c.header != inner.header && wanted(c.kind))
+                .filter(|c| contains(c, inner.header))
+                .reduce(|a, b| {
+                    if dominators.dominates(a.header, b.header) {
+                        b
+                    } else {
+                        a
+                    }
+                })
+        };
+        for block in &self.function.blocks {
+            if !dominators.is_reachable(block.id) {
+                continue;
+            }
+            self.block = Some(block.id);
+            let targets = block.terminator.successors();
+            for (index, &target) in targets.iter().enumerate() {
+                if targets[..index].contains(&target) {
+                    continue;
+                }
+                for construct in constructs {
+                    if !contains(construct, block.id)
+                        || contains(construct, target)
+                        || target == construct.merge
+                    {
+                        continue;
+                    }
+                    let allowed = match construct.kind {
+                        ConstructKind::Loop { .. } => false,
+                        ConstructKind::Selection | ConstructKind::Switch => {
+                            let breaks = innermost(construct, |kind| {
+                                !matches!(kind, ConstructKind::Selection)
+                            })
+                            .is_some_and(|c| target == c.merge);
+                            let continues = innermost(construct, |kind| {
+                                matches!(kind, ConstructKind::Loop { .. })
+                            })
+                            .is_some_and(|c| match c.kind {
+                                ConstructKind::Loop { continue_target } => {
+                                    target == c.merge
+                                        || target == continue_target
+                                        || target == c.header
+                                }
+                                _ => false,
+                            });
+                            breaks || continues
+                        }
+                    };
+                    if !allowed {
+                        self.report(VerifyError::InvalidExit {
+                            header: construct.header,
+                            target,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+// src/licm.rs
+// This file implements loop-invariant code motion (LICM), an optimization
+// pass that moves instructions computing the same value in every
+// iteration of a loop out of it, into its preheader, so they run once per
+// entry to the loop instead of once per iteration. In
+//
+//     for (...) { sum = sum + sample(uv + offset * scale); }
+//
+// with `scale` defined before the loop, `offset * scale` is not hoisted
+// unless `offset` is, but a `normalize(light)` or a constant is.
+//
+// An instruction is invariant if it is pure and each of its operands is
+// defined outside the loop or by an instruction already hoisted. Pure
+// instructions have no effects, so one in a branch the loop might not take
+// can still be run ahead of the loop. Loads are not hoisted, as a store in
+// the loop could change what they read. Inner loops are visited first, so
+// an instruction can be hoisted out of several loops at once.
+//
+// A loop is only visited if its header has a single predecessor outside
+// it, which structured control flow always gives it.
+
+use std::collections::HashSet;
+
+use crate::ir::{Function, Module, Value};
+use crate::loops::LoopInfo;
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Licm;
+
+impl Pass for Licm {
+    fn name(&self) -> &'static str {
+        "licm"
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            let loops = analyses.loops(function);
+            if hoist_invariants(function, &loops) {
+                analyses.invalidate_function(&function.name, Preserved::Cfg);
+                preserved = Preserved::Cfg;
+            }
+        }
+        preserved
+    }
+}
+
+// Moves the invariant instructions of each loop into its preheader.
+// Returns whether it moved any.
+fn hoist_invariants(function: &mut Function, loops: &LoopInfo) -> bool {
+    // Visiting blocks in this order sees each definition before its uses,
+    // other than in phis.
+    let order = function.reverse_postorder();
+    let mut changed = false;
+    for l in loops.loops.iter().rev() {
+        let Some(preheader) = l.preheader else {
+            continue;
+        };
+        // The values defined in the loop, less those hoisted.
+        let mut defined: HashSet<Value> = function
+            .blocks
+            .iter()
+            .filter(|block| l.blocks.contains(&block.id))
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| instruction.result)
+            .collect();
+        let mut hoisted = Vec::new();
+        for &block in order.iter().filter(|block| l.blocks.contains(block)) {
+            let block = function.block_mut(block).expect("the block exists");
+            block.instructions.retain(|instruction| {
+                let invariant = instruction.kind.is_pure()
+                    && instruction
+                        .kind
+                        .operands()
+                        .iter()
+                        .all(|operand| !defined.contains(operand));
+                if !invariant {
+                    return true;
+                }
+                if let Some(result) = instruction.result {
+                    defined.remove(&result);
+                }
+                hoisted.push(instruction.clone());
+                false
+            });
+        }
+        if !hoisted.is_empty() {
+            let preheader = function.block_mut(preheader).expect("the preheader exists");
+            preheader.instructions.extend(hoisted);
+            changed = true;
+        }
+    }
+    changed
+}
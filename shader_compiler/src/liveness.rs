@@ -0,0 +1,75 @@
+// src/liveness.rs
+// This file computes where the contents of each function-local variable
+// are live: where some path goes on to load the variable before storing to
+// it again. Only loads and stores count, so this only describes variables
+// nothing else, such as a call, reads or writes through. mem2reg uses it
+// to place phis only where the variable is still read.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::ir::{BlockId, Function, InstKind, Value};
+
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    variables_in: HashMap<BlockId, BTreeSet<Value>>,
+}
+
+impl Liveness {
+    pub fn compute(function: &Function) -> Self {
+        // For each block, the variables it loads before storing to, and
+        // those it stores to.
+        let variables: BTreeSet<Value> = function.blocks[0]
+            .instructions
+            .iter()
+            .filter(|i| matches!(i.kind, InstKind::Variable(_)))
+            .filter_map(|i| i.result)
+            .collect();
+        let mut loaded: HashMap<BlockId, BTreeSet<Value>> = HashMap::new();
+        let mut stored: HashMap<BlockId, BTreeSet<Value>> = HashMap::new();
+        for block in &function.blocks {
+            let block_loaded = loaded.entry(block.id).or_default();
+            let block_stored = stored.entry(block.id).or_default();
+            for instruction in &block.instructions {
+                match instruction.kind {
+                    InstKind::Load(pointer)
+                        if variables.contains(&pointer) && !block_stored.contains(&pointer) =>
+                    {
+                        block_loaded.insert(pointer);
+                    }
+                    InstKind::Store(pointer, _) if variables.contains(&pointer) => {
+                        block_stored.insert(pointer);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut liveness = Liveness {
+            variables_in: loaded.clone(),
+        };
+        // Blocks are visited in reverse layout order, which is close to
+        // postorder, so most loads reach back to their stores in one sweep.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in function.blocks.iter().rev() {
+                let mut variables_in = loaded[&block.id].clone();
+                for successor in block.terminator.successors() {
+                    if let Some(successor_in) = liveness.variables_in.get(&successor) {
+                        variables_in.extend(successor_in.difference(&stored[&block.id]).copied());
+                    }
+                }
+                if variables_in != liveness.variables_in[&block.id] {
+                    liveness.variables_in.insert(block.id, variables_in);
+                    changed = true;
+                }
+            }
+        }
+        liveness
+    }
+
+    // The variables whose contents are live when control enters the block.
+    pub fn variables_live_in(&self, block: BlockId) -> &BTreeSet<Value> {
+        &self.variables_in[&block]
+    }
+}
@@ -0,0 +1,247 @@
+// src/loops.rs
+// This file finds the natural loops of a function's control flow graph.
+// An edge whose target dominates its source is a back edge, and its target
+// is a loop header. The loop is the header and every block that reaches
+// one of the header's back edges without passing through the header.
+// Loops sharing a header are one loop; loops inside another are nested in
+// it.
+//
+// A loop whose header counts an induction variable from a constant by a
+// constant step, and leaves once comparing it with a constant fails, runs
+// a number of times known at compile time, its trip count.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::const_eval::ConstValue;
+use crate::dominators::Dominators;
+use crate::ir::{BinaryOp, BlockId, Function, InstKind, Terminator, Value};
+
+#[derive(Debug, Clone)]
+pub struct Loop {
+    pub header: BlockId,
+    // Every block in the loop, the header and nested loops included.
+    pub blocks: BTreeSet<BlockId>,
+    // The blocks whose back edges lead to the header.
+    pub latches: Vec<BlockId>,
+    // The blocks outside the loop that blocks in it branch to.
+    pub exits: Vec<BlockId>,
+    // The header's only predecessor outside the loop, if it has one. It
+    // runs once each time the loop is entered.
+    pub preheader: Option<BlockId>,
+    // The index of the innermost loop containing this one.
+    pub parent: Option<usize>,
+    // 1 for an outermost loop.
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LoopInfo {
+    // Each loop comes before the loops nested in it.
+    pub loops: Vec<Loop>,
+}
+
+impl LoopInfo {
+    pub fn compute(function: &Function, dominators: &Dominators) -> Self {
+        let predecessors = function.predecessors();
+        let mut latches: HashMap<BlockId, Vec<BlockId>> = HashMap::new();
+        let mut headers = Vec::new();
+        for block in function.reverse_postorder() {
+            let successors = function
+                .block(block)
+                .map_or(Vec::new(), |b| b.terminator.successors());
+            for successor in successors {
+                if dominators.dominates(successor, block) {
+                    if !latches.contains_key(&successor) {
+                        headers.push(successor);
+                    }
+                    latches.entry(successor).or_default().push(block);
+                }
+            }
+        }
+
+        let mut loops: Vec<Loop> = headers
+            .into_iter()
+            .map(|header| {
+                let latches = latches.remove(&header).unwrap_or_default();
+                // Walk backwards from the latches; the header stops the
+                // walk, as every block in the loop is dominated by it.
+                let mut blocks = BTreeSet::from([header]);
+                let mut worklist = latches.clone();
+                while let Some(block) = worklist.pop() {
+                    if dominators.is_reachable(block) && blocks.insert(block) {
+                        worklist.extend(predecessors[&block].iter().copied());
+                    }
+                }
+                let mut exits = Vec::new();
+                for &block in &blocks {
+                    let successors = function
+                        .block(block)
+                        .map_or(Vec::new(), |b| b.terminator.successors());
+                    for successor in successors {
+                        if !blocks.contains(&successor) && !exits.contains(&successor) {
+                            exits.push(successor);
+                        }
+                    }
+                }
+                let mut outside = predecessors[&header]
+                    .iter()
+                    .filter(|&block| !blocks.contains(block));
+                let preheader = match (outside.next(), outside.next()) {
+                    (Some(&preheader), None) => Some(preheader),
+                    _ => None,
+                };
+                Loop {
+                    header,
+                    blocks,
+                    latches,
+                    exits,
+                    preheader,
+                    parent: None,
+                    depth: 1,
+                }
+            })
+            .collect();
+
+        // A loop containing another has more blocks, so sorting by size
+        // puts every loop after the loops containing it.
+        loops.sort_by_key(|l| std::cmp::Reverse(l.blocks.len()));
+        for inner in 0..loops.len() {
+            let header = loops[inner].header;
+            let parent = (0..inner)
+                .rev()
+                .find(|&outer| loops[outer].blocks.contains(&header));
+            loops[inner].parent = parent;
+            loops[inner].depth = parent.map_or(1, |parent| loops[parent].depth + 1);
+        }
+        LoopInfo { loops }
+    }
+
+    // The index of the innermost loop containing the block.
+    pub fn innermost(&self, block: BlockId) -> Option<usize> {
+        // Inner loops come later.
+        self.loops.iter().rposition(|l| l.blocks.contains(&block))
+    }
+
+    // How many loops contain the block.
+    pub fn depth(&self, block: BlockId) -> usize {
+        self.innermost(block)
+            .map_or(0, |index| self.loops[index].depth)
+    }
+}
+
+impl Loop {
+    // How many times the loop body runs, if that is known and at most
+    // `limit`. Only a loop left from its header is counted, where the
+    // header's branch compares a phi with a constant, and the phi starts at
+    // a constant and is stepped by a constant on the back edge:
+    //
+    //     bb1:
+    //         %2: int = phi [%1, bb0], [%5, bb2]
+    //         %3: bool = lt %2, %0
+    //         loop_merge bb3, continue bb2
+    //         br_if %3, bb2, bb3
+    //
+    // Ints wrap as they do at run time.
+    pub fn trip_count(&self, function: &Function, limit: u32) -> Option<u32> {
+        let (Some(preheader), [latch]) = (self.preheader, self.latches.as_slice()) else {
+            return None;
+        };
+        let header = function.block(self.header)?;
+        let Terminator::CondBranch {
+            condition,
+            if_true,
+            if_false,
+        } = header.terminator
+        else {
+            return None;
+        };
+        // Whether the loop goes on while the condition holds.
+        let continues_on = match (
+            self.blocks.contains(&if_true),
+            self.blocks.contains(&if_false),
+        ) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => return None,
+        };
+
+        let definitions: HashMap<Value, &InstKind> = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter_map(|instruction| Some((instruction.result?, &instruction.kind)))
+            .collect();
+        let constant = |value: Value| match definitions.get(&value) {
+            Some(InstKind::Constant(ConstValue::Int(value))) => Some(*value as i32),
+            _ => None,
+        };
+
+        // The comparison, with the induction variable on the left.
+        let InstKind::Binary(op, left, right) = **definitions.get(&condition)? else {
+            return None;
+        };
+        let (op, phi, bound) = match (constant(left), constant(right)) {
+            (None, Some(bound)) => (op, left, bound),
+            (Some(bound), None) => (swapped(op)?, right, bound),
+            _ => return None,
+        };
+        let in_header = header
+            .instructions
+            .iter()
+            .any(|instruction| instruction.result == Some(phi));
+        let Some(InstKind::Phi(incoming)) = definitions.get(&phi).filter(|_| in_header) else {
+            return None;
+        };
+        let incoming_from = |block: BlockId| {
+            incoming
+                .iter()
+                .find(|&&(from, _)| from == block)
+                .map(|&(_, value)| value)
+        };
+        if incoming.len() != 2 {
+            return None;
+        }
+        let start = constant(incoming_from(preheader)?)?;
+        let step = match **definitions.get(&incoming_from(*latch)?)? {
+            InstKind::Binary(BinaryOp::Add, l, r) if l == phi => constant(r)?,
+            InstKind::Binary(BinaryOp::Add, l, r) if r == phi => constant(l)?,
+            InstKind::Binary(BinaryOp::Subtract, l, r) if l == phi => constant(r)?.wrapping_neg(),
+            _ => return None,
+        };
+
+        let mut count = 0;
+        let mut value = start;
+        loop {
+            let holds = match op {
+                BinaryOp::LessThan => value < bound,
+                BinaryOp::LessThanOrEqual => value <= bound,
+                BinaryOp::GreaterThan => value > bound,
+                BinaryOp::GreaterThanOrEqual => value >= bound,
+                BinaryOp::Equal => value == bound,
+                BinaryOp::NotEqual => value != bound,
+                _ => return None,
+            };
+            if holds != continues_on {
+                return Some(count);
+            }
+            if count == limit {
+                return None;
+            }
+            count += 1;
+            value = value.wrapping_add(step);
+        }
+    }
+}
+
+// The comparison that holds with its operands swapped.
+fn swapped(op: BinaryOp) -> Option<BinaryOp> {
+    let op = match op {
+        BinaryOp::LessThan => BinaryOp::GreaterThan,
+        BinaryOp::LessThanOrEqual => BinaryOp::GreaterThanOrEqual,
+        BinaryOp::GreaterThan => BinaryOp::LessThan,
+        BinaryOp::GreaterThanOrEqual => BinaryOp::LessThanOrEqual,
+        BinaryOp::Equal | BinaryOp::NotEqual => op,
+        _ => return None,
+    };
+    Some(op)
+}
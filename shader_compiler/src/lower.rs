@@ -0,0 +1,732 @@
+// src/lower.rs
+// This file lowers a checked, monomorphized translation unit to IR. Every
+// type is concrete by then, so the type of each value is computed bottom-up
+// from its operands. Mutable locals and `out`/`inout` parameters become
+// memory that is loaded and stored; immutable bindings are SSA values
+// directly. Each `if`, `switch` and `match` becomes a selection construct
+// with its own merge block.
+
+use std::collections::HashMap;
+
+use crate::ast::{
+    self, CaseLabel, EnumDefinition, Expression, FunctionDefinition, MatchArm, ParameterQualifier,
+    Pattern, PatternKind, Statement, TranslationUnit, Type,
+};
+use crate::const_eval::{self, ConstValue};
+use crate::intrinsics::{BuiltIn, Intrinsic};
+use crate::ir::{
+    BinaryOp, BlockId, Function, Global, InstKind, Instruction, IrType, Merge, Module, Param,
+    SpecConstant, StorageClass, Terminator, Value,
+};
+
+// Lowers a unit that has passed semantic analysis and been monomorphized.
+pub fn lower(unit: &TranslationUnit) -> Module {
+    let context = Context {
+        functions: unit
+            .functions
+            .iter()
+            .map(|function| (function.name.as_str(), function))
+            .collect(),
+        enums: unit
+            .enums
+            .iter()
+            .map(|definition| (definition.name.as_str(), definition))
+            .collect(),
+        spec_constants: unit
+            .spec_constants
+            .iter()
+            .enumerate()
+            .map(|(index, constant)| (constant.name.as_str(), (index, constant.type_name.clone())))
+            .collect(),
+        globals: unit
+            .workgroup_variables
+            .iter()
+            .enumerate()
+            .map(|(index, variable)| {
+                let ty = lower_type(&variable.type_name);
+                (variable.name.as_str(), (index, ty))
+            })
+            .collect(),
+    };
+
+    Module {
+        spec_constants: unit
+            .spec_constants
+            .iter()
+            .map(|constant| SpecConstant {
+                id: constant.id,
+                name: constant.name.clone(),
+                default: const_eval::evaluate(&constant.default)
+                    .expect("specialization constant defaults are checked to be constant"),
+            })
+            .collect(),
+        globals: unit
+            .workgroup_variables
+            .iter()
+            .map(|variable| Global {
+                name: variable.name.clone(),
+                ty: lower_type(&variable.type_name),
+                storage: StorageClass::Workgroup,
+            })
+            .collect(),
+        functions: unit
+            .functions
+            .iter()
+            .map(|function| FunctionLowerer::lower(&context, function))
+            .collect(),
+    }
+}
+
+// Enums are represented by their discriminants.
+fn lower_type(ty: &Type) -> Type {
+    match ty {
+        Type::Named(_) => Type::Int,
+        _ => ty.clone(),
+    }
+}
+
+struct Context<'a> {
+    functions: HashMap<&'a str, &'a FunctionDefinition>,
+    enums: HashMap<&'a str, &'a EnumDefinition>,
+    // The index and type of each specialization constant and global.
+    spec_constants: HashMap<&'a str, (usize, Type)>,
+    globals: HashMap<&'a str, (usize, Type)>,
+}
+
+// What a name in scope stands for.
+#[derive(Clone, Copy)]
+enum Binding {
+    // An immutable binding or `in` parameter.
+    Value(Value),
+    // A mutable local or `out`/`inout` parameter, held in memory.
+    Pointer(Value),
+}
+
+struct FunctionLowerer<'a> {
+    context: &'a Context<'a>,
+    function: Function,
+    // The block instructions are appended to, or `None` after a terminator,
+    // when the statements that follow can never run.
+    current: Option<BlockId>,
+    scopes: Vec<HashMap<String, Binding>>,
+    // The merge block of each enclosing switch, innermost last.
+    break_targets: Vec<BlockId>,
+    // The block `fallthrough` continues into, for each enclosing switch
+    // case.
+    fallthrough_targets: Vec<Option<BlockId>>,
+}
+
+impl<'a> FunctionLowerer<'a> {
+    fn lower(context: &'a Context<'a>, definition: &FunctionDefinition) -> Function {
+        let mut function = Function::new(
+            definition.name.clone(),
+            definition.stage,
+            lower_type(&definition.return_type),
+        );
+        function.inline = definition.inline;
+        let entry = function.new_block();
+
+        let mut scope = HashMap::new();
+        for param in &definition.params {
+            let ty = lower_type(&param.type_name);
+            let (ty, binding): (IrType, fn(Value) -> Binding) = match param.qualifier {
+                ParameterQualifier::In => (IrType::Value(ty), Binding::Value),
+                ParameterQualifier::Out | ParameterQualifier::InOut => (
+                    IrType::Pointer(ty, StorageClass::Function),
+                    Binding::Pointer,
+                ),
+            };
+            let value = function.new_value(ty);
+            function.params.push(Param {
+                name: param.name.clone(),
+                value,
+            });
+            scope.insert(param.name.clone(), binding(value));
+        }
+
+        let mut lowerer = FunctionLowerer {
+            context,
+            function,
+            current: Some(entry),
+            scopes: vec![scope],
+            break_targets: Vec::new(),
+            fallthrough_targets: Vec::new(),
+        };
+        lowerer.lower_block(&definition.body);
+        // Falling off the end returns from a void function. Analysis
+        // rejects other functions whose end can be reached.
+        if lowerer.current.is_some() {
+            let terminator = if definition.return_type == Type::Void {
+                Terminator::Return(None)
+            } else {
+                Terminator::Unreachable
+            };
+            lowerer.terminate(terminator);
+        }
+
+        let mut function = lowerer.function;
+        sort_blocks(&mut function);
+        function
+    }
+
+    // Appends an instruction to the current block, returning its result.
+    fn emit(&mut self, kind: InstKind, ty: Option<Type>) -> Option<Value> {
+        self.emit_typed(kind, ty.map(IrType::Value))
+    }
+
+    fn emit_typed(&mut self, kind: InstKind, ty: Option<IrType>) -> Option<Value> {
+        let result = ty.map(|ty| self.function.new_value(ty));
+        let current = self
+            .current
+            .expect("statements after a terminator are not lowered");
+        self.function
+            .block_mut(current)
+            .expect("the current block exists")
+            .instructions
+            .push(Instruction { result, kind });
+        result
+    }
+
+    // Emits an instruction that always produces a value.
+    fn value(&mut self, kind: InstKind, ty: Type) -> Value {
+        self.emit(kind, Some(ty)).expect("a typed result was given")
+    }
+
+    fn constant(&mut self, value: ConstValue) -> Value {
+        self.value(InstKind::Constant(value), value.ty())
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let current = self
+            .current
+            .take()
+            .expect("the block is not terminated yet");
+        self.function
+            .block_mut(current)
+            .expect("the current block exists")
+            .terminator = terminator;
+    }
+
+    fn set_merge(&mut self, merge: Merge) {
+        let current = self.current.expect("a header block is open");
+        self.function
+            .block_mut(current)
+            .expect("the current block exists")
+            .merge = Some(merge);
+    }
+
+    // Whether any block branches to `block`.
+    fn is_reached(&self, block: BlockId) -> bool {
+        self.function
+            .blocks
+            .iter()
+            .any(|other| other.terminator.successors().contains(&block))
+    }
+
+    fn value_type(&self, value: Value) -> Type {
+        match self.function.value_type(value) {
+            IrType::Value(ty) | IrType::Pointer(ty, _) => ty.clone(),
+        }
+    }
+
+    fn lower_block(&mut self, stmts: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            if self.current.is_none() {
+                break;
+            }
+            self.lower_statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        self.scopes
+            .last_mut()
+            .expect("bindings are made inside a function")
+            .insert(name.to_string(), binding);
+    }
+
+    fn lower_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Declaration {
+                mutable,
+                type_name,
+                name,
+                initializer,
+                ..
+            } => {
+                let ty = lower_type(
+                    type_name
+                        .as_ref()
+                        .expect("analysis fills in every declaration's type"),
+                );
+                let initial = initializer
+                    .as_ref()
+                    .and_then(|initializer| self.lower_expression(initializer));
+                // An immutable declaration without a value is assigned
+                // later, so it needs a variable like a mutable one.
+                if *mutable || initializer.is_none() {
+                    let pointer = self.variable(name, ty);
+                    if let Some(initial) = initial {
+                        self.emit(InstKind::Store(pointer, initial), None);
+                    }
+                    self.bind(name, Binding::Pointer(pointer));
+                } else {
+                    let value = initial.expect("an initializer is not void");
+                    self.bind(name, Binding::Value(value));
+                }
+            }
+            Statement::Block(stmts) => self.lower_block(stmts),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                let condition = self.lower_value(condition);
+                self.lower_if(condition, |lowerer| lowerer.lower_block(if_block), {
+                    else_block.as_ref().map(|else_block| {
+                        move |lowerer: &mut FunctionLowerer| lowerer.lower_block(else_block)
+                    })
+                });
+            }
+            Statement::StaticIf { .. } => {
+                unreachable!("`static if` is resolved before lowering")
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                let selector = self.lower_value(selector);
+                self.lower_switch(selector, cases);
+            }
+            Statement::Match { selector, arms, .. } => {
+                let selector = self.lower_value(selector);
+                self.lower_match(selector, arms);
+            }
+            Statement::Break { .. } => {
+                let target = *self
+                    .break_targets
+                    .last()
+                    .expect("`break` is checked to be inside a switch");
+                self.terminate(Terminator::Branch(target));
+            }
+            Statement::Fallthrough { .. } => {
+                let target = self
+                    .fallthrough_targets
+                    .last()
+                    .copied()
+                    .flatten()
+                    .expect("`fallthrough` is checked to have a following case");
+                self.terminate(Terminator::Branch(target));
+            }
+            Statement::Discard { .. } => self.terminate(Terminator::Discard),
+            Statement::Demote { .. } => {
+                self.emit(InstKind::Demote, None);
+            }
+            Statement::Return { value, .. } => {
+                let value = value
+                    .as_ref()
+                    .and_then(|value| self.lower_expression(value));
+                self.terminate(Terminator::Return(value));
+            }
+            Statement::Expression(expr) => {
+                self.lower_expression(expr);
+            }
+        }
+    }
+
+    // Lowers a selection on `condition` that runs `then` if it holds and
+    // `otherwise`, if given, if not.
+    fn lower_if(
+        &mut self,
+        condition: Value,
+        then: impl FnOnce(&mut Self),
+        otherwise: Option<impl FnOnce(&mut Self)>,
+    ) {
+        let then_block = self.function.new_block();
+        let else_block = otherwise.as_ref().map(|_| self.function.new_block());
+        let merge = self.function.new_block();
+        self.set_merge(Merge::Selection(merge));
+        self.terminate(Terminator::CondBranch {
+            condition,
+            if_true: then_block,
+            if_false: else_block.unwrap_or(merge),
+        });
+
+        self.current = Some(then_block);
+        then(self);
+        self.branch_to(merge);
+        if let (Some(else_block), Some(otherwise)) = (else_block, otherwise) {
+            self.current = Some(else_block);
+            otherwise(self);
+            self.branch_to(merge);
+        }
+        self.current = self.is_reached(merge).then_some(merge);
+    }
+
+    // Ends the current block, if it is still open, with a branch.
+    fn branch_to(&mut self, target: BlockId) {
+        if self.current.is_some() {
+            self.terminate(Terminator::Branch(target));
+        }
+    }
+
+    fn lower_switch(&mut self, selector: Value, cases: &[ast::SwitchCase]) {
+        let blocks: Vec<BlockId> = cases.iter().map(|_| self.function.new_block()).collect();
+        let merge = self.function.new_block();
+
+        let mut targets = Vec::new();
+        let mut default = merge;
+        for (case, &block) in cases.iter().zip(&blocks) {
+            for label in &case.labels {
+                match label {
+                    CaseLabel::Value(value) => {
+                        let value = const_eval::evaluate_int(value)
+                            .expect("case labels are checked to be constant");
+                        targets.push((value, block));
+                    }
+                    CaseLabel::Default { .. } => default = block,
+                }
+            }
+        }
+        self.set_merge(Merge::Selection(merge));
+        self.terminate(Terminator::Switch {
+            selector,
+            cases: targets,
+            default,
+        });
+
+        self.break_targets.push(merge);
+        for (i, case) in cases.iter().enumerate() {
+            self.current = Some(blocks[i]);
+            self.fallthrough_targets.push(blocks.get(i + 1).copied());
+            self.lower_block(&case.body);
+            self.fallthrough_targets.pop();
+            self.branch_to(merge);
+        }
+        self.break_targets.pop();
+        self.current = self.is_reached(merge).then_some(merge);
+    }
+
+    // A match becomes a chain of selections, one per arm, testing whether
+    // any of the arm's patterns matches. The arms are exhaustive, so the
+    // last arm, or the first with a `_` pattern, needs no test.
+    fn lower_match(&mut self, selector: Value, arms: &[MatchArm]) {
+        let Some((arm, rest)) = arms.split_first() else {
+            return;
+        };
+        let catch_all = arm
+            .patterns
+            .iter()
+            .any(|pattern| pattern.kind == PatternKind::Wildcard);
+        if catch_all || rest.is_empty() {
+            self.lower_block(&arm.body);
+            return;
+        }
+
+        let mut condition = None;
+        for pattern in &arm.patterns {
+            let test = self.lower_pattern_test(selector, pattern);
+            condition = Some(match condition {
+                Some(previous) => {
+                    self.value(InstKind::Binary(BinaryOp::Or, previous, test), Type::Bool)
+                }
+                None => test,
+            });
+        }
+        let condition = condition.expect("every arm has a pattern");
+        self.lower_if(
+            condition,
+            |lowerer| lowerer.lower_block(&arm.body),
+            Some(|lowerer: &mut FunctionLowerer| lowerer.lower_match(selector, rest)),
+        );
+    }
+
+    // Whether the selector matches a pattern other than `_`.
+    fn lower_pattern_test(&mut self, selector: Value, pattern: &Pattern) -> Value {
+        let compare = |lowerer: &mut Self, op, value| {
+            let constant = lowerer.constant(value);
+            lowerer.value(InstKind::Binary(op, selector, constant), Type::Bool)
+        };
+        match &pattern.kind {
+            PatternKind::Bool(value) => compare(self, BinaryOp::Equal, ConstValue::Bool(*value)),
+            PatternKind::Int(value) => {
+                compare(self, BinaryOp::Equal, ConstValue::Int(i64::from(*value)))
+            }
+            PatternKind::IntRange(start, end) => {
+                let above = compare(
+                    self,
+                    BinaryOp::GreaterThanOrEqual,
+                    ConstValue::Int(i64::from(*start)),
+                );
+                let below = compare(
+                    self,
+                    BinaryOp::LessThanOrEqual,
+                    ConstValue::Int(i64::from(*end)),
+                );
+                self.value(InstKind::Binary(BinaryOp::And, above, below), Type::Bool)
+            }
+            PatternKind::EnumVariant { enum_name, variant } => {
+                let discriminant = self.context.enums[enum_name.as_str()]
+                    .discriminant(variant)
+                    .expect("variants are checked to exist");
+                compare(self, BinaryOp::Equal, ConstValue::Int(discriminant))
+            }
+            PatternKind::Wildcard => unreachable!("`_` arms are lowered without a test"),
+        }
+    }
+
+    // Declares a function-local variable. Variables are kept at the start
+    // of the entry block.
+    fn variable(&mut self, name: &str, ty: Type) -> Value {
+        let pointer = self
+            .function
+            .new_value(IrType::Pointer(ty, StorageClass::Function));
+        let entry = &mut self.function.blocks[0].instructions;
+        let position = entry
+            .iter()
+            .take_while(|instruction| matches!(instruction.kind, InstKind::Variable(_)))
+            .count();
+        entry.insert(
+            position,
+            Instruction {
+                result: Some(pointer),
+                kind: InstKind::Variable(name.to_string()),
+            },
+        );
+        pointer
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+    }
+
+    // The pointer to the variable `name` refers to, for writing it.
+    fn pointer(&mut self, name: &str) -> Value {
+        match self.lookup(name) {
+            Some(Binding::Pointer(pointer)) => pointer,
+            Some(Binding::Value(_)) => unreachable!("immutable bindings are never written"),
+            None => {
+                let (index, ty) = self.context.globals[name].clone();
+                self.emit_typed(
+                    InstKind::Global(index),
+                    Some(IrType::Pointer(ty, StorageClass::Workgroup)),
+                )
+                .expect("a typed result was given")
+            }
+        }
+    }
+
+    // Lowers an expression that produces a value.
+    fn lower_value(&mut self, expr: &Expression) -> Value {
+        self.lower_expression(expr)
+            .expect("analysis checks that the expression has a value")
+    }
+
+    // Returns the value of the expression, or `None` for a call to a
+    // function that returns nothing.
+    fn lower_expression(&mut self, expr: &Expression) -> Option<Value> {
+        let value = match expr {
+            Expression::Identifier { name, .. } => self.lower_identifier(name),
+            Expression::IntLiteral { value, .. } => self.constant(ConstValue::Int(*value)),
+            Expression::FloatLiteral { value, .. } => self.constant(ConstValue::Float(*value)),
+            Expression::BoolLiteral { value, .. } => self.constant(ConstValue::Bool(*value)),
+            Expression::EnumVariant {
+                enum_name, variant, ..
+            } => {
+                let discriminant = self.context.enums[enum_name.as_str()]
+                    .discriminant(variant)
+                    .expect("variants are checked to exist");
+                self.constant(ConstValue::Int(discriminant))
+            }
+            Expression::BinaryOp {
+                op, left, right, ..
+            } => {
+                let left = self.lower_value(left);
+                let right = self.lower_value(right);
+                self.lower_binary(*op, left, right)
+            }
+            Expression::Assignment { name, value, .. } => {
+                let value = self.lower_value(value);
+                let pointer = self.pointer(name);
+                self.emit(InstKind::Store(pointer, value), None);
+                value
+            }
+            Expression::Call { name, args, .. } => return self.lower_call(name, args),
+            Expression::Constructor {
+                type_name, args, ..
+            } => {
+                let args: Vec<Value> = args.iter().map(|arg| self.lower_value(arg)).collect();
+                self.lower_constructor(&lower_type(type_name), &args)
+            }
+        };
+        Some(value)
+    }
+
+    fn lower_identifier(&mut self, name: &str) -> Value {
+        match self.lookup(name) {
+            Some(Binding::Value(value)) => return value,
+            Some(Binding::Pointer(pointer)) => {
+                let ty = self.value_type(pointer);
+                return self.value(InstKind::Load(pointer), ty);
+            }
+            None => {}
+        }
+        if self.context.globals.contains_key(name) {
+            let pointer = self.pointer(name);
+            let ty = self.value_type(pointer);
+            return self.value(InstKind::Load(pointer), ty);
+        }
+        if let Some((index, ty)) = self.context.spec_constants.get(name).cloned() {
+            return self.value(InstKind::SpecConstant(index), ty);
+        }
+        let built_in = BuiltIn::from_name(name).expect("names are checked to be declared");
+        self.value(InstKind::BuiltIn(built_in), built_in.ty())
+    }
+
+    fn lower_binary(&mut self, op: ast::BinaryOp, left: Value, right: Value) -> Value {
+        let (mut left, mut right) = (left, right);
+        let left_type = self.value_type(left);
+        let right_type = self.value_type(right);
+        // A scalar combined with a vector applies to every component.
+        let ty = if left_type.width() == Some(1) && right_type.width() != Some(1) {
+            left = self.value(InstKind::Splat(left), right_type.clone());
+            right_type
+        } else {
+            if right_type.width() == Some(1) && left_type.width() != Some(1) {
+                right = self.value(InstKind::Splat(right), left_type.clone());
+            }
+            left_type
+        };
+        let (op, result) = match op {
+            ast::BinaryOp::Add => (BinaryOp::Add, ty),
+            ast::BinaryOp::Subtract => (BinaryOp::Subtract, ty),
+            ast::BinaryOp::Multiply => (BinaryOp::Multiply, ty),
+            ast::BinaryOp::Divide => (BinaryOp::Divide, ty),
+            ast::BinaryOp::Equal => (BinaryOp::Equal, Type::Bool),
+            ast::BinaryOp::NotEqual => (BinaryOp::NotEqual, Type::Bool),
+            ast::BinaryOp::LessThan => (BinaryOp::LessThan, Type::Bool),
+            ast::BinaryOp::GreaterThan => (BinaryOp::GreaterThan, Type::Bool),
+            ast::BinaryOp::LessThanOrEqual => (BinaryOp::LessThanOrEqual, Type::Bool),
+            ast::BinaryOp::GreaterThanOrEqual => (BinaryOp::GreaterThanOrEqual, Type::Bool),
+        };
+        self.value(InstKind::Binary(op, left, right), result)
+    }
+
+    // Converts `value` to `ty`'s element type, if it is not already of it.
+    fn convert(&mut self, value: Value, element: &Type) -> Value {
+        let ty = self.value_type(value);
+        if ty.element().as_ref() == Some(element) {
+            return value;
+        }
+        let width = ty
+            .width()
+            .expect("constructor arguments are scalars or vectors");
+        let converted = Type::vector(element, width).expect("the width is 1 to 4");
+        self.value(InstKind::Convert(value), converted)
+    }
+
+    fn lower_constructor(&mut self, ty: &Type, args: &[Value]) -> Value {
+        let element = ty
+            .element()
+            .expect("constructors build scalars and vectors");
+        let args: Vec<Value> = args
+            .iter()
+            .map(|&arg| self.convert(arg, &element))
+            .collect();
+        match args.as_slice() {
+            [arg] if self.value_type(*arg) == *ty => *arg,
+            [arg] => self.value(InstKind::Splat(*arg), ty.clone()),
+            _ => self.value(InstKind::Construct(args), ty.clone()),
+        }
+    }
+
+    fn lower_call(&mut self, name: &str, args: &[Expression]) -> Option<Value> {
+        if let Some(function) = self.context.functions.get(name).copied() {
+            let mut values = Vec::new();
+            // The variables passed through a temporary, each with the
+            // temporary, to copy back once the call returns.
+            let mut copies = Vec::new();
+            for (param, arg) in function.params.iter().zip(args) {
+                let (qualifier, Expression::Identifier { name, .. }) = (param.qualifier, arg)
+                else {
+                    values.push(self.lower_value(arg));
+                    continue;
+                };
+                if qualifier == ParameterQualifier::In {
+                    values.push(self.lower_value(arg));
+                    continue;
+                }
+                // A parameter points to function memory, and no two point
+                // to the same variable, so workgroup variables and
+                // variables passed twice go through a local copy.
+                let pointer = self.pointer(name);
+                let IrType::Pointer(ty, storage) = self.function.value_type(pointer).clone() else {
+                    unreachable!("variables are pointers");
+                };
+                if storage == StorageClass::Function && !values.contains(&pointer) {
+                    values.push(pointer);
+                    continue;
+                }
+                let copy = self.variable(name, ty.clone());
+                if qualifier == ParameterQualifier::InOut {
+                    let value = self.value(InstKind::Load(pointer), ty.clone());
+                    self.emit(InstKind::Store(copy, value), None);
+                }
+                values.push(copy);
+                copies.push((pointer, copy, ty));
+            }
+            let ty = lower_type(&function.return_type);
+            let ty = (ty != Type::Void).then_some(ty);
+            let result = self.emit(InstKind::Call(name.to_string(), values), ty);
+            for (pointer, copy, ty) in copies {
+                let value = self.value(InstKind::Load(copy), ty);
+                self.emit(InstKind::Store(pointer, value), None);
+            }
+            return result;
+        }
+
+        let intrinsic = Intrinsic::from_name(name).expect("calls are checked to resolve");
+        let mut values = Vec::new();
+        let mut types = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            let value = match arg {
+                // An atomic is operated on in place.
+                Expression::Identifier { name, .. } if i == 0 && intrinsic.is_atomic() => {
+                    self.pointer(name)
+                }
+                arg => self.lower_value(arg),
+            };
+            values.push(value);
+            types.push(self.value_type(value));
+        }
+        let ty = intrinsic
+            .result_type(&types)
+            .expect("intrinsic calls are checked");
+        let ty = (ty != Type::Void).then_some(ty);
+        self.emit(InstKind::Intrinsic(intrinsic, values), ty)
+    }
+}
+
+// Orders the blocks so each comes after every block that dominates it, as
+// SPIR-V requires: the reachable ones in reverse postorder from the entry,
+// then the merge blocks no path reaches, in the order they were created.
+fn sort_blocks(function: &mut Function) {
+    let order = function.reverse_postorder();
+    let mut blocks = std::mem::take(&mut function.blocks);
+    let mut sorted = Vec::with_capacity(blocks.len());
+    for id in order {
+        let index = blocks
+            .iter()
+            .position(|block| block.id == id)
+            .expect("ordered blocks exist");
+        sorted.push(blocks.remove(index));
+    }
+    sorted.extend(blocks);
+    function.blocks = sorted;
+}
@@ -3,6 +3,76 @@
 
 // Use a module to hold the AST definitions.
 pub mod ast;
+// Compile-time evaluation of constant expressions.
+pub mod const_eval;
+// Checks that variables are assigned before they are read.
+pub mod definite_assignment;
+// Removes unreachable blocks and unused instructions from functions.
+pub mod dce;
+// The dominator tree of a function's control flow graph.
+pub mod dominators;
+// Diagnostics reported to the user, with the source locations they cite.
+pub mod diagnostics;
+// Checks that match statements cover every value of their selector.
+pub mod exhaustiveness;
+// The call graph between the functions of a translation unit.
+pub mod call_graph;
+// Global value numbering, which removes repeated computations.
+pub mod gvn;
+// Replaces calls with copies of the functions they call.
+pub mod inline;
+// The built-in functions of the language.
+pub mod intrinsics;
+// Moves loop-invariant computations out of loops.
+pub mod licm;
+// The intermediate representation optimizations and backends work on.
+pub mod ir;
+// Reads IR back from its textual form.
+pub mod ir_parser;
+// Writes IR in its textual form.
+pub mod ir_printer;
+// Runs each pass over the IR tests in `tests/ir`.
+#[cfg(test)]
+mod ir_tests;
+// Checks that a module is valid IR.
+pub mod ir_verifier;
+// Lowers the checked AST to IR.
+pub mod lower;
+// Loads a program's source files, following their imports.
+pub mod modules;
+// Which values are live at the start and end of each block.
+pub mod liveness;
+// The natural loops of a function's control flow graph.
+pub mod loops;
+// Promotes local variables to SSA values.
+pub mod mem2reg;
+// Replaces generic functions with one copy per use.
+pub mod monomorphize;
+// Gives the private items of each module a namespace of their own.
+pub mod namespaces;
+// Runs the optimization passes selected by the optimization level.
+pub mod pass_manager;
+// Resolves `static if` against the constants defined with -D.
+pub mod static_if;
+// Compiles the source tests in `tests/osl` and checks their diagnostics.
+#[cfg(test)]
+mod source_tests;
+// Reflection data describing a compiled program to the runtime.
+pub mod reflection;
+// Checks that barriers are only reached in uniform control flow.
+pub mod uniformity;
+// The targets a program can be compiled for, and what each supports.
+pub mod target;
+// Sparse conditional constant propagation over the IR.
+pub mod sccp;
+// Semantic analysis runs over the AST once parsing succeeds.
+pub mod semantic;
+// The text of every source file, for citing it in diagnostics.
+pub mod source_map;
+// Strips a module down to what its entry points use.
+pub mod strip;
+// Unrolls loops that run a number of times known at compile time.
+pub mod unroll;
 
 // This line includes the Rust code that LALRPOP generates from our
 // shader.lalrpop grammar file. The `#[allow]` attributes are to
@@ -13,33 +83,405 @@ pub mod shader {
     include!(concat!(env!("OUT_DIR"), "/shader.rs"));
 }
 
+use std::path::PathBuf;
+
+use ast::TranslationUnit;
+use source_map::SourceMap;
+
+// Compiled when no input file is given.
+const SAMPLE_SOURCE: &str = r#"
+    void scale(in float factor, inout float value) {
+        value = value * factor;
+    }
+
+    float my_func(int a, out float result) {
+        var float c = 1.0;
+        let limit = min(float(a), 10.0);
+        if (limit > 5.0) {
+           c = 2.0;
+        }
+        scale(2.0, c);
+        result = c;
+        return c;
+    }
+"#;
+
+// What `--emit` prints besides the diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    TypedAst,
+    MonoAst,
+    Ir,
+    Reflection,
+}
+
+impl Emit {
+    const ALL: [Emit; 4] = [Emit::TypedAst, Emit::MonoAst, Emit::Ir, Emit::Reflection];
+
+    // The output named by `--emit <name>`.
+    fn from_name(name: &str) -> Option<Emit> {
+        Emit::ALL.into_iter().find(|emit| emit.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Emit::TypedAst => "typed-ast",
+            Emit::MonoAst => "mono-ast",
+            Emit::Ir => "ir",
+            Emit::Reflection => "reflection",
+        }
+    }
+}
+
 fn main() {
     println!("--- Shader Compiler ---");
 
-    let source_code = r#"
-        float my_func(int a) {
-            float c = 1.0;
-            if (a > 10) {
-               c = 2.0;
+    // Usage: shader_compiler [-I <dir>]... [-D <NAME>[=<values>]]...
+    //                        [--target <target>] [--emit <stage>]
+    //                        [-O0 | -O1 | -O2 | -Os] [--verify-each]
+    //                        [--print-after=<pass>]... [--time-passes]
+    //                        [--entry <name>] [--shadowing=<policy>]
+    //                        [<file.osl> | <file.osir>]
+    //
+    // `-I` adds a directory to search for imported files. `-D` defines a
+    // constant for `static if`; giving it several comma-separated values
+    // compiles one permutation per combination of values. `--target`
+    // selects what the program is compiled for, vulkan1.1 by default.
+    // `--emit typed-ast` prints the AST again after semantic analysis, with
+    // the inferred type of every declaration filled in. `--emit mono-ast`
+    // prints it after generic functions have been monomorphized, `--emit
+    // ir` prints the IR it is lowered to, and `--emit reflection` prints
+    // the program's reflection data as JSON. A `.osir` file holds IR in
+    // its textual form, which is read instead of lowered from source.
+    // `-O1`, `-O2` and `-Os` optimize the IR; `-O0`, the default, does
+    // not. `--verify-each` checks that the IR is valid after lowering and
+    // after every optimization pass, and names the pass that first made it
+    // invalid. `--print-after` prints the IR after each run of the named
+    // pass, and `--time-passes` reports how long each pass took.
+    // `--entry` selects the entry point to compile: the others, and what
+    // only they use, are removed from the IR. `--shadowing` decides whether
+    // a declaration hiding one from an enclosing scope is allowed, a
+    // warning (the default) or an error: `allow`, `warn` or `deny`.
+    let mut args = std::env::args().skip(1);
+    let mut include_dirs = Vec::new();
+    let mut defines = Vec::new();
+    let mut target = None;
+    let mut shadowing = None;
+    let mut emit = None;
+    let mut pipeline = pass_manager::PipelineOptions::default();
+    let mut input: Option<PathBuf> = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-I" => include_dirs.extend(args.next().map(PathBuf::from)),
+            "-D" => defines.extend(args.next()),
+            "--target" => target = args.next(),
+            "--emit" => emit = args.next(),
+            "--verify-each" => pipeline.verify_each = true,
+            "--time-passes" => pipeline.time_passes = true,
+            "--entry" => pipeline.entry_point = args.next(),
+            _ => {
+                if let Some(level) = pass_manager::OptLevel::from_flag(&arg) {
+                    pipeline.level = level;
+                } else if let Some(policy) = arg.strip_prefix("--shadowing=") {
+                    shadowing = Some(policy.to_string());
+                } else if let Some(pass) = arg.strip_prefix("--print-after=") {
+                    pipeline.print_after.push(pass.to_string());
+                } else if let Some(dir) = arg.strip_prefix("-I") {
+                    include_dirs.push(PathBuf::from(dir));
+                } else if let Some(define) = arg.strip_prefix("-D") {
+                    defines.push(define.to_string());
+                } else if arg.starts_with('-') {
+                    println!("error: unknown option `{}`", arg);
+                    println!(
+                        "help: to compile a file whose name starts with `-`, write `./{}`",
+                        arg
+                    );
+                    return;
+                } else if let Some(first) = &input {
+                    println!(
+                        "error: more than one input file: `{}` and `{}`",
+                        first.display(),
+                        arg
+                    );
+                    println!("help: compile one file at a time");
+                    return;
+                } else {
+                    input = Some(PathBuf::from(arg));
+                }
             }
         }
-    "#;
+    }
+    let defines = match defines
+        .iter()
+        .map(|define| static_if::parse_define(define))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(defines) => defines,
+        Err(error) => {
+            println!("error: {}", error);
+            println!("help: use -D NAME, -D NAME=value or -D NAME=value1,value2");
+            return;
+        }
+    };
+    let permutations = match static_if::permutations(&defines) {
+        Ok(permutations) => permutations,
+        Err(error) => {
+            println!("error: {}", error);
+            println!("help: give fewer values to -D, or compile the rest separately");
+            return;
+        }
+    };
+
+    let target = match target {
+        None => target::Target::default(),
+        Some(name) => match target::Target::from_name(&name) {
+            Some(target) => target,
+            None => {
+                let names: Vec<&str> = target::Target::ALL.iter().map(|t| t.name()).collect();
+                println!("error: unknown target `{}`", name);
+                println!("help: the targets are {}", names.join(", "));
+                return;
+            }
+        },
+    };
+    let shadowing = match shadowing {
+        None => semantic::ShadowingPolicy::default(),
+        Some(name) => match semantic::ShadowingPolicy::from_name(&name) {
+            Some(policy) => policy,
+            None => {
+                let names: Vec<&str> = semantic::ShadowingPolicy::ALL
+                    .iter()
+                    .map(|policy| policy.name())
+                    .collect();
+                println!("error: unknown shadowing policy `{}`", name);
+                println!("help: the policies are {}", names.join(", "));
+                return;
+            }
+        },
+    };
+    let options = semantic::AnalysisOptions { shadowing, target };
+    let emit = match emit {
+        None => None,
+        Some(name) => match Emit::from_name(&name) {
+            Some(emit) => Some(emit),
+            None => {
+                let names: Vec<&str> = Emit::ALL.iter().map(|emit| emit.name()).collect();
+                println!("error: unknown output `{}` for --emit", name);
+                println!("help: the outputs are {}", names.join(", "));
+                return;
+            }
+        },
+    };
 
-    println!("\nParsing source code:\n{}", source_code);
+    if let Some(path) = &input
+        && path
+            .extension()
+            .is_some_and(|extension| extension == "osir")
+    {
+        compile_ir_file(path, &pipeline, emit);
+        return;
+    }
 
-    // Instantiate our generated parser.
-    let parser = shader::TranslationUnitParser::new();
+    let mut loader = modules::ModuleLoader::new(include_dirs);
+    let loaded = match &input {
+        Some(path) => {
+            println!("\nParsing {} and its imports", path.display());
+            loader.load(path)
+        }
+        None => {
+            println!("\nParsing source code:\n{}", SAMPLE_SOURCE);
+            loader.load_source("<sample>", SAMPLE_SOURCE)
+        }
+    };
 
-    // Parse the source code.
-    match parser.parse(source_code) {
+    match loaded {
         Ok(ast) => {
             println!("\nSuccessfully parsed into AST!");
             // The `:#?` format specifier pretty-prints the debug output.
             println!("{:#?}", ast);
+
+            for permutation in &permutations {
+                if permutations.len() > 1 {
+                    let values: Vec<String> = defines
+                        .iter()
+                        .map(|(name, _)| format!("{}={}", name, permutation[name]))
+                        .collect();
+                    println!("\n--- Permutation {} ---", values.join(" "));
+                }
+                compile(
+                    ast.clone(),
+                    permutation,
+                    &options,
+                    &pipeline,
+                    emit,
+                    loader.sources(),
+                );
+            }
+        }
+        Err(diagnostics) => {
+            println!("\nFailed to parse!");
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic.render(loader.sources()));
+            }
+        }
+    }
+}
+
+// Compiles one permutation of a parsed program.
+fn compile(
+    mut ast: TranslationUnit,
+    defines: &static_if::Defines,
+    options: &semantic::AnalysisOptions,
+    pipeline: &pass_manager::PipelineOptions,
+    emit: Option<Emit>,
+    sources: &SourceMap,
+) {
+    let mut diagnostics = static_if::resolve(&mut ast, defines);
+    // Analysis only sees the branches of `static if` that were taken.
+    if diagnostics.is_empty() {
+        diagnostics = semantic::analyze(&mut ast, options);
+    }
+    println!();
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic.render(sources));
+    }
+    let failed = diagnostics.iter().any(|d| d.is_error());
+    if failed {
+        println!("Semantic analysis failed.");
+    } else {
+        println!("Semantic analysis passed!");
+    }
+
+    if emit == Some(Emit::TypedAst) {
+        println!("\nTyped AST:");
+        println!("{:#?}", ast);
+    }
+    // Monomorphization relies on the type arguments analysis resolves, so
+    // it only runs on a valid program.
+    if failed {
+        return;
+    }
+
+    monomorphize::monomorphize(&mut ast);
+    if emit == Some(Emit::MonoAst) {
+        println!("\nMonomorphized AST:");
+        println!("{:#?}", ast);
+    }
+    let mut module = lower::lower(&ast);
+    let text = ir_printer::print(&module);
+    // Whatever lowering produces must read back as the same module.
+    debug_assert_eq!(
+        ir_parser::parse(&text, 0).map(|parsed| ir_printer::print(&parsed)),
+        Ok(text.clone())
+    );
+    if pipeline.verify_each
+        && let Err(error) = pass_manager::verify_after("lower", &module)
+    {
+        println!("\nerror: {}", error);
+        return;
+    }
+    if !optimize(&mut module, pipeline) {
+        return;
+    }
+    if emit == Some(Emit::Ir) {
+        println!("\nIR:");
+        print!("{}", ir_printer::print(&module));
+    }
+    if emit == Some(Emit::Reflection) {
+        println!("\nReflection:");
+        println!("{}", reflection::reflect(&module).to_json());
+    }
+}
+
+// Reads a module of textual IR in place of a program's source.
+fn compile_ir_file(
+    path: &std::path::Path,
+    pipeline: &pass_manager::PipelineOptions,
+    emit: Option<Emit>,
+) {
+    println!("\nParsing {}", path.display());
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            println!("error: cannot read `{}`: {}", path.display(), error);
+            return;
+        }
+    };
+    let mut sources = SourceMap::default();
+    let id = sources.add(path.display().to_string(), text);
+    let file = sources.file(id);
+    match ir_parser::parse(&file.text, file.base) {
+        Ok(mut module) => {
+            println!("\nSuccessfully parsed IR!");
+            // Hand-written IR is checked the way a pass's output would be.
+            let violations = ir_verifier::verify(&module);
+            for violation in &violations {
+                println!("error: {}", violation);
+            }
+            if !violations.is_empty() {
+                println!("Invalid IR.");
+                return;
+            }
+            if !optimize(&mut module, pipeline) {
+                return;
+            }
+            if emit == Some(Emit::Ir) {
+                println!("\nIR:");
+                print!("{}", ir_printer::print(&module));
+            }
+            if emit == Some(Emit::Reflection) {
+                println!("\nReflection:");
+                println!("{}", reflection::reflect(&module).to_json());
+            }
         }
-        Err(e) => {
+        Err(diagnostics) => {
             println!("\nFailed to parse!");
-            println!("{}", e);
+            for diagnostic in &diagnostics {
+                println!("{}", diagnostic.render(&sources));
+            }
+        }
+    }
+}
+
+// Runs the optimization pipeline over the module. Returns whether it
+// left the module valid.
+fn optimize(module: &mut ir::Module, options: &pass_manager::PipelineOptions) -> bool {
+    if let Some(entry_point) = &options.entry_point
+        && !module
+            .functions
+            .iter()
+            .any(|function| function.stage.is_some() && function.name == *entry_point)
+    {
+        println!("\nerror: no entry point named `{}`", entry_point);
+        let names: Vec<&str> = module
+            .functions
+            .iter()
+            .filter(|function| function.stage.is_some())
+            .map(|function| function.name.as_str())
+            .collect();
+        if names.is_empty() {
+            println!("help: the module has no entry points");
+        } else {
+            println!("help: the entry points are {}", names.join(", "));
+        }
+        return false;
+    }
+    let mut manager = pass_manager::PassManager::new(options.clone());
+    for name in manager.unknown_print_after() {
+        println!(
+            "warning: no pass named `{}` runs at this optimization level",
+            name
+        );
+    }
+    let result = manager.run(module);
+    if options.time_passes {
+        print!("\n{}", manager.timing_report());
+    }
+    match result {
+        Ok(()) => true,
+        Err(error) => {
+            println!("\nerror: {}", error);
+            false
         }
     }
 }
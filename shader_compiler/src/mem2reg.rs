@@ -0,0 +1,277 @@
+// src/mem2reg.rs
+// This file implements mem2reg, an optimization pass that promotes local
+// variables to SSA values. Lowering gives every mutable local a variable
+// that is stored to on assignment and loaded from on use; once promoted,
+// each load is replaced by the value last stored, and the variable, its
+// loads and its stores are removed. Only variables whose pointer is never
+// passed anywhere, such as to a call or an atomic, are promoted.
+//
+// Where stores on different paths meet, a phi picks the value: phis go
+// in the iterated dominance frontier of the blocks that store to the
+// variable, as in Cytron et al., "Efficiently Computing Static Single
+// Assignment Form and the Control Dependence Graph". A phi is only
+// inserted where the variable is live, that is where some path goes on to
+// load it before storing to it again, so a variable assigned in both
+// branches of an `if` and not read after it gets none.
+//
+// A load that no store reaches reads memory never written, whose value is
+// undefined; it is replaced by zero. Atomic variables are never promoted.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Type;
+use crate::const_eval::ConstValue;
+use crate::dce;
+use crate::dominators::Dominators;
+use crate::ir::{BlockId, Function, InstKind, Instruction, IrType, Module, Value};
+use crate::liveness::Liveness;
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Mem2Reg;
+
+impl Pass for Mem2Reg {
+    fn name(&self) -> &'static str {
+        "mem2reg"
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            // Blocks nothing reaches are left out of the dominator tree the
+            // renaming walks, so their loads and stores go first.
+            if dce::remove_unreachable_blocks(function) {
+                analyses.invalidate_function(&function.name, Preserved::Nothing);
+                preserved = Preserved::Nothing;
+            }
+            // Atomics are only accessed through the atomic intrinsics.
+            let mut variables: Vec<Value> = function
+                .private_variables()
+                .into_iter()
+                .filter(|&variable| held_type(function, variable).element().is_some())
+                .collect();
+            if variables.is_empty() {
+                continue;
+            }
+            variables.sort_by_key(|variable| variable.0);
+            let dominators = analyses.dominators(function);
+            let liveness = analyses.liveness(function);
+            promote(function, &dominators, &liveness, &variables);
+            analyses.invalidate_function(&function.name, Preserved::Cfg);
+            preserved = preserved.weakest(Preserved::Cfg);
+        }
+        preserved
+    }
+}
+
+fn promote(
+    function: &mut Function,
+    dominators: &Dominators,
+    liveness: &Liveness,
+    variables: &[Value],
+) {
+    let promoted: HashSet<Value> = variables.iter().copied().collect();
+
+    // The phis to insert in each block, with the variable each is for.
+    let frontiers = dominators.frontiers(function);
+    let mut phis: HashMap<BlockId, Vec<(Value, Value)>> = HashMap::new();
+    for &variable in variables {
+        let ty = held_type(function, variable);
+        let mut worklist: Vec<BlockId> = function
+            .blocks
+            .iter()
+            .filter(|block| {
+                block
+                    .instructions
+                    .iter()
+                    .any(|i| matches!(i.kind, InstKind::Store(pointer, _) if pointer == variable))
+            })
+            .map(|block| block.id)
+            .collect();
+        // The iterated frontier is computed in full, as a block where the
+        // variable is dead can still pass its definitions on to one where
+        // it is live.
+        let mut in_frontier = HashSet::new();
+        while let Some(block) = worklist.pop() {
+            for &frontier in frontiers.get(&block).into_iter().flatten() {
+                if !in_frontier.insert(frontier) {
+                    continue;
+                }
+                worklist.push(frontier);
+                if liveness.variables_live_in(frontier).contains(&variable) {
+                    let phi = function.new_value(IrType::Value(ty.clone()));
+                    phis.entry(frontier).or_default().push((variable, phi));
+                }
+            }
+        }
+    }
+
+    // Walk the dominator tree, keeping the value each variable holds at
+    // the current point on a stack per variable.
+    let indices = function.block_indices();
+    let children = dominators.children();
+    let mut current: HashMap<Value, Vec<Value>> = HashMap::new();
+    let mut replacements: HashMap<Value, Value> = HashMap::new();
+    let mut incoming: HashMap<Value, Vec<(BlockId, Value)>> = HashMap::new();
+    let mut zeros: HashMap<Value, Value> = HashMap::new();
+    let mut zero_instructions = Vec::new();
+
+    enum Walk {
+        Enter(BlockId),
+        // Leaving a block undoes the values it pushed.
+        Exit(Vec<Value>),
+    }
+    let mut walk = vec![Walk::Enter(function.blocks[0].id)];
+    while let Some(step) = walk.pop() {
+        let block = match step {
+            Walk::Enter(block) => block,
+            Walk::Exit(pushed) => {
+                for variable in pushed {
+                    current.get_mut(&variable).map(Vec::pop);
+                }
+                continue;
+            }
+        };
+        let mut pushed = Vec::new();
+        for &(variable, phi) in phis.get(&block).into_iter().flatten() {
+            current.entry(variable).or_default().push(phi);
+            pushed.push(variable);
+        }
+
+        let index = indices[&block];
+        let instructions = std::mem::take(&mut function.blocks[index].instructions);
+        let mut kept = Vec::with_capacity(instructions.len());
+        for instruction in instructions {
+            match instruction.kind {
+                InstKind::Load(pointer) if promoted.contains(&pointer) => {
+                    let value = current_value(
+                        function,
+                        pointer,
+                        &current,
+                        &mut zeros,
+                        &mut zero_instructions,
+                    );
+                    let result = instruction.result.expect("a load has a result");
+                    replacements.insert(result, value);
+                }
+                InstKind::Store(pointer, value) if promoted.contains(&pointer) => {
+                    let value = replacements.get(&value).copied().unwrap_or(value);
+                    current.entry(pointer).or_default().push(value);
+                    pushed.push(pointer);
+                }
+                _ => kept.push(instruction),
+            }
+        }
+        function.blocks[index].instructions = kept;
+
+        // A phi takes one value per predecessor, however many of the
+        // block's edges lead to it.
+        let mut successors = function.blocks[index].terminator.successors();
+        let mut seen = HashSet::new();
+        successors.retain(|&successor| seen.insert(successor));
+        for successor in successors {
+            for &(variable, phi) in phis.get(&successor).into_iter().flatten() {
+                let value = current_value(
+                    function,
+                    variable,
+                    &current,
+                    &mut zeros,
+                    &mut zero_instructions,
+                );
+                incoming.entry(phi).or_default().push((block, value));
+            }
+        }
+
+        walk.push(Walk::Exit(pushed));
+        // Pushed in reverse so the children are visited in order.
+        for &child in children[&block].iter().rev() {
+            walk.push(Walk::Enter(child));
+        }
+    }
+
+    for block in &mut function.blocks {
+        let mut new_phis: Vec<Instruction> = phis
+            .remove(&block.id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, phi)| Instruction {
+                result: Some(phi),
+                kind: InstKind::Phi(incoming.remove(&phi).unwrap_or_default()),
+            })
+            .collect();
+        block.instructions.retain(|instruction| {
+            !(matches!(instruction.kind, InstKind::Variable(_))
+                && instruction
+                    .result
+                    .is_some_and(|result| promoted.contains(&result)))
+        });
+        new_phis.append(&mut block.instructions);
+        block.instructions = new_phis;
+    }
+    function.blocks[0]
+        .instructions
+        .splice(0..0, zero_instructions);
+    for block in &mut function.blocks {
+        for instruction in &mut block.instructions {
+            for operand in instruction.kind.operands_mut() {
+                if let Some(&value) = replacements.get(operand) {
+                    *operand = value;
+                }
+            }
+        }
+        for operand in block.terminator.operands_mut() {
+            if let Some(&value) = replacements.get(operand) {
+                *operand = value;
+            }
+        }
+    }
+}
+
+// The value the variable holds at the current point of the walk, or zero
+// if no store reaches it.
+fn current_value(
+    function: &mut Function,
+    variable: Value,
+    current: &HashMap<Value, Vec<Value>>,
+    zeros: &mut HashMap<Value, Value>,
+    zero_instructions: &mut Vec<Instruction>,
+) -> Value {
+    if let Some(&value) = current.get(&variable).and_then(|stack| stack.last()) {
+        return value;
+    }
+    *zeros.entry(variable).or_insert_with(|| {
+        let ty = held_type(function, variable);
+        zero(function, &ty, zero_instructions)
+    })
+}
+
+// The type of the value a variable holds.
+fn held_type(function: &Function, variable: Value) -> Type {
+    match function.value_type(variable) {
+        IrType::Pointer(ty, _) => ty.clone(),
+        IrType::Value(_) => unreachable!("a variable is a pointer"),
+    }
+}
+
+// Emits zero of a scalar or vector type, returning its value.
+fn zero(function: &mut Function, ty: &Type, instructions: &mut Vec<Instruction>) -> Value {
+    let element = ty.element().expect("variables hold scalars and vectors");
+    let constant = match element {
+        Type::Float => ConstValue::Float(0.0),
+        Type::Int => ConstValue::Int(0),
+        _ => ConstValue::Bool(false),
+    };
+    let scalar = function.new_value(IrType::Value(element.clone()));
+    instructions.push(Instruction {
+        result: Some(scalar),
+        kind: InstKind::Constant(constant),
+    });
+    if element == *ty {
+        return scalar;
+    }
+    let vector = function.new_value(IrType::Value(ty.clone()));
+    instructions.push(Instruction {
+        result: Some(vector),
+        kind: InstKind::Splat(scalar),
+    });
+    vector
+}
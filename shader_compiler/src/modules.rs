@@ -0,0 +1,306 @@
+// src/modules.rs
+// This file loads a program from its root source file and every file it
+// imports, directly or indirectly. Each file is parsed once, however many
+// files import it, and becomes one module of the resulting translation
+// unit. Import paths are resolved relative to the importing file first,
+// then to each include directory in order.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lalrpop_util::ParseError;
+
+use crate::ast::{Module, ModuleInfo, Span, TranslationUnit};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::namespaces;
+use crate::shader;
+use crate::source_map::SourceMap;
+
+#[derive(Debug, PartialEq)]
+pub enum ModuleError {
+    Unreadable { path: String, reason: String },
+    // No file matched an import path in any searched directory.
+    NotFound(String),
+    // A module imports itself, directly or through other modules.
+    ImportCycle(String),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    InvalidToken,
+}
+
+// An error raised by a parsing action. Unlike the parser's own errors, its
+// span already includes the file's base offset.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::Unreadable { path, reason } => {
+                write!(f, "cannot read `{}`: {}", path, reason)
+            }
+            ModuleError::NotFound(path) => write!(f, "cannot find module `{}`", path),
+            ModuleError::ImportCycle(path) => {
+                write!(f, "module `{}` imports itself", path)
+            }
+            ModuleError::UnexpectedToken(token) => write!(f, "unexpected `{}`", token),
+            ModuleError::UnexpectedEnd => write!(f, "unexpected end of file"),
+            ModuleError::InvalidToken => write!(f, "invalid token"),
+        }
+    }
+}
+
+pub struct ModuleLoader {
+    include_dirs: Vec<PathBuf>,
+    sources: SourceMap,
+    // The module id of every file loaded so far, by canonical path.
+    loaded: HashMap<PathBuf, usize>,
+    // The modules being loaded, outermost first, each with the import that
+    // led to it. An import of one of these is a cycle.
+    loading: Vec<(usize, Option<Span>)>,
+    // Each module, by id, once it has been parsed.
+    modules: Vec<Option<(ModuleInfo, Module)>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ModuleLoader {
+    pub fn new(include_dirs: Vec<PathBuf>) -> Self {
+        ModuleLoader {
+            include_dirs,
+            sources: SourceMap::default(),
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+            modules: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    // The text of every file loaded, for rendering diagnostics.
+    pub fn sources(&self) -> &SourceMap {
+        &self.sources
+    }
+
+    // Loads the program whose root module is the file at `path`.
+    pub fn load(&mut self, path: &Path) -> Result<TranslationUnit, Vec<Diagnostic>> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+                self.load_module(path, Some(canonical), text, None);
+            }
+            Err(error) => self.unreadable(path, &error, None),
+        }
+        self.finish()
+    }
+
+    // Loads the program whose root module is `text`, which is not read from
+    // a file. Its imports are resolved relative to the working directory.
+    pub fn load_source(
+        &mut self,
+        name: &str,
+        text: &str,
+    ) -> Result<TranslationUnit, Vec<Diagnostic>> {
+        self.load_module(Path::new(name), None, text.to_string(), None);
+        self.finish()
+    }
+
+    fn finish(&mut self) -> Result<TranslationUnit, Vec<Diagnostic>> {
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+        let mut unit = TranslationUnit {
+            modules: Vec::new(),
+            spec_constants: Vec::new(),
+            workgroup_variables: Vec::new(),
+            enums: Vec::new(),
+            functions: Vec::new(),
+        };
+        for (info, module) in self.modules.drain(..).flatten() {
+            unit.modules.push(info);
+            unit.spec_constants.extend(module.spec_constants);
+            unit.workgroup_variables.extend(module.workgroup_variables);
+            unit.enums.extend(module.enums);
+            unit.functions.extend(module.functions);
+        }
+        namespaces::scope_private_items(&mut unit);
+        Ok(unit)
+    }
+
+    // Parses one file and loads its imports, returning its module id.
+    // `canonical` is `None` for text that does not come from a file.
+    fn load_module(
+        &mut self,
+        path: &Path,
+        canonical: Option<PathBuf>,
+        text: String,
+        imported_at: Option<Span>,
+    ) -> usize {
+        let display = path.display().to_string();
+        let id = self.sources.add(display.clone(), text);
+        if let Some(canonical) = canonical {
+            self.loaded.insert(canonical, id);
+        }
+        self.modules.push(None);
+
+        let base = self.sources.file(id).base;
+        let parsed = shader::ModuleParser::new().parse(base, &self.sources.file(id).text);
+        let mut module = match parsed {
+            Ok(module) => module,
+            Err(error) => {
+                self.diagnostics.push(parse_error(error, base));
+                return id;
+            }
+        };
+        for constant in &mut module.spec_constants {
+            constant.module = id;
+        }
+        for variable in &mut module.workgroup_variables {
+            variable.module = id;
+        }
+        for definition in &mut module.enums {
+            definition.module = id;
+        }
+        for definition in &mut module.functions {
+            definition.module = id;
+        }
+
+        self.loading.push((id, imported_at));
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut imports = Vec::new();
+        for import in &module.imports {
+            if let Some(imported) = self.load_import(&directory, &import.path, import.span)
+                && !imports.contains(&imported)
+            {
+                imports.push(imported);
+            }
+        }
+        self.loading.pop();
+
+        let info = ModuleInfo {
+            path: display,
+            imports,
+        };
+        self.modules[id] = Some((info, module));
+        id
+    }
+
+    // Resolves and loads one import of a file in `directory`, returning the
+    // id of the imported module.
+    fn load_import(&mut self, directory: &Path, path: &str, span: Span) -> Option<usize> {
+        let candidates =
+            std::iter::once(directory).chain(self.include_dirs.iter().map(PathBuf::as_path));
+        let Some(found) = candidates
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+        else {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                ModuleError::NotFound(path.to_string()).to_string(),
+            )
+            .with_span(span)
+            .with_help("import paths are relative to the importing file or an include directory given with -I");
+            self.diagnostics.push(diagnostic);
+            return None;
+        };
+
+        let canonical = found.canonicalize().unwrap_or_else(|_| found.clone());
+        if let Some(&id) = self.loaded.get(&canonical) {
+            if let Some(position) = self.loading.iter().position(|&(loading, _)| loading == id) {
+                self.report_cycle(position, span);
+                return None;
+            }
+            return Some(id);
+        }
+
+        match fs::read_to_string(&found) {
+            Ok(text) => Some(self.load_module(&found, Some(canonical), text, Some(span))),
+            Err(error) => {
+                self.unreadable(&found, &error, Some(span));
+                None
+            }
+        }
+    }
+
+    // Reports the cycle formed by an import at `span` of the module at
+    // `position` in the loading stack, citing each import along the way.
+    fn report_cycle(&mut self, position: usize, span: Span) {
+        let (id, _) = self.loading[position];
+        let path = self.sources.file(id).path.clone();
+        let mut diagnostic =
+            Diagnostic::new(Severity::Error, ModuleError::ImportCycle(path).to_string())
+                .with_span(span);
+        for pair in self.loading[position..].windows(2) {
+            let (importer, _) = pair[0];
+            let (imported, imported_at) = pair[1];
+            let message = format!(
+                "`{}` imports `{}` here",
+                self.sources.file(importer).path,
+                self.sources.file(imported).path
+            );
+            diagnostic = diagnostic.with_note(message, imported_at);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn unreadable(&mut self, path: &Path, error: &std::io::Error, span: Option<Span>) {
+        let error = ModuleError::Unreadable {
+            path: path.display().to_string(),
+            reason: error.to_string(),
+        };
+        let mut diagnostic = Diagnostic::new(Severity::Error, error.to_string());
+        if let Some(span) = span {
+            diagnostic = diagnostic.with_span(span);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+// Converts a parse error, whose locations are relative to the file, into a
+// diagnostic.
+fn parse_error<T: fmt::Display>(
+    error: ParseError<usize, T, SyntaxError>,
+    base: usize,
+) -> Diagnostic {
+    let (error, start, end, expected) = match error {
+        ParseError::InvalidToken { location } => {
+            (ModuleError::InvalidToken, location, location, Vec::new())
+        }
+        ParseError::UnrecognizedEof { location, expected } => {
+            (ModuleError::UnexpectedEnd, location, location, expected)
+        }
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected,
+        } => (
+            ModuleError::UnexpectedToken(token.to_string()),
+            start,
+            end,
+            expected,
+        ),
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => (
+            ModuleError::UnexpectedToken(token.to_string()),
+            start,
+            end,
+            Vec::new(),
+        ),
+        ParseError::User { error } => {
+            return Diagnostic::new(Severity::Error, error.message).with_span(error.span);
+        }
+    };
+    let span = Span {
+        start: base + start,
+        end: base + end,
+    };
+    let diagnostic = Diagnostic::new(Severity::Error, error.to_string()).with_span(span);
+    if expected.is_empty() {
+        diagnostic
+    } else {
+        diagnostic.with_help(format!("expected one of {}", expected.join(", ")))
+    }
+}
@@ -0,0 +1,288 @@
+// src/monomorphize.rs
+// This file implements monomorphization: it replaces every generic function
+// with one copy per set of type arguments it is called with, so the stages
+// after semantic analysis only ever see concrete types. It runs on an AST
+// that has passed analysis, which has already checked each generic function
+// once and recorded the type arguments of every call to one.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{CaseLabel, Expression, FunctionDefinition, Statement, TranslationUnit, Type};
+
+// Rewrites the unit so that it contains no generic functions. Each
+// instance is named after its function and type arguments, as in
+// `scale<float3>`, and calls are renamed to the instance they use. Generic
+// functions that are never called are dropped.
+pub fn monomorphize(unit: &mut TranslationUnit) {
+    let (generic, concrete): (Vec<_>, Vec<_>) = std::mem::take(&mut unit.functions)
+        .into_iter()
+        .partition(|function| !function.generics.is_empty());
+    let generic: HashMap<String, FunctionDefinition> = generic
+        .into_iter()
+        .map(|function| (function.name.clone(), function))
+        .collect();
+
+    let mut instantiator = Instantiator {
+        generic: &generic,
+        created: HashSet::new(),
+        pending: Vec::new(),
+    };
+    let mut functions = concrete;
+    for function in &mut functions {
+        instantiator.statements(&mut function.body);
+    }
+    // Instances can call further generic functions, which are instantiated
+    // in turn.
+    let mut next = 0;
+    while next < instantiator.pending.len() {
+        let mut instance = instantiator.pending[next].clone();
+        instantiator.statements(&mut instance.body);
+        functions.push(instance);
+        next += 1;
+    }
+    unit.functions = functions;
+}
+
+struct Instantiator<'a> {
+    generic: &'a HashMap<String, FunctionDefinition>,
+    // Names of the instances created so far.
+    created: HashSet<String>,
+    // Instances whose bodies still have to be rewritten, in creation order.
+    pending: Vec<FunctionDefinition>,
+}
+
+impl Instantiator<'_> {
+    fn statements(&mut self, stmts: &mut [Statement]) {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Declaration {
+                initializer: Some(initializer),
+                ..
+            } => self.expression(initializer),
+            Statement::Block(stmts) => self.statements(stmts),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            }
+            | Statement::StaticIf {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                self.expression(condition);
+                self.statements(if_block);
+                if let Some(else_block) = else_block {
+                    self.statements(else_block);
+                }
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                self.expression(selector);
+                for case in cases {
+                    self.statements(&mut case.body);
+                }
+            }
+            Statement::Match { selector, arms, .. } => {
+                self.expression(selector);
+                for arm in arms {
+                    self.statements(&mut arm.body);
+                }
+            }
+            Statement::Return {
+                value: Some(value), ..
+            }
+            | Statement::Expression(value) => self.expression(value),
+            Statement::Declaration {
+                initializer: None, ..
+            }
+            | Statement::Return { value: None, .. }
+            | Statement::Break { .. }
+            | Statement::Fallthrough { .. }
+            | Statement::Discard { .. }
+            | Statement::Demote { .. } => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Call {
+                name,
+                args,
+                type_args,
+                ..
+            } => {
+                for arg in args.iter_mut() {
+                    self.expression(arg);
+                }
+                if let Some(function) = self.generic.get(name.as_str()) {
+                    *name = self.instantiate(function, type_args);
+                    type_args.clear();
+                }
+            }
+            Expression::Constructor { args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Assignment { value, .. } => self.expression(value),
+            Expression::Identifier { .. }
+            | Expression::EnumVariant { .. }
+            | Expression::IntLiteral { .. }
+            | Expression::FloatLiteral { .. }
+            | Expression::BoolLiteral { .. } => {}
+        }
+    }
+
+    // Returns the name of the instance of `function` for `type_args`,
+    // creating it if this is the first call that needs it.
+    fn instantiate(&mut self, function: &FunctionDefinition, type_args: &[Type]) -> String {
+        let spelled: Vec<String> = type_args.iter().map(Type::to_string).collect();
+        let name = format!("{}<{}>", function.name, spelled.join(", "));
+        if !self.created.insert(name.clone()) {
+            return name;
+        }
+
+        let bindings: HashMap<String, Type> = function
+            .generics
+            .iter()
+            .map(|generic| generic.name.clone())
+            .zip(type_args.iter().cloned())
+            .collect();
+        let mut instance = function.clone();
+        instance.name = name.clone();
+        instance.generics.clear();
+        instance.return_type = instance.return_type.substitute(&bindings);
+        for param in &mut instance.params {
+            param.type_name = param.type_name.substitute(&bindings);
+        }
+        substitute_statements(&mut instance.body, &bindings);
+        self.pending.push(instance);
+        name
+    }
+}
+
+// Replaces type parameters with their bound types in every type written in
+// the statements.
+fn substitute_statements(stmts: &mut [Statement], bindings: &HashMap<String, Type>) {
+    for stmt in stmts {
+        match stmt {
+            Statement::Declaration {
+                type_name,
+                initializer,
+                ..
+            } => {
+                if let Some(type_name) = type_name {
+                    *type_name = type_name.substitute(bindings);
+                }
+                if let Some(initializer) = initializer {
+                    substitute_expression(initializer, bindings);
+                }
+            }
+            Statement::Block(stmts) => substitute_statements(stmts, bindings),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            }
+            | Statement::StaticIf {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                substitute_expression(condition, bindings);
+                substitute_statements(if_block, bindings);
+                if let Some(else_block) = else_block {
+                    substitute_statements(else_block, bindings);
+                }
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                substitute_expression(selector, bindings);
+                for case in cases {
+                    for label in &mut case.labels {
+                        if let CaseLabel::Value(value) = label {
+                            substitute_expression(value, bindings);
+                        }
+                    }
+                    substitute_statements(&mut case.body, bindings);
+                }
+            }
+            Statement::Match { selector, arms, .. } => {
+                substitute_expression(selector, bindings);
+                for arm in arms {
+                    substitute_statements(&mut arm.body, bindings);
+                }
+            }
+            Statement::Return {
+                value: Some(value), ..
+            }
+            | Statement::Expression(value) => substitute_expression(value, bindings),
+            Statement::Return { value: None, .. }
+            | Statement::Break { .. }
+            | Statement::Fallthrough { .. }
+            | Statement::Discard { .. }
+            | Statement::Demote { .. } => {}
+        }
+    }
+}
+
+fn substitute_expression(expr: &mut Expression, bindings: &HashMap<String, Type>) {
+    match expr {
+        Expression::Call {
+            name,
+            args,
+            type_args,
+            span,
+        } => {
+            for arg in args.iter_mut() {
+                substitute_expression(arg, bindings);
+            }
+            // `T(x)` constructs a value of the type `T` stands for.
+            if let Some(ty) = bindings.get(name.as_str()) {
+                *expr = Expression::Constructor {
+                    type_name: ty.clone(),
+                    args: std::mem::take(args),
+                    span: *span,
+                };
+                return;
+            }
+            for ty in type_args {
+                *ty = ty.substitute(bindings);
+            }
+        }
+        Expression::Constructor {
+            type_name, args, ..
+        } => {
+            *type_name = type_name.substitute(bindings);
+            for arg in args {
+                substitute_expression(arg, bindings);
+            }
+        }
+        Expression::BinaryOp { left, right, .. } => {
+            substitute_expression(left, bindings);
+            substitute_expression(right, bindings);
+        }
+        Expression::Assignment { value, .. } => substitute_expression(value, bindings),
+        Expression::Identifier { .. }
+        | Expression::EnumVariant { .. }
+        | Expression::IntLiteral { .. }
+        | Expression::FloatLiteral { .. }
+        | Expression::BoolLiteral { .. } => {}
+    }
+}
@@ -0,0 +1,316 @@
+// src/namespaces.rs
+// This file gives each module's private items a namespace of their own.
+// Every later stage finds functions, enums and globals by name alone, so
+// when a private item shares its name with an item of another module, it
+// is renamed, together with every use of it in its own module, to a name
+// qualified by the module's path. Two files may then each define a private
+// `helper` without either seeing the other's.
+//
+// Of the items sharing a name, a public one keeps it, as other modules may
+// use it; if all of them are private, the first keeps it, so a use from
+// another module still finds one and is reported as private. Two items
+// with the same name in one module are left as they are, for semantic
+// analysis to report.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{
+    CaseLabel, Expression, FunctionDefinition, PatternKind, Statement, TranslationUnit, Type,
+};
+
+// The new name of each renamed item of one module, by the namespace it is
+// in. Spec constants and workgroup variables share one.
+#[derive(Default)]
+struct Renames {
+    functions: HashMap<String, String>,
+    types: HashMap<String, String>,
+    globals: HashMap<String, String>,
+}
+
+impl Renames {
+    fn is_empty(&self) -> bool {
+        self.functions.is_empty() && self.types.is_empty() && self.globals.is_empty()
+    }
+}
+
+// Renames the private items that share a name with an item of another
+// module, and their uses.
+pub fn scope_private_items(unit: &mut TranslationUnit) {
+    let mut renames: Vec<Renames> = unit.modules.iter().map(|_| Renames::default()).collect();
+    let qualify = |module: usize, name: &str| format!("{}::{}", unit.modules[module].path, name);
+
+    let functions = unit
+        .functions
+        .iter()
+        .map(|function| (function.module, function.public, function.name.as_str()));
+    for (module, name) in clashing_private_items(functions) {
+        let qualified = qualify(module, name);
+        renames[module]
+            .functions
+            .insert(name.to_string(), qualified);
+    }
+    let enums = unit.enums.iter().map(|definition| {
+        (
+            definition.module,
+            definition.public,
+            definition.name.as_str(),
+        )
+    });
+    for (module, name) in clashing_private_items(enums) {
+        let qualified = qualify(module, name);
+        renames[module].types.insert(name.to_string(), qualified);
+    }
+    let constants = unit
+        .spec_constants
+        .iter()
+        .map(|constant| (constant.module, constant.public, constant.name.as_str()));
+    let variables = unit
+        .workgroup_variables
+        .iter()
+        .map(|variable| (variable.module, variable.public, variable.name.as_str()));
+    for (module, name) in clashing_private_items(constants.chain(variables)) {
+        let qualified = qualify(module, name);
+        renames[module].globals.insert(name.to_string(), qualified);
+    }
+    if renames.iter().all(Renames::is_empty) {
+        return;
+    }
+
+    for constant in &mut unit.spec_constants {
+        let renames = &renames[constant.module];
+        rename(&mut constant.name, &renames.globals);
+        rename_type(&mut constant.type_name, &renames.types, &HashSet::new());
+    }
+    for variable in &mut unit.workgroup_variables {
+        let renames = &renames[variable.module];
+        rename(&mut variable.name, &renames.globals);
+        rename_type(&mut variable.type_name, &renames.types, &HashSet::new());
+    }
+    for definition in &mut unit.enums {
+        rename(&mut definition.name, &renames[definition.module].types);
+    }
+    for function in &mut unit.functions {
+        let renames = &renames[function.module];
+        if !renames.is_empty() {
+            rename_function(function, renames);
+        }
+    }
+}
+
+// The module and name of each private item, given as (module, public,
+// name), that must be renamed.
+fn clashing_private_items<'a>(
+    items: impl Iterator<Item = (usize, bool, &'a str)>,
+) -> Vec<(usize, &'a str)> {
+    let mut by_name: HashMap<&str, Vec<(usize, bool)>> = HashMap::new();
+    let mut names = Vec::new();
+    for (module, public, name) in items {
+        let definitions = by_name.entry(name).or_default();
+        if definitions.is_empty() {
+            names.push(name);
+        }
+        definitions.push((module, public));
+    }
+
+    let mut clashing = Vec::new();
+    for name in names {
+        let definitions = &by_name[name];
+        let (kept, _) = definitions
+            .iter()
+            .find(|&&(_, public)| public)
+            .unwrap_or(&definitions[0]);
+        for &(module, public) in definitions {
+            if !public && module != *kept && !clashing.contains(&(module, name)) {
+                clashing.push((module, name));
+            }
+        }
+    }
+    clashing
+}
+
+fn rename(name: &mut String, renames: &HashMap<String, String>) {
+    if let Some(renamed) = renames.get(name.as_str()) {
+        *name = renamed.clone();
+    }
+}
+
+// Renames the enum a type names, unless it is one of the type parameters
+// in `generics`.
+fn rename_type(ty: &mut Type, renames: &HashMap<String, String>, generics: &HashSet<String>) {
+    match ty {
+        Type::Named(name) if !generics.contains(name) => rename(name, renames),
+        Type::Atomic(element) => rename_type(element, renames, generics),
+        _ => {}
+    }
+}
+
+fn rename_function(function: &mut FunctionDefinition, renames: &Renames) {
+    rename(&mut function.name, &renames.functions);
+    let generics = function
+        .generics
+        .iter()
+        .map(|generic| generic.name.clone())
+        .collect();
+    let mut renamer = Renamer {
+        renames,
+        generics,
+        scopes: vec![function.params.iter().map(|p| p.name.clone()).collect()],
+    };
+    rename_type(&mut function.return_type, &renames.types, &renamer.generics);
+    for param in &mut function.params {
+        rename_type(&mut param.type_name, &renames.types, &renamer.generics);
+    }
+    renamer.block(&mut function.body);
+}
+
+// Renames the uses in one function body. Globals are only renamed where no
+// local of the same name hides them.
+struct Renamer<'r> {
+    renames: &'r Renames,
+    generics: HashSet<String>,
+    // The locals declared in each enclosing scope, outermost first.
+    scopes: Vec<HashSet<String>>,
+}
+
+impl Renamer<'_> {
+    fn block(&mut self, stmts: &mut [Statement]) {
+        self.scopes.push(HashSet::new());
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn global(&self, name: &mut String) {
+        if !self
+            .scopes
+            .iter()
+            .any(|scope| scope.contains(name.as_str()))
+        {
+            rename(name, &self.renames.globals);
+        }
+    }
+
+    fn ty(&self, ty: &mut Type) {
+        rename_type(ty, &self.renames.types, &self.generics);
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Declaration {
+                type_name,
+                name,
+                initializer,
+                ..
+            } => {
+                if let Some(initializer) = initializer {
+                    self.expression(initializer);
+                }
+                if let Some(type_name) = type_name {
+                    self.ty(type_name);
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone());
+                }
+            }
+            Statement::Block(stmts) => self.block(stmts),
+            // The condition of a `static if` names -D constants, not
+            // globals.
+            Statement::StaticIf {
+                if_block,
+                else_block,
+                ..
+            } => {
+                self.block(if_block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                self.expression(condition);
+                self.block(if_block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                self.expression(selector);
+                for case in cases {
+                    for label in &mut case.labels {
+                        if let CaseLabel::Value(value) = label {
+                            self.expression(value);
+                        }
+                    }
+                    self.block(&mut case.body);
+                }
+            }
+            Statement::Match { selector, arms, .. } => {
+                self.expression(selector);
+                for arm in arms {
+                    for pattern in &mut arm.patterns {
+                        if let PatternKind::EnumVariant { enum_name, .. } = &mut pattern.kind {
+                            rename(enum_name, &self.renames.types);
+                        }
+                    }
+                    self.block(&mut arm.body);
+                }
+            }
+            Statement::Return {
+                value: Some(value), ..
+            }
+            | Statement::Expression(value) => self.expression(value),
+            Statement::Return { value: None, .. }
+            | Statement::Break { .. }
+            | Statement::Fallthrough { .. }
+            | Statement::Discard { .. }
+            | Statement::Demote { .. } => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Identifier { name, .. } => self.global(name),
+            Expression::Assignment { name, value, .. } => {
+                self.expression(value);
+                self.global(name);
+            }
+            Expression::EnumVariant { enum_name, .. } => rename(enum_name, &self.renames.types),
+            Expression::BinaryOp { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Call {
+                name,
+                args,
+                type_args,
+                ..
+            } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                for ty in type_args {
+                    self.ty(ty);
+                }
+                // `T(x)` constructs a value of a type parameter.
+                if !self.generics.contains(name.as_str()) {
+                    rename(name, &self.renames.functions);
+                }
+            }
+            Expression::Constructor { args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            Expression::IntLiteral { .. }
+            | Expression::FloatLiteral { .. }
+            | Expression::BoolLiteral { .. } => {}
+        }
+    }
+}
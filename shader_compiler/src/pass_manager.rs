@@ -0,0 +1,297 @@
+// src/pass_manager.rs
+// This file runs the optimization passes over a module. An optimization
+// level selects a pipeline, the list of passes to run in order. Passes ask
+// the analysis cache for the dominators, loops and liveness of a function
+// instead of computing them, so an analysis is only recomputed once a pass
+// has changed what it depends on. Each pass reports what it kept intact,
+// and the cache drops what it did not.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::dce::Dce;
+use crate::dominators::Dominators;
+use crate::gvn::Gvn;
+use crate::inline::Inliner;
+use crate::ir::{Function, Module};
+use crate::ir_printer;
+use crate::ir_verifier::{self, Violation};
+use crate::licm::Licm;
+use crate::liveness::Liveness;
+use crate::loops::LoopInfo;
+use crate::mem2reg::Mem2Reg;
+use crate::sccp::Sccp;
+use crate::strip::Strip;
+use crate::unroll::Unroll;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OptLevel {
+    // No optimization: the IR is emitted as lowered.
+    #[default]
+    O0,
+    O1,
+    O2,
+    // Like -O2, without the passes that grow code to speed it up.
+    Os,
+}
+
+impl OptLevel {
+    // The level selected by a flag such as `-O2`.
+    pub fn from_flag(flag: &str) -> Option<OptLevel> {
+        let level = match flag {
+            "-O0" => OptLevel::O0,
+            "-O1" => OptLevel::O1,
+            "-O2" => OptLevel::O2,
+            "-Os" => OptLevel::Os,
+            _ => return None,
+        };
+        Some(level)
+    }
+}
+
+// The passes run at the selected optimization level, in order. Selecting
+// an entry point strips the others even at -O0.
+pub fn pipeline(options: &PipelineOptions) -> Vec<Box<dyn Pass>> {
+    let strip = Box::new(Strip {
+        entry_point: options.entry_point.clone(),
+    });
+    match options.level {
+        OptLevel::O0 if options.entry_point.is_some() => vec![strip],
+        OptLevel::O0 => Vec::new(),
+        OptLevel::O1 | OptLevel::O2 | OptLevel::Os => {
+            // -Os only inlines what is about as small as the call, and
+            // only unrolls loops hinted `unroll`.
+            let (threshold, unroll_threshold) = match options.level {
+                OptLevel::O2 => (64, 256),
+                OptLevel::Os => (8, 0),
+                _ => (16, 64),
+            };
+            // Constants are propagated again once unrolling has made each
+            // copy's induction variable a constant.
+            vec![
+                Box::new(Inliner { threshold }),
+                Box::new(Mem2Reg),
+                Box::new(Sccp),
+                Box::new(Unroll {
+                    threshold: unroll_threshold,
+                }),
+                Box::new(Sccp),
+                Box::new(Licm),
+                Box::new(Gvn),
+                Box::new(Dce),
+                strip,
+            ]
+        }
+    }
+}
+
+// What a pass left intact, from most to least.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Preserved {
+    // The pass changed nothing.
+    All,
+    // The pass changed instructions, but not the blocks or the edges
+    // between them, so analyses of the control flow graph still hold.
+    Cfg,
+    Nothing,
+}
+
+impl Preserved {
+    // What two changes made one after the other leave intact.
+    pub fn weakest(self, other: Preserved) -> Preserved {
+        match (self, other) {
+            (Preserved::Nothing, _) | (_, Preserved::Nothing) => Preserved::Nothing,
+            (Preserved::Cfg, _) | (_, Preserved::Cfg) => Preserved::Cfg,
+            (Preserved::All, Preserved::All) => Preserved::All,
+        }
+    }
+}
+
+pub trait Pass {
+    // The name `--print-after` and the timing report refer to it by.
+    fn name(&self) -> &'static str;
+
+    // Transforms the module, returning what it preserved. A pass that
+    // changes a function and then asks for another analysis of it must
+    // invalidate the function's analyses first.
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved;
+}
+
+// The analyses computed for each function, by function name, until a pass
+// invalidates them.
+#[derive(Default)]
+pub struct Analyses {
+    dominators: HashMap<String, Rc<Dominators>>,
+    loops: HashMap<String, Rc<LoopInfo>>,
+    liveness: HashMap<String, Rc<Liveness>>,
+}
+
+impl Analyses {
+    pub fn dominators(&mut self, function: &Function) -> Rc<Dominators> {
+        self.dominators
+            .entry(function.name.clone())
+            .or_insert_with(|| Rc::new(Dominators::compute(function)))
+            .clone()
+    }
+
+    pub fn loops(&mut self, function: &Function) -> Rc<LoopInfo> {
+        if let Some(loops) = self.loops.get(&function.name) {
+            return loops.clone();
+        }
+        let dominators = self.dominators(function);
+        let loops = Rc::new(LoopInfo::compute(function, &dominators));
+        self.loops.insert(function.name.clone(), loops.clone());
+        loops
+    }
+
+    pub fn liveness(&mut self, function: &Function) -> Rc<Liveness> {
+        self.liveness
+            .entry(function.name.clone())
+            .or_insert_with(|| Rc::new(Liveness::compute(function)))
+            .clone()
+    }
+
+    // Drops every analysis of every function that `preserved` does not
+    // cover.
+    pub fn invalidate(&mut self, preserved: Preserved) {
+        match preserved {
+            Preserved::All => {}
+            Preserved::Cfg => self.liveness.clear(),
+            Preserved::Nothing => *self = Analyses::default(),
+        }
+    }
+
+    // Like `invalidate`, for the analyses of one function.
+    pub fn invalidate_function(&mut self, name: &str, preserved: Preserved) {
+        if preserved == Preserved::All {
+            return;
+        }
+        self.liveness.remove(name);
+        if preserved == Preserved::Nothing {
+            self.dominators.remove(name);
+            self.loops.remove(name);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct PipelineOptions {
+    pub level: OptLevel,
+    // Check the IR after every pass.
+    pub verify_each: bool,
+    // Print the IR after each pass with one of these names.
+    pub print_after: Vec<String>,
+    // Print how long each pass took.
+    pub time_passes: bool,
+    // The only entry point to keep, if one was selected.
+    pub entry_point: Option<String>,
+}
+
+// A pass left the module invalid.
+#[derive(Debug)]
+pub struct PipelineError {
+    pub pass: String,
+    pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the IR is invalid after `{}`", self.pass)?;
+        for violation in &self.violations {
+            write!(f, "\n  {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+// Verifies the module `pass` produced.
+pub fn verify_after(pass: &str, module: &Module) -> Result<(), PipelineError> {
+    let violations = ir_verifier::verify(module);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(PipelineError {
+            pass: pass.to_string(),
+            violations,
+        })
+    }
+}
+
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    options: PipelineOptions,
+    // How long each pass took, in the order they ran.
+    timings: Vec<(&'static str, Duration)>,
+}
+
+impl PassManager {
+    // A pass manager running the pipeline `options` select.
+    pub fn new(options: PipelineOptions) -> Self {
+        PassManager {
+            passes: pipeline(&options),
+            options,
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, module: &mut Module) -> Result<(), PipelineError> {
+        let mut analyses = Analyses::default();
+        for pass in &mut self.passes {
+            let start = Instant::now();
+            let preserved = pass.run(module, &mut analyses);
+            self.timings.push((pass.name(), start.elapsed()));
+            analyses.invalidate(preserved);
+
+            if self
+                .options
+                .print_after
+                .iter()
+                .any(|name| name == pass.name())
+            {
+                println!("\nIR after {}:", pass.name());
+                print!("{}", ir_printer::print(module));
+            }
+            if self.options.verify_each {
+                verify_after(pass.name(), module)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The names given to `--print-after` that no pass in the pipeline has.
+    pub fn unknown_print_after(&self) -> Vec<&str> {
+        self.options
+            .print_after
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !self.passes.iter().any(|pass| pass.name() == *name))
+            .collect()
+    }
+
+    // A table of how long each pass took, and its share of the total.
+    pub fn timing_report(&self) -> String {
+        let total: Duration = self.timings.iter().map(|(_, time)| *time).sum();
+        let mut report = String::from("pass timings:\n");
+        for (name, time) in &self.timings {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            report.push_str(&format!(
+                "  {:<24} {:>10.3} ms {:>6.1}%\n",
+                name,
+                time.as_secs_f64() * 1000.0,
+                share
+            ));
+        }
+        report.push_str(&format!(
+            "  {:<24} {:>10.3} ms\n",
+            "total",
+            total.as_secs_f64() * 1000.0
+        ));
+        report
+    }
+}
@@ -0,0 +1,110 @@
+// src/reflection.rs
+// This file collects reflection data: what a runtime needs to know about a
+// compiled program to use it, without parsing the program itself. It
+// covers the entry points and the specialization constants with their
+// ids, types and defaults, and is written out as JSON.
+//
+// It is collected from the module the optimizer leaves, so it only lists
+// what the compiled program still has: the entry point `--entry` selects,
+// and the specialization constants it uses once unused ones are stripped.
+
+use std::fmt::Write;
+
+use crate::ast::{ShaderStage, Type};
+use crate::const_eval::ConstValue;
+use crate::ir::Module;
+
+pub struct Reflection {
+    pub entry_points: Vec<EntryPoint>,
+    // Sorted by id.
+    pub spec_constants: Vec<SpecConstantInfo>,
+}
+
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: ShaderStage,
+}
+
+pub struct SpecConstantInfo {
+    pub id: u32,
+    pub name: String,
+    pub type_name: Type,
+    pub default: ConstValue,
+}
+
+// Collects the reflection data of an optimized module.
+pub fn reflect(module: &Module) -> Reflection {
+    let entry_points = module
+        .functions
+        .iter()
+        .filter_map(|function| {
+            Some(EntryPoint {
+                name: function.name.clone(),
+                stage: function.stage?,
+            })
+        })
+        .collect();
+
+    let mut spec_constants: Vec<SpecConstantInfo> = module
+        .spec_constants
+        .iter()
+        .map(|constant| SpecConstantInfo {
+            id: constant.id,
+            name: constant.name.clone(),
+            type_name: constant.default.ty(),
+            default: constant.default,
+        })
+        .collect();
+    spec_constants.sort_by_key(|constant| constant.id);
+
+    Reflection {
+        entry_points,
+        spec_constants,
+    }
+}
+
+impl Reflection {
+    // Names are identifiers, so they never need escaping.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n  \"entry_points\": [");
+        for (i, entry_point) in self.entry_points.iter().enumerate() {
+            let stage = entry_point.stage.name();
+            let _ = write!(
+                out,
+                "{}\n    {{ \"name\": \"{}\", \"stage\": \"{}\" }}",
+                if i == 0 { "" } else { "," },
+                entry_point.name,
+                stage
+            );
+        }
+        out.push_str(if self.entry_points.is_empty() {
+            "],\n"
+        } else {
+            "\n  ],\n"
+        });
+
+        out.push_str("  \"specialization_constants\": [");
+        for (i, constant) in self.spec_constants.iter().enumerate() {
+            // JSON has no literal for infinities or NaN.
+            let default = match constant.default {
+                ConstValue::Float(value) if !value.is_finite() => "null".to_string(),
+                value => value.to_string(),
+            };
+            let _ = write!(
+                out,
+                "{}\n    {{ \"id\": {}, \"name\": \"{}\", \"type\": \"{}\", \"default\": {} }}",
+                if i == 0 { "" } else { "," },
+                constant.id,
+                constant.name,
+                constant.type_name,
+                default
+            );
+        }
+        out.push_str(if self.spec_constants.is_empty() {
+            "]\n}"
+        } else {
+            "\n  ]\n}"
+        });
+        out
+    }
+}
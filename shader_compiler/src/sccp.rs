@@ -0,0 +1,512 @@
+// src/sccp.rs
+// This file implements sparse conditional constant propagation (SCCP), an
+// optimization pass that finds the values that are the same constant every
+// time they are computed, replaces their instructions with that constant,
+// and turns branches on constant conditions into jumps.
+//
+// Every value starts out undefined, meaning no path computing it has been
+// seen yet, and each block unreached. Starting from the entry, the pass
+// evaluates the blocks found reachable, taking only the branches their
+// conditions allow, and lowers a value to constant or overdefined as it
+// learns more. A phi only meets the values flowing in along edges found
+// reachable, so `x` below is the constant 1, and the branch on it is
+// folded:
+//
+//     var int x = 1;
+//     if (x > 2) { x = 3; }
+//
+// Folding reproduces what the target computes, not what the host would:
+// `float` is 32-bit IEEE and `int` is 32-bit two's complement, though
+// constants hold them at 64 bits. None of the supported targets promises
+// IEEE behaviour for infinities, NaNs or subnormal floats (Vulkan and
+// WebGPU may flush subnormals to zero, and need not produce infinities),
+// so an operation is not folded if any of its operands or its result is
+// one. Nor is integer division by zero or overflowing division, which
+// SPIR-V leaves undefined. Specialization constants are not folded either:
+// their values are only known when the pipeline is created.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::Type;
+use crate::const_eval::ConstValue;
+use crate::ir::{
+    BinaryOp, BlockId, Function, InstKind, Instruction, IrType, Merge, Module, Terminator, Value,
+};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Sccp;
+
+impl Pass for Sccp {
+    fn name(&self) -> &'static str {
+        "sccp"
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            let lattice = Solver::solve(function);
+            preserved = preserved.weakest(rewrite(function, &lattice));
+        }
+        preserved
+    }
+}
+
+// What is known about a value. Each value only moves down the list.
+#[derive(Debug, PartialEq, Clone)]
+enum Lattice {
+    Undefined,
+    // The components of a scalar or vector constant.
+    Constant(Vec<ConstValue>),
+    Overdefined,
+}
+
+impl Lattice {
+    fn meet(&self, other: &Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undefined, other) | (other, Lattice::Undefined) => other.clone(),
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == b => self.clone(),
+            _ => Lattice::Overdefined,
+        }
+    }
+
+    fn scalar(&self) -> Option<ConstValue> {
+        match self {
+            Lattice::Constant(components) if components.len() == 1 => Some(components[0]),
+            _ => None,
+        }
+    }
+}
+
+// The result of solving a function: what is known about each value, and
+// which blocks can execute.
+struct Solution {
+    values: HashMap<Value, Lattice>,
+    blocks: HashSet<BlockId>,
+}
+
+struct Solver<'a> {
+    function: &'a Function,
+    values: HashMap<Value, Lattice>,
+    blocks: HashSet<BlockId>,
+    edges: HashSet<(BlockId, BlockId)>,
+}
+
+impl<'a> Solver<'a> {
+    fn solve(function: &'a Function) -> Solution {
+        let mut solver = Solver {
+            function,
+            values: HashMap::new(),
+            blocks: HashSet::new(),
+            edges: HashSet::new(),
+        };
+        for param in &function.params {
+            solver.values.insert(param.value, Lattice::Overdefined);
+        }
+        let order = function.reverse_postorder();
+        if let Some(&entry) = order.first() {
+            solver.blocks.insert(entry);
+        }
+        // Visiting the blocks in reverse postorder until nothing changes
+        // reaches the same fixpoint as a worklist would.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &id in &order {
+                if !solver.blocks.contains(&id) {
+                    continue;
+                }
+                let block = function.block(id).expect("ordered blocks exist");
+                for instruction in &block.instructions {
+                    let Some(result) = instruction.result else {
+                        continue;
+                    };
+                    let value = solver.evaluate(id, &instruction.kind, result);
+                    let old = solver.get(result);
+                    let new = old.meet(&value);
+                    if new != old {
+                        solver.values.insert(result, new);
+                        changed = true;
+                    }
+                }
+                for target in solver.taken_successors(&block.terminator) {
+                    if solver.edges.insert((id, target)) {
+                        solver.blocks.insert(target);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        Solution {
+            values: solver.values,
+            blocks: solver.blocks,
+        }
+    }
+
+    fn get(&self, value: Value) -> Lattice {
+        self.values
+            .get(&value)
+            .cloned()
+            .unwrap_or(Lattice::Undefined)
+    }
+
+    // The successors the terminator can branch to, given what is known
+    // about its operand so far.
+    fn taken_successors(&self, terminator: &Terminator) -> Vec<BlockId> {
+        match terminator {
+            Terminator::CondBranch {
+                condition,
+                if_true,
+                if_false,
+            } => match self.get(*condition) {
+                Lattice::Undefined => Vec::new(),
+                condition => match condition.scalar() {
+                    Some(ConstValue::Bool(true)) => vec![*if_true],
+                    Some(ConstValue::Bool(false)) => vec![*if_false],
+                    _ => vec![*if_true, *if_false],
+                },
+            },
+            Terminator::Switch { selector, .. } => match self.get(*selector) {
+                Lattice::Undefined => Vec::new(),
+                selector => match selector.scalar() {
+                    Some(ConstValue::Int(selector)) => vec![switch_target(terminator, selector)],
+                    _ => terminator.successors(),
+                },
+            },
+            terminator => terminator.successors(),
+        }
+    }
+
+    fn evaluate(&self, block: BlockId, kind: &InstKind, result: Value) -> Lattice {
+        let result_type = self.function.value_type(result);
+        match kind {
+            InstKind::Constant(constant) => Lattice::Constant(vec![*constant]),
+            InstKind::Phi(incoming) => incoming
+                .iter()
+                .filter(|&&(from, _)| self.edges.contains(&(from, block)))
+                .fold(Lattice::Undefined, |known, &(_, value)| {
+                    known.meet(&self.get(value))
+                }),
+            InstKind::Binary(op, left, right) => {
+                let (left, right) = (self.get(*left), self.get(*right));
+                // `false && x` and `true || x` need not know `x`.
+                let absorbing = ConstValue::Bool(*op == BinaryOp::Or);
+                if matches!(op, BinaryOp::And | BinaryOp::Or)
+                    && (left.scalar() == Some(absorbing) || right.scalar() == Some(absorbing))
+                {
+                    return Lattice::Constant(vec![absorbing]);
+                }
+                match (&left, &right) {
+                    (Lattice::Constant(left), Lattice::Constant(right)) => {
+                        fold(fold_binary(*op, left, right))
+                    }
+                    (Lattice::Overdefined, _) | (_, Lattice::Overdefined) => Lattice::Overdefined,
+                    _ => Lattice::Undefined,
+                }
+            }
+            InstKind::Convert(operand) => {
+                let element = match result_type {
+                    IrType::Value(ty) => ty.element(),
+                    IrType::Pointer(..) => None,
+                };
+                self.map(&[*operand], |components| {
+                    let element = element.as_ref()?;
+                    components
+                        .iter()
+                        .map(|&component| convert(element, component))
+                        .collect()
+                })
+            }
+            InstKind::Splat(operand) => {
+                let width = match result_type {
+                    IrType::Value(ty) => ty.width(),
+                    IrType::Pointer(..) => None,
+                };
+                self.map(&[*operand], |components| match components {
+                    [component] => Some(vec![*component; width? as usize]),
+                    _ => None,
+                })
+            }
+            InstKind::Construct(args) => self.map(args, |components| Some(components.to_vec())),
+            // Everything else depends on memory, the invocation or the
+            // pipeline, or is left to the target to compute.
+            _ => Lattice::Overdefined,
+        }
+    }
+
+    // Applies `f` to the components of the operands, concatenated, once
+    // they are all constant.
+    fn map(
+        &self,
+        operands: &[Value],
+        f: impl Fn(&[ConstValue]) -> Option<Vec<ConstValue>>,
+    ) -> Lattice {
+        let mut components = Vec::new();
+        for &operand in operands {
+            match self.get(operand) {
+                Lattice::Constant(constant) => components.extend(constant),
+                Lattice::Overdefined => return Lattice::Overdefined,
+                Lattice::Undefined => return Lattice::Undefined,
+            }
+        }
+        fold(f(&components))
+    }
+}
+
+fn fold(components: Option<Vec<ConstValue>>) -> Lattice {
+    match components {
+        Some(components) => Lattice::Constant(components),
+        None => Lattice::Overdefined,
+    }
+}
+
+// The block a switch on a constant selector jumps to.
+fn switch_target(switch: &Terminator, selector: i64) -> BlockId {
+    let Terminator::Switch { cases, default, .. } = switch else {
+        unreachable!("only called on a switch");
+    };
+    cases
+        .iter()
+        .find(|&&(label, _)| label == selector)
+        .map_or(*default, |&(_, target)| target)
+}
+
+// The float as the target holds it, if folding it is safe.
+fn to_f32(value: f64) -> Option<f32> {
+    let value = value as f32;
+    (value.is_finite() && (value == 0.0 || value.is_normal())).then_some(value)
+}
+
+fn from_f32(value: f32) -> Option<ConstValue> {
+    to_f32(f64::from(value)).map(|value| ConstValue::Float(f64::from(value)))
+}
+
+fn fold_binary(op: BinaryOp, left: &[ConstValue], right: &[ConstValue]) -> Option<Vec<ConstValue>> {
+    use ConstValue::{Bool, Float, Int};
+
+    if left.len() != right.len() {
+        return None;
+    }
+    let pairs = left.iter().copied().zip(right.iter().copied());
+    match op {
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => pairs
+            .map(|pair| match pair {
+                (Int(l), Int(r)) => {
+                    let (l, r) = (l as i32, r as i32);
+                    let value = match op {
+                        BinaryOp::Add => l.wrapping_add(r),
+                        BinaryOp::Subtract => l.wrapping_sub(r),
+                        BinaryOp::Multiply => l.wrapping_mul(r),
+                        _ => l.checked_div(r)?,
+                    };
+                    Some(Int(i64::from(value)))
+                }
+                (Float(l), Float(r)) => {
+                    let (l, r) = (to_f32(l)?, to_f32(r)?);
+                    from_f32(match op {
+                        BinaryOp::Add => l + r,
+                        BinaryOp::Subtract => l - r,
+                        BinaryOp::Multiply => l * r,
+                        _ => l / r,
+                    })
+                }
+                _ => None,
+            })
+            .collect(),
+        // Vectors are equal if every component is.
+        BinaryOp::Equal | BinaryOp::NotEqual => {
+            let mut equal = true;
+            for pair in pairs {
+                equal &= match pair {
+                    (Int(l), Int(r)) => l as i32 == r as i32,
+                    (Float(l), Float(r)) => to_f32(l)? == to_f32(r)?,
+                    (Bool(l), Bool(r)) => l == r,
+                    _ => return None,
+                };
+            }
+            Some(vec![Bool(equal == (op == BinaryOp::Equal))])
+        }
+        BinaryOp::LessThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::LessThanOrEqual
+        | BinaryOp::GreaterThanOrEqual => {
+            let ordering = match (left, right) {
+                ([Int(l)], [Int(r)]) => (*l as i32).cmp(&(*r as i32)),
+                ([Float(l)], [Float(r)]) => to_f32(*l)?.partial_cmp(&to_f32(*r)?)?,
+                _ => return None,
+            };
+            let value = match op {
+                BinaryOp::LessThan => ordering.is_lt(),
+                BinaryOp::GreaterThan => ordering.is_gt(),
+                BinaryOp::LessThanOrEqual => ordering.is_le(),
+                _ => ordering.is_ge(),
+            };
+            Some(vec![Bool(value)])
+        }
+        BinaryOp::And | BinaryOp::Or => pairs
+            .map(|pair| match pair {
+                (Bool(l), Bool(r)) => Some(Bool(if op == BinaryOp::And { l && r } else { l || r })),
+                _ => None,
+            })
+            .collect(),
+    }
+}
+
+// Converts a component to `element` as the target does. Float to int
+// conversion truncates toward zero, and is undefined out of range.
+fn convert(element: &Type, value: ConstValue) -> Option<ConstValue> {
+    use ConstValue::{Bool, Float, Int};
+
+    let value = match (element, value) {
+        (Type::Int, Int(v)) => Int(v),
+        (Type::Int, Float(v)) => {
+            let v = to_f32(v)?.trunc();
+            if !(-2147483648.0..2147483648.0).contains(&v) {
+                return None;
+            }
+            Int(i64::from(v as i32))
+        }
+        (Type::Int, Bool(v)) => Int(i64::from(v)),
+        (Type::Float, Int(v)) => from_f32(v as i32 as f32)?,
+        (Type::Float, Float(v)) => Float(v),
+        (Type::Float, Bool(v)) => Float(if v { 1.0 } else { 0.0 }),
+        (Type::Bool, Int(v)) => Bool(v as i32 != 0),
+        (Type::Bool, Float(v)) => Bool(to_f32(v)? != 0.0),
+        (Type::Bool, Bool(v)) => Bool(v),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// Replaces the instructions of constant values with their constants and
+// folds branches on constant conditions, returning what was preserved.
+fn rewrite(function: &mut Function, solution: &Solution) -> Preserved {
+    let mut preserved = Preserved::All;
+    // A splat or construct of scalars that are constant, and so are or
+    // will be written as constants, is already folded.
+    let scalar_constant = |value: &Value| {
+        solution
+            .values
+            .get(value)
+            .is_some_and(|known| known.scalar().is_some())
+    };
+
+    let mut blocks = std::mem::take(&mut function.blocks);
+    // The phi inputs to drop, as (block, predecessor), for edges that no
+    // longer exist.
+    let mut removed_edges = Vec::new();
+    for block in &mut blocks {
+        if !solution.blocks.contains(&block.id) {
+            continue;
+        }
+        let instructions = std::mem::take(&mut block.instructions);
+        let mut phis = Vec::new();
+        let mut folded_phis = Vec::new();
+        let mut rest = Vec::new();
+        for instruction in instructions {
+            let components = match instruction.result.and_then(|r| solution.values.get(&r)) {
+                Some(Lattice::Constant(components)) => components,
+                _ => {
+                    match instruction.kind {
+                        InstKind::Phi(_) => phis.push(instruction),
+                        _ => rest.push(instruction),
+                    }
+                    continue;
+                }
+            };
+            let folded = match &instruction.kind {
+                InstKind::Constant(_) => true,
+                InstKind::Splat(arg) => scalar_constant(arg),
+                InstKind::Construct(args) => args.iter().all(scalar_constant),
+                _ => false,
+            };
+            if folded {
+                rest.push(instruction);
+                continue;
+            }
+            preserved = Preserved::Cfg;
+            let result = instruction.result.expect("constant values have results");
+            let materialized = materialize(function, result, components);
+            match instruction.kind {
+                // Constants may not come between phis.
+                InstKind::Phi(_) => folded_phis.extend(materialized),
+                _ => rest.extend(materialized),
+            }
+        }
+        phis.append(&mut folded_phis);
+        phis.append(&mut rest);
+        block.instructions = phis;
+
+        let taken = match &block.terminator {
+            Terminator::CondBranch {
+                condition,
+                if_true,
+                if_false,
+            } => match solution.values.get(condition).and_then(Lattice::scalar) {
+                Some(ConstValue::Bool(true)) => Some(*if_true),
+                Some(ConstValue::Bool(false)) => Some(*if_false),
+                _ => None,
+            },
+            Terminator::Switch { selector, .. } => {
+                match solution.values.get(selector).and_then(Lattice::scalar) {
+                    Some(ConstValue::Int(selector)) => {
+                        Some(switch_target(&block.terminator, selector))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(taken) = taken {
+            preserved = Preserved::Nothing;
+            for successor in block.terminator.successors() {
+                if successor != taken {
+                    removed_edges.push((successor, block.id));
+                }
+            }
+            block.terminator = Terminator::Branch(taken);
+            // A jump needs no merge; a loop header keeps its own.
+            if let Some(Merge::Selection(_)) = block.merge {
+                block.merge = None;
+            }
+        }
+    }
+    for (id, predecessor) in removed_edges {
+        let Some(block) = blocks.iter_mut().find(|block| block.id == id) else {
+            continue;
+        };
+        for instruction in &mut block.instructions {
+            if let InstKind::Phi(incoming) = &mut instruction.kind {
+                incoming.retain(|&(from, _)| from != predecessor);
+            }
+        }
+    }
+    function.blocks = blocks;
+    preserved
+}
+
+// The instructions defining `result` as the constant `components`.
+fn materialize(
+    function: &mut Function,
+    result: Value,
+    components: &[ConstValue],
+) -> Vec<Instruction> {
+    if let [component] = components {
+        return vec![Instruction {
+            result: Some(result),
+            kind: InstKind::Constant(*component),
+        }];
+    }
+    let mut instructions: Vec<Instruction> = components
+        .iter()
+        .map(|&component| Instruction {
+            result: Some(function.new_value(IrType::Value(component.ty()))),
+            kind: InstKind::Constant(component),
+        })
+        .collect();
+    let args = instructions.iter().filter_map(|i| i.result).collect();
+    instructions.push(Instruction {
+        result: Some(result),
+        kind: InstKind::Construct(args),
+    });
+    instructions
+}
@@ -0,0 +1,2065 @@
+// src/semantic.rs
+// This file implements semantic analysis: the validation layer that runs
+// over the AST after parsing. It checks that every variable and function
+// is declared before use, that types are compatible, and that parameter
+// qualifiers are respected.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::{
+    BinaryOp, CaseLabel, EnumDefinition, Expression, FunctionDefinition, MatchArm, ModuleInfo,
+    ParameterQualifier, PatternKind, ShaderStage, Span, SpecConstant, Statement, SwitchCase,
+    TranslationUnit, Type, TypeBound, WorkgroupVariable,
+};
+use crate::call_graph::CallGraph;
+use crate::const_eval;
+use crate::definite_assignment;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::exhaustiveness::{self, Domain};
+use crate::intrinsics::{BuiltIn, Intrinsic};
+use crate::target::{Capability, Target};
+use crate::uniformity;
+
+// How a declaration that hides one from an enclosing scope is reported.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ShadowingPolicy {
+    Allow,
+    #[default]
+    Warn,
+    Deny,
+}
+
+impl ShadowingPolicy {
+    pub const ALL: [ShadowingPolicy; 3] = [
+        ShadowingPolicy::Allow,
+        ShadowingPolicy::Warn,
+        ShadowingPolicy::Deny,
+    ];
+
+    // The policy named by `--shadowing=<name>`.
+    pub fn from_name(name: &str) -> Option<ShadowingPolicy> {
+        ShadowingPolicy::ALL
+            .into_iter()
+            .find(|policy| policy.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShadowingPolicy::Allow => "allow",
+            ShadowingPolicy::Warn => "warn",
+            ShadowingPolicy::Deny => "deny",
+        }
+    }
+}
+
+// Settings that change which programs semantic analysis accepts.
+#[derive(Debug, Default)]
+pub struct AnalysisOptions {
+    pub shadowing: ShadowingPolicy,
+    // Intrinsics that need a capability the target lacks are rejected.
+    pub target: Target,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SemanticError {
+    UndeclaredVariable(String),
+    Redeclaration(String),
+    Shadowing(String),
+    UndefinedFunction(String),
+    DuplicateFunction(String),
+    IntrinsicRedefinition(String),
+    // A definition that is not `pub` is used from another module.
+    PrivateItem {
+        kind: &'static str,
+        name: String,
+        module: String,
+    },
+    // A definition is used from a module that does not import the module
+    // defining it.
+    NotImported {
+        kind: &'static str,
+        name: String,
+        module: String,
+    },
+    // An intrinsic needs a capability the target does not have.
+    MissingCapability {
+        intrinsic: Intrinsic,
+        capability: Capability,
+        target: Target,
+    },
+    // The invocation id of `subgroup_broadcast` must be known when
+    // compiling.
+    NonConstantBroadcastId,
+    // No overload of an intrinsic takes the argument types supplied.
+    NoMatchingOverload {
+        intrinsic: Intrinsic,
+        args: Vec<Type>,
+    },
+    InvalidConstructor {
+        type_name: Type,
+        args: Vec<Type>,
+    },
+    TypeMismatch {
+        expected: Type,
+        found: Type,
+    },
+    InvalidOperands {
+        op: BinaryOp,
+        left: Type,
+        right: Type,
+    },
+    VoidVariable(String),
+    // Specialization constants must be a scalar, with a compile-time
+    // constant default of that type.
+    SpecConstantType {
+        name: String,
+        found: Type,
+    },
+    NonConstantSpecDefault(String),
+    DuplicateSpecId(u32),
+    DuplicateConstant(String),
+    // A workgroup variable whose name another global already has.
+    DuplicateGlobal(String),
+    UnknownType(String),
+    // Atomics hold an int; there is no unsigned integer type yet.
+    UnsupportedAtomic(Type),
+    // An atomic type anywhere but a workgroup variable, the only memory
+    // shared between invocations.
+    MisplacedAtomic(Type),
+    // An atomic variable read or assigned directly.
+    AtomicAccess(String),
+    // The first argument of an atomic intrinsic is not an atomic variable.
+    NonAtomicOperand(Intrinsic),
+    DuplicateType(String),
+    DuplicateVariant {
+        enum_name: String,
+        variant: String,
+    },
+    UnknownVariant {
+        enum_name: String,
+        variant: String,
+    },
+    GenericEntryPoint(String),
+    // Entry points are called by the pipeline, never inlined.
+    InlineEntryPoint(String),
+    // A type parameter that no parameter type mentions, so a call can
+    // never determine it.
+    UninferableTypeParameter {
+        function: String,
+        parameter: String,
+    },
+    // The arguments of a generic call bind a type parameter to two
+    // different types.
+    ConflictingTypeArgument {
+        function: String,
+        parameter: String,
+        first: Type,
+        second: Type,
+    },
+    UnsatisfiedBound {
+        function: String,
+        parameter: String,
+        found: Type,
+        bound: TypeBound,
+    },
+    ArgumentCount {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    // An `out` or `inout` argument was not something that can be written to.
+    NonLvalueArgument {
+        function: String,
+        parameter: String,
+    },
+    // An `out` parameter can reach a return without having been written.
+    OutParameterNotWritten {
+        function: String,
+        parameter: String,
+    },
+    // A function returning a value can reach the end of its body.
+    MissingReturn {
+        function: String,
+        return_type: Type,
+    },
+    // A variable can be read on some path before it has been assigned.
+    PossiblyUninitialized(String),
+    // An assignment, or an `out`/`inout` argument, writes to a binding that
+    // was not declared mutable.
+    ImmutableAssignment(String),
+    NonIntegerSelector(Type),
+    NonConstantCaseLabel,
+    // A case label's value does not fit in the 32-bit selector, so no
+    // selector could ever match it.
+    CaseLabelOutOfRange(i64),
+    DuplicateCaseLabel(i64),
+    DuplicateDefault,
+    // Only bools, ints and enums can be matched on.
+    UnmatchableSelector(Type),
+    EmptyRange {
+        start: i32,
+        end: i32,
+    },
+    // Lists the values no arm handles.
+    NonExhaustiveMatch(String),
+    UnreachablePattern,
+    BreakOutsideSwitch,
+    // `fallthrough` must be the last statement of a case that has a
+    // following case to fall into.
+    MisplacedFallthrough,
+    // `discard` or `demote` in a function that a non-fragment entry point
+    // can call.
+    FragmentOnlyStatement {
+        function: String,
+        entry_point: String,
+        stage: ShaderStage,
+    },
+    // A function that calls itself, directly or through the functions in
+    // `through`. Shaders run without a call stack, so they cannot recurse.
+    RecursiveCall {
+        function: String,
+        through: Vec<String>,
+    },
+    // A workgroup variable, compute built-in or workgroup barrier, named by
+    // `item`, in a function that a non-compute entry point can call.
+    ComputeOnly {
+        item: String,
+        function: String,
+        entry_point: String,
+        stage: ShaderStage,
+    },
+    // A barrier, or a call to a function that reaches one, where not every
+    // invocation of the workgroup may get to it.
+    NonUniformBarrier(String),
+    NonUniformCall(String),
+    // An argument that can differ between invocations, for a parameter
+    // that decides whether the callee reaches a barrier.
+    NonUniformArgument {
+        function: String,
+        parameter: String,
+    },
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::UndeclaredVariable(name) => {
+                write!(f, "use of undeclared variable `{}`", name)
+            }
+            SemanticError::Redeclaration(name) => {
+                write!(f, "variable `{}` is already declared in this scope", name)
+            }
+            SemanticError::Shadowing(name) => {
+                write!(
+                    f,
+                    "declaration of `{}` shadows an earlier declaration",
+                    name
+                )
+            }
+            SemanticError::UndefinedFunction(name) => {
+                write!(f, "call to undefined function `{}`", name)
+            }
+            SemanticError::DuplicateFunction(name) => {
+                write!(f, "function `{}` is defined more than once", name)
+            }
+            SemanticError::PrivateItem { kind, name, module } => {
+                write!(f, "{} `{}` is private to `{}`", kind, name, module)
+            }
+            SemanticError::NotImported { kind, name, module } => write!(
+                f,
+                "{} `{}` is defined in `{}`, which is not imported here",
+                kind, name, module
+            ),
+            SemanticError::IntrinsicRedefinition(name) => {
+                write!(f, "function `{}` redefines a built-in function", name)
+            }
+            SemanticError::MissingCapability {
+                intrinsic,
+                capability,
+                target,
+            } => write!(
+                f,
+                "`{}` requires {}, which target `{}` does not support",
+                intrinsic.name(),
+                capability,
+                target.name()
+            ),
+            SemanticError::NonConstantBroadcastId => write!(
+                f,
+                "invocation id of `subgroup_broadcast` must be a constant integer expression"
+            ),
+            SemanticError::NoMatchingOverload { intrinsic, args } => write!(
+                f,
+                "no overload of `{}` accepts arguments of type ({})",
+                intrinsic.name(),
+                type_list(args)
+            ),
+            SemanticError::InvalidConstructor { type_name, args } => write!(
+                f,
+                "cannot construct {} from arguments of type ({})",
+                type_name,
+                type_list(args)
+            ),
+            SemanticError::TypeMismatch { expected, found } => {
+                write!(f, "type mismatch: expected {}, found {}", expected, found)
+            }
+            SemanticError::InvalidOperands { op, left, right } => write!(
+                f,
+                "operator `{}` cannot be applied to {} and {}",
+                op, left, right
+            ),
+            SemanticError::VoidVariable(name) => {
+                write!(f, "variable `{}` cannot have type void", name)
+            }
+            SemanticError::SpecConstantType { name, found } => write!(
+                f,
+                "specialization constant `{}` must be a bool, int or float, found {}",
+                name, found
+            ),
+            SemanticError::NonConstantSpecDefault(name) => write!(
+                f,
+                "default of specialization constant `{}` must be a compile-time constant",
+                name
+            ),
+            SemanticError::DuplicateSpecId(id) => {
+                write!(f, "specialization id {} is used more than once", id)
+            }
+            SemanticError::DuplicateConstant(name) => {
+                write!(f, "constant `{}` is defined more than once", name)
+            }
+            SemanticError::DuplicateGlobal(name) => {
+                write!(f, "global `{}` is defined more than once", name)
+            }
+            SemanticError::UnknownType(name) => write!(f, "unknown type `{}`", name),
+            SemanticError::UnsupportedAtomic(ty) => {
+                write!(f, "{} is not supported, only atomic<int> is", ty)
+            }
+            SemanticError::MisplacedAtomic(ty) => {
+                write!(
+                    f,
+                    "{} is only allowed as the type of a workgroup variable",
+                    ty
+                )
+            }
+            SemanticError::AtomicAccess(name) => write!(
+                f,
+                "atomic variable `{}` can only be accessed through atomic intrinsics",
+                name
+            ),
+            SemanticError::NonAtomicOperand(intrinsic) => write!(
+                f,
+                "first argument of `{}` must be an atomic variable",
+                intrinsic.name()
+            ),
+            SemanticError::DuplicateType(name) => {
+                write!(f, "type `{}` is defined more than once", name)
+            }
+            SemanticError::DuplicateVariant { enum_name, variant } => {
+                write!(
+                    f,
+                    "enum `{}` has more than one variant named `{}`",
+                    enum_name, variant
+                )
+            }
+            SemanticError::UnknownVariant { enum_name, variant } => {
+                write!(f, "enum `{}` has no variant `{}`", enum_name, variant)
+            }
+            SemanticError::GenericEntryPoint(name) => {
+                write!(f, "entry point `{}` cannot be generic", name)
+            }
+            SemanticError::InlineEntryPoint(name) => {
+                write!(f, "entry point `{}` cannot have an @inline attribute", name)
+            }
+            SemanticError::UninferableTypeParameter {
+                function,
+                parameter,
+            } => write!(
+                f,
+                "type parameter `{}` of `{}` is not used by any parameter, so it cannot be inferred",
+                parameter, function
+            ),
+            SemanticError::ConflictingTypeArgument {
+                function,
+                parameter,
+                first,
+                second,
+            } => write!(
+                f,
+                "type parameter `{}` of `{}` cannot be both {} and {}",
+                parameter, function, first, second
+            ),
+            SemanticError::UnsatisfiedBound {
+                function,
+                parameter,
+                found,
+                bound,
+            } => write!(
+                f,
+                "{} does not satisfy the bound `{:?}` of type parameter `{}` of `{}`",
+                found, bound, parameter, function
+            ),
+            SemanticError::ArgumentCount {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "function `{}` takes {} argument(s) but {} were supplied",
+                function, expected, found
+            ),
+            SemanticError::NonLvalueArgument {
+                function,
+                parameter,
+            } => write!(
+                f,
+                "argument for `out`/`inout` parameter `{}` of `{}` must be a variable",
+                parameter, function
+            ),
+            SemanticError::MissingReturn {
+                function,
+                return_type,
+            } => write!(
+                f,
+                "`{}` returns {}, but can reach its end without returning a value",
+                function, return_type
+            ),
+            SemanticError::OutParameterNotWritten {
+                function,
+                parameter,
+            } => write!(
+                f,
+                "`out` parameter `{}` of `{}` is not written on every path before returning",
+                parameter, function
+            ),
+            SemanticError::PossiblyUninitialized(name) => {
+                write!(f, "variable `{}` is possibly uninitialized here", name)
+            }
+            SemanticError::ImmutableAssignment(name) => {
+                write!(f, "cannot assign to immutable variable `{}`", name)
+            }
+            SemanticError::NonIntegerSelector(found) => {
+                write!(f, "switch selector must be int, found {}", found)
+            }
+            SemanticError::NonConstantCaseLabel => {
+                write!(f, "case label must be a constant integer expression")
+            }
+            SemanticError::CaseLabelOutOfRange(value) => {
+                write!(f, "case label `{}` is outside the range of int", value)
+            }
+            SemanticError::DuplicateCaseLabel(value) => {
+                write!(f, "duplicate case label `{}`", value)
+            }
+            SemanticError::DuplicateDefault => {
+                write!(f, "switch has more than one `default` label")
+            }
+            SemanticError::UnmatchableSelector(found) => {
+                write!(f, "cannot match on a value of type {}", found)
+            }
+            SemanticError::EmptyRange { start, end } => {
+                write!(f, "range pattern `{}..={}` matches no values", start, end)
+            }
+            SemanticError::NonExhaustiveMatch(missing) => {
+                write!(
+                    f,
+                    "match does not cover every value: {} not covered",
+                    missing
+                )
+            }
+            SemanticError::UnreachablePattern => write!(f, "unreachable pattern"),
+            SemanticError::BreakOutsideSwitch => write!(f, "`break` outside of a switch"),
+            SemanticError::MisplacedFallthrough => write!(
+                f,
+                "`fallthrough` must be the last statement of a case followed by another case"
+            ),
+            SemanticError::FragmentOnlyStatement {
+                function,
+                entry_point,
+                stage,
+            } => write!(
+                f,
+                "`discard`/`demote` in `{}` is reachable from {} entry point `{}`, but is only allowed in fragment shaders",
+                function,
+                stage.name(),
+                entry_point
+            ),
+            SemanticError::RecursiveCall { function, through } if through.is_empty() => {
+                write!(f, "`{}` calls itself, but shaders cannot recurse", function)
+            }
+            SemanticError::RecursiveCall { function, through } => write!(
+                f,
+                "`{}` calls itself through `{}`, but shaders cannot recurse",
+                function,
+                through.join("` -> `")
+            ),
+            SemanticError::ComputeOnly {
+                item,
+                function,
+                entry_point,
+                stage,
+            } => write!(
+                f,
+                "`{}` in `{}` is reachable from {} entry point `{}`, but is only available in compute shaders",
+                item,
+                function,
+                stage.name(),
+                entry_point
+            ),
+            SemanticError::NonUniformBarrier(name) => {
+                write!(f, "`{}` must be called in uniform control flow", name)
+            }
+            SemanticError::NonUniformCall(function) => write!(
+                f,
+                "`{}` reaches a barrier, so it must be called in uniform control flow",
+                function
+            ),
+            SemanticError::NonUniformArgument {
+                function,
+                parameter,
+            } => write!(
+                f,
+                "argument for `{}` of `{}` must be uniform, since it decides whether a barrier is reached",
+                parameter, function
+            ),
+        }
+    }
+}
+
+// Runs every semantic check over the translation unit and collects all
+// diagnostics found, rather than stopping at the first error. The program
+// is valid if none of them is an error.
+//
+// What analysis resolves is written back into the AST: the inferred type
+// of each declaration written without one, and the type arguments of each
+// call to a generic function. Later stages, such as monomorphization and
+// `--emit typed-ast`, rely on both.
+pub fn analyze(unit: &mut TranslationUnit, options: &AnalysisOptions) -> Vec<Diagnostic> {
+    let mut analyzer = SemanticAnalyzer::new(options);
+    analyzer.analyze_unit(unit);
+    let SemanticAnalyzer {
+        diagnostics,
+        resolved,
+        ..
+    } = analyzer;
+
+    for function in &mut unit.functions {
+        resolved.fill_statements(&mut function.body);
+    }
+    diagnostics
+}
+
+// Types resolved during analysis, keyed by the span of the name they
+// belong to.
+#[derive(Default)]
+struct Resolved {
+    declaration_types: HashMap<Span, Type>,
+    type_args: HashMap<Span, Vec<Type>>,
+}
+
+impl Resolved {
+    fn fill_statements(&self, stmts: &mut [Statement]) {
+        for stmt in stmts {
+            match stmt {
+                Statement::Declaration {
+                    type_name,
+                    initializer,
+                    span,
+                    ..
+                } => {
+                    if type_name.is_none() {
+                        *type_name = self.declaration_types.get(span).cloned();
+                    }
+                    if let Some(initializer) = initializer {
+                        self.fill_expression(initializer);
+                    }
+                }
+                Statement::Block(stmts) => self.fill_statements(stmts),
+                Statement::If {
+                    condition,
+                    if_block,
+                    else_block,
+                    ..
+                }
+                | Statement::StaticIf {
+                    condition,
+                    if_block,
+                    else_block,
+                    ..
+                } => {
+                    self.fill_expression(condition);
+                    self.fill_statements(if_block);
+                    if let Some(else_block) = else_block {
+                        self.fill_statements(else_block);
+                    }
+                }
+                Statement::Switch {
+                    selector, cases, ..
+                } => {
+                    self.fill_expression(selector);
+                    for case in cases {
+                        self.fill_statements(&mut case.body);
+                    }
+                }
+                Statement::Match { selector, arms, .. } => {
+                    self.fill_expression(selector);
+                    for arm in arms {
+                        self.fill_statements(&mut arm.body);
+                    }
+                }
+                Statement::Return {
+                    value: Some(value), ..
+                }
+                | Statement::Expression(value) => self.fill_expression(value),
+                Statement::Return { value: None, .. }
+                | Statement::Break { .. }
+                | Statement::Fallthrough { .. }
+                | Statement::Discard { .. }
+                | Statement::Demote { .. } => {}
+            }
+        }
+    }
+
+    fn fill_expression(&self, expr: &mut Expression) {
+        match expr {
+            Expression::Call {
+                args,
+                span,
+                type_args,
+                ..
+            } => {
+                if let Some(resolved) = self.type_args.get(span) {
+                    *type_args = resolved.clone();
+                }
+                for arg in args {
+                    self.fill_expression(arg);
+                }
+            }
+            Expression::Constructor { args, .. } => {
+                for arg in args {
+                    self.fill_expression(arg);
+                }
+            }
+            Expression::BinaryOp { left, right, .. } => {
+                self.fill_expression(left);
+                self.fill_expression(right);
+            }
+            Expression::Assignment { value, .. } => self.fill_expression(value),
+            Expression::Identifier { .. }
+            | Expression::EnumVariant { .. }
+            | Expression::IntLiteral { .. }
+            | Expression::FloatLiteral { .. }
+            | Expression::BoolLiteral { .. } => {}
+        }
+    }
+}
+
+struct Symbol {
+    // `None` if the type could not be inferred because the initializer
+    // had an error. Uses of the variable are then not checked further.
+    ty: Option<Type>,
+    span: Span,
+    mutable: bool,
+    is_parameter: bool,
+    // An immutable local declared without a value. Definite-assignment
+    // analysis checks that it is assigned at most once on each path.
+    deferred: bool,
+}
+
+struct SemanticAnalyzer<'a> {
+    options: &'a AnalysisOptions,
+    functions: HashMap<&'a str, &'a FunctionDefinition>,
+    enums: HashMap<&'a str, &'a EnumDefinition>,
+    spec_constants: HashMap<&'a str, &'a SpecConstant>,
+    workgroup_variables: HashMap<&'a str, &'a WorkgroupVariable>,
+    modules: &'a [ModuleInfo],
+    // The module of the function being checked.
+    module: usize,
+    // The name of the function being checked.
+    function: &'a str,
+    // The first use in each function of something only compute shaders
+    // have, with a description of it.
+    compute_only: HashMap<&'a str, (String, Span)>,
+    // Variables visible at the statement being checked, one map per
+    // lexical scope from outermost to innermost.
+    scopes: Vec<HashMap<String, Symbol>>,
+    // Number of switches enclosing the statement being checked.
+    switch_depth: usize,
+    diagnostics: Vec<Diagnostic>,
+    resolved: Resolved,
+    // The type parameters of the function being checked, and their bounds.
+    generics: HashMap<String, TypeBound>,
+}
+
+impl<'a> SemanticAnalyzer<'a> {
+    fn new(options: &'a AnalysisOptions) -> Self {
+        SemanticAnalyzer {
+            options,
+            functions: HashMap::new(),
+            enums: HashMap::new(),
+            spec_constants: HashMap::new(),
+            workgroup_variables: HashMap::new(),
+            modules: &[],
+            module: 0,
+            function: "",
+            compute_only: HashMap::new(),
+            scopes: Vec::new(),
+            switch_depth: 0,
+            diagnostics: Vec::new(),
+            resolved: Resolved::default(),
+            generics: HashMap::new(),
+        }
+    }
+
+    fn error(&mut self, error: SemanticError, span: Span) {
+        self.diagnostics
+            .push(Diagnostic::new(Severity::Error, error.to_string()).with_span(span));
+    }
+
+    fn analyze_unit(&mut self, unit: &'a TranslationUnit) {
+        self.modules = &unit.modules;
+        // Collect types and signatures first so functions can use them
+        // regardless of the order they are defined in. All modules share
+        // one namespace, other than for the private items that
+        // `namespaces` renamed.
+        let mut ids = HashMap::new();
+        for constant in &unit.spec_constants {
+            self.declare_spec_constant(constant, &mut ids);
+        }
+        for definition in &unit.enums {
+            self.declare_enum(definition);
+        }
+        for variable in &unit.workgroup_variables {
+            self.declare_workgroup_variable(variable);
+        }
+        for function in &unit.functions {
+            if Intrinsic::from_name(&function.name).is_some() {
+                let diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    SemanticError::IntrinsicRedefinition(function.name.clone()).to_string(),
+                )
+                .with_span(function.span);
+                self.diagnostics.push(diagnostic);
+            }
+            // Later definitions are reported and ignored, so calls resolve
+            // to the first.
+            if let Some(previous) = self.functions.get(function.name.as_str()) {
+                let diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    SemanticError::DuplicateFunction(function.name.clone()).to_string(),
+                )
+                .with_span(function.span)
+                .with_note(
+                    format!("`{}` is first defined here", function.name),
+                    Some(previous.span),
+                );
+                self.diagnostics.push(diagnostic);
+            } else {
+                self.functions.insert(function.name.as_str(), function);
+            }
+        }
+
+        for function in &unit.functions {
+            self.analyze_function(function);
+        }
+
+        let call_graph = CallGraph::build(unit);
+        self.check_recursion(unit, &call_graph);
+        self.check_fragment_only_statements(unit, &call_graph);
+        self.check_compute_only_uses(unit, &call_graph);
+        uniformity::check(unit, &mut self.diagnostics);
+    }
+
+    // `ids` maps each specialization id seen so far to where it was used.
+    fn declare_spec_constant(&mut self, constant: &'a SpecConstant, ids: &mut HashMap<u32, Span>) {
+        let name = &constant.name;
+        if !matches!(constant.type_name, Type::Bool | Type::Int | Type::Float) {
+            let error = SemanticError::SpecConstantType {
+                name: name.clone(),
+                found: constant.type_name.clone(),
+            };
+            let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                .with_span(constant.span)
+                .with_help("the runtime can only specialize scalar constants");
+            self.diagnostics.push(diagnostic);
+        } else {
+            match const_eval::evaluate(&constant.default) {
+                Some(value) if value.ty() != constant.type_name => {
+                    let error = SemanticError::TypeMismatch {
+                        expected: constant.type_name.clone(),
+                        found: value.ty(),
+                    };
+                    let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                        .with_span(constant.span);
+                    self.diagnostics.push(diagnostic);
+                }
+                Some(_) => {}
+                None => {
+                    let diagnostic = Diagnostic::new(
+                        Severity::Error,
+                        SemanticError::NonConstantSpecDefault(name.clone()).to_string(),
+                    )
+                    .with_span(constant.span);
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+        }
+
+        if let Some(&previous) = ids.get(&constant.id) {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::DuplicateSpecId(constant.id).to_string(),
+            )
+            .with_span(constant.id_span)
+            .with_note("it is first used here", Some(previous));
+            self.diagnostics.push(diagnostic);
+        } else {
+            ids.insert(constant.id, constant.id_span);
+        }
+
+        if let Some(previous) = self.spec_constants.get(name.as_str()) {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::DuplicateConstant(name.clone()).to_string(),
+            )
+            .with_span(constant.span)
+            .with_note(
+                format!("`{}` is first defined here", name),
+                Some(previous.span),
+            );
+            self.diagnostics.push(diagnostic);
+        } else {
+            self.spec_constants.insert(name, constant);
+        }
+    }
+
+    fn declare_workgroup_variable(&mut self, variable: &'a WorkgroupVariable) {
+        let name = &variable.name;
+        self.module = variable.module;
+        self.check_type(&variable.type_name, variable.span);
+        if variable.type_name == Type::Void {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::VoidVariable(name.clone()).to_string(),
+            )
+            .with_span(variable.span);
+            self.diagnostics.push(diagnostic);
+        }
+
+        let previous = match self.spec_constants.get(name.as_str()) {
+            Some(constant) => Some(constant.span),
+            None => self
+                .workgroup_variables
+                .get(name.as_str())
+                .map(|previous| previous.span),
+        };
+        if let Some(previous) = previous {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::DuplicateGlobal(name.clone()).to_string(),
+            )
+            .with_span(variable.span)
+            .with_note(format!("`{}` is first defined here", name), Some(previous));
+            self.diagnostics.push(diagnostic);
+        } else {
+            self.workgroup_variables.insert(name, variable);
+        }
+    }
+
+    fn declare_enum(&mut self, definition: &'a EnumDefinition) {
+        // Later definitions are reported and ignored, so uses resolve to the
+        // first.
+        if let Some(previous) = self.enums.get(definition.name.as_str()) {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::DuplicateType(definition.name.clone()).to_string(),
+            )
+            .with_span(definition.span)
+            .with_note(
+                format!("`{}` is first defined here", definition.name),
+                Some(previous.span),
+            );
+            self.diagnostics.push(diagnostic);
+        } else {
+            self.enums.insert(definition.name.as_str(), definition);
+        }
+
+        let mut seen: HashMap<&str, Span> = HashMap::new();
+        for variant in &definition.variants {
+            if let Some(previous) = seen.insert(&variant.name, variant.span) {
+                let error = SemanticError::DuplicateVariant {
+                    enum_name: definition.name.clone(),
+                    variant: variant.name.clone(),
+                };
+                let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                    .with_span(variant.span)
+                    .with_note(
+                        format!("`{}` is first declared here", variant.name),
+                        Some(previous),
+                    );
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // Fragment-only statements may appear in helpers, as long as no
+    // entry point of another stage can reach them through the call graph.
+    fn check_fragment_only_statements(
+        &mut self,
+        unit: &'a TranslationUnit,
+        call_graph: &CallGraph<'a>,
+    ) {
+        for entry_point in &unit.functions {
+            let Some(stage) = entry_point.stage else {
+                continue;
+            };
+            if stage == ShaderStage::Fragment {
+                continue;
+            }
+
+            let reached = call_graph.reachable_from(&entry_point.name);
+            for function in &unit.functions {
+                if !reached.contains(function.name.as_str()) {
+                    continue;
+                }
+                let Some(span) = first_fragment_only_statement(&function.body) else {
+                    continue;
+                };
+                let error = SemanticError::FragmentOnlyStatement {
+                    function: function.name.clone(),
+                    entry_point: entry_point.name.clone(),
+                    stage,
+                };
+                let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                    .with_span(span)
+                    .with_note(
+                        format!("`{}` is declared here", entry_point.name),
+                        Some(entry_point.span),
+                    );
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // Reports each cycle of calls once, at the call that starts it in the
+    // function of the cycle defined first.
+    fn check_recursion(&mut self, unit: &'a TranslationUnit, call_graph: &CallGraph<'a>) {
+        let mut reported = HashSet::new();
+        for function in &unit.functions {
+            if reported.contains(function.name.as_str()) {
+                continue;
+            }
+            let Some(cycle) = call_graph.cycle_through(&function.name) else {
+                continue;
+            };
+            reported.extend(cycle.iter().copied());
+            let through = &cycle[1..cycle.len() - 1];
+            let error = SemanticError::RecursiveCall {
+                function: function.name.clone(),
+                through: through.iter().map(|name| name.to_string()).collect(),
+            };
+            let mut diagnostic = Diagnostic::new(Severity::Error, error.to_string());
+            if let Some(span) = call_graph.call_site(cycle[0], cycle[1]) {
+                diagnostic = diagnostic.with_span(span);
+            }
+            for pair in cycle[1..].windows(2) {
+                diagnostic = diagnostic.with_note(
+                    format!("`{}` calls `{}` here", pair[0], pair[1]),
+                    call_graph.call_site(pair[0], pair[1]),
+                );
+            }
+            self.diagnostics
+                .push(diagnostic.with_help("rewrite the recursion as a loop"));
+        }
+    }
+
+    // Workgroup memory, barriers and compute built-ins may likewise be used
+    // in helpers that only compute entry points reach. Each function is
+    // reported once per entry point, at its first such use.
+    fn check_compute_only_uses(&mut self, unit: &'a TranslationUnit, call_graph: &CallGraph<'a>) {
+        for entry_point in &unit.functions {
+            let Some(stage) = entry_point.stage else {
+                continue;
+            };
+            if stage == ShaderStage::Compute {
+                continue;
+            }
+
+            let reached = call_graph.reachable_from(&entry_point.name);
+            for function in &unit.functions {
+                if !reached.contains(function.name.as_str()) {
+                    continue;
+                }
+                let Some((item, span)) = self.compute_only.get(function.name.as_str()) else {
+                    continue;
+                };
+                let error = SemanticError::ComputeOnly {
+                    item: item.clone(),
+                    function: function.name.clone(),
+                    entry_point: entry_point.name.clone(),
+                    stage,
+                };
+                let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                    .with_span(*span)
+                    .with_note(
+                        format!("`{}` is declared here", entry_point.name),
+                        Some(entry_point.span),
+                    );
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // Records the first use in the function being checked of something
+    // only compute shaders have.
+    fn use_compute_only(&mut self, item: &str, span: Span) {
+        self.compute_only
+            .entry(self.function)
+            .or_insert_with(|| (item.to_string(), span));
+    }
+
+    fn analyze_function(&mut self, function: &'a FunctionDefinition) {
+        self.module = function.module;
+        self.function = &function.name;
+        self.generics = function
+            .generics
+            .iter()
+            .map(|generic| (generic.name.clone(), generic.bound))
+            .collect();
+        self.check_generics(function);
+        if function.inline.is_some() && function.stage.is_some() {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::InlineEntryPoint(function.name.clone()).to_string(),
+            )
+            .with_span(function.span)
+            .with_help("nothing calls an entry point, so there are no calls to inline; put the attribute on a helper it calls instead");
+            self.diagnostics.push(diagnostic);
+        }
+        self.check_type(&function.return_type, function.span);
+        self.check_not_atomic(&function.return_type, function.span);
+
+        // Parameters share the outermost scope with the function body, so
+        // redeclaring one at the top level is an error, not shadowing.
+        self.scopes = vec![HashMap::new()];
+        for param in &function.params {
+            self.check_type(&param.type_name, param.span);
+            self.check_not_atomic(&param.type_name, param.span);
+            if param.type_name == Type::Void {
+                self.error(SemanticError::VoidVariable(param.name.clone()), param.span);
+            }
+            // Only parameters that pass a value back to the caller can be
+            // written.
+            let symbol = Symbol {
+                ty: Some(param.type_name.clone()),
+                span: param.span,
+                mutable: param.qualifier != ParameterQualifier::In,
+                is_parameter: true,
+                deferred: false,
+            };
+            self.declare(&param.name, symbol);
+        }
+
+        for stmt in &function.body {
+            self.check_statement(stmt, &function.return_type);
+        }
+        self.scopes.clear();
+
+        definite_assignment::check(function, &self.functions, &mut self.diagnostics);
+    }
+
+    // A generic function is checked once, against every type its parameters
+    // may stand for, so its type parameters must be determined by each call.
+    fn check_generics(&mut self, function: &FunctionDefinition) {
+        if function.generics.is_empty() {
+            return;
+        }
+        if function.stage.is_some() {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::GenericEntryPoint(function.name.clone()).to_string(),
+            )
+            .with_span(function.generics[0].span)
+            .with_help("entry points are called by the pipeline, which cannot choose a type argument; call a generic helper from a non-generic entry point instead");
+            self.diagnostics.push(diagnostic);
+        }
+        for generic in &function.generics {
+            let inferable = function
+                .params
+                .iter()
+                .any(|param| param.type_name == Type::Named(generic.name.clone()));
+            if !inferable {
+                let error = SemanticError::UninferableTypeParameter {
+                    function: function.name.clone(),
+                    parameter: generic.name.clone(),
+                };
+                let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                    .with_span(generic.span)
+                    .with_help(format!(
+                        "use `{}` as the type of one of the parameters",
+                        generic.name
+                    ));
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+
+    // Reports a type written by a name that is neither a visible enum nor a
+    // type parameter of the function being checked. `span` is where the
+    // type is used.
+    fn check_type(&mut self, ty: &Type, span: Span) {
+        if let Type::Atomic(element) = ty
+            && **element != Type::Int
+        {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::UnsupportedAtomic(ty.clone()).to_string(),
+            )
+            .with_span(span);
+            self.diagnostics.push(diagnostic);
+        }
+        let Type::Named(name) = ty else {
+            return;
+        };
+        if self.generics.contains_key(name) {
+            return;
+        }
+        match self.enums.get(name.as_str()) {
+            Some(&definition) => self.check_visible(
+                "enum",
+                name,
+                definition.module,
+                definition.public,
+                definition.span,
+                span,
+            ),
+            None => self.error(SemanticError::UnknownType(name.clone()), span),
+        }
+    }
+
+    // Reports an atomic type used at `span` for anything other than a
+    // workgroup variable.
+    fn check_not_atomic(&mut self, ty: &Type, span: Span) {
+        if let Type::Atomic(_) = ty {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::MisplacedAtomic(ty.clone()).to_string(),
+            )
+            .with_span(span)
+            .with_help("declare it at the top level as `workgroup atomic<int> name;`");
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    // Reports a use at `span` of a definition from another module that is
+    // either private or in a module the current one does not import.
+    fn check_visible(
+        &mut self,
+        kind: &'static str,
+        name: &str,
+        module: usize,
+        public: bool,
+        defined_at: Span,
+        span: Span,
+    ) {
+        if module == self.module {
+            return;
+        }
+        let path = self.modules[module].path.clone();
+        let diagnostic = if !public {
+            let error = SemanticError::PrivateItem {
+                kind,
+                name: name.to_string(),
+                module: path,
+            };
+            Diagnostic::new(Severity::Error, error.to_string())
+                .with_span(span)
+                .with_note(format!("`{}` is defined here", name), Some(defined_at))
+                .with_help(format!(
+                    "mark `{}` as `pub` to use it from other modules",
+                    name
+                ))
+        } else if !self.modules[self.module].imports.contains(&module) {
+            let error = SemanticError::NotImported {
+                kind,
+                name: name.to_string(),
+                module: path,
+            };
+            Diagnostic::new(Severity::Error, error.to_string())
+                .with_span(span)
+                .with_help("import the module that defines it into this file")
+        } else {
+            return;
+        };
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Applies the typing rule of an operation to argument types that may
+    // mention type parameters. The rule must accept every type each
+    // parameter can stand for, so an error in a generic function is found
+    // once at its definition. The result is the type the rule gives for all
+    // of them, or a type parameter if the result always equals it.
+    fn apply_rule(&self, args: &[Type], rule: impl Fn(&[Type]) -> Option<Type>) -> Option<Type> {
+        let mut params: Vec<&str> = Vec::new();
+        for arg in args {
+            if let Type::Named(name) = arg
+                && self.generics.contains_key(name)
+                && !params.contains(&name.as_str())
+            {
+                params.push(name);
+            }
+        }
+
+        // Every combination of types the parameters can stand for.
+        let mut combinations = vec![HashMap::new()];
+        for &param in &params {
+            let members = self.generics.get(param)?.members();
+            combinations = combinations
+                .into_iter()
+                .flat_map(|bindings: HashMap<String, Type>| {
+                    members.iter().map(move |member| {
+                        let mut bindings = bindings.clone();
+                        bindings.insert(param.to_string(), member.clone());
+                        bindings
+                    })
+                })
+                .collect();
+        }
+
+        let mut results = Vec::new();
+        for bindings in &combinations {
+            let args: Vec<Type> = args.iter().map(|arg| arg.substitute(bindings)).collect();
+            results.push(rule(&args)?);
+        }
+        if results.iter().all(|result| *result == results[0]) {
+            return Some(results[0].clone());
+        }
+        params.into_iter().find_map(|param| {
+            combinations
+                .iter()
+                .zip(&results)
+                .all(|(bindings, result)| bindings[param] == *result)
+                .then(|| Type::Named(param.to_string()))
+        })
+    }
+
+    // Whether `ty` is one of the types `bound` allows. A type parameter of
+    // the caller satisfies it if everything its own bound allows does.
+    fn satisfies(&self, ty: &Type, bound: TypeBound) -> bool {
+        let allowed = bound.members();
+        match ty {
+            Type::Named(name) => self
+                .generics
+                .get(name)
+                .is_some_and(|own| own.members().iter().all(|ty| allowed.contains(ty))),
+            _ => allowed.contains(ty),
+        }
+    }
+
+    fn declare(&mut self, name: &str, symbol: Symbol) {
+        let span = symbol.span;
+        let (current, enclosing) = self
+            .scopes
+            .split_last_mut()
+            .expect("declarations are only checked inside a function");
+
+        if let Some(previous) = current.get(name) {
+            let diagnostic = Diagnostic::new(
+                Severity::Error,
+                SemanticError::Redeclaration(name.to_string()).to_string(),
+            )
+            .with_span(span)
+            .with_note(
+                format!("previous declaration of `{}` is here", name),
+                Some(previous.span),
+            );
+            self.diagnostics.push(diagnostic);
+            return;
+        }
+
+        let shadowed = enclosing.iter().rev().find_map(|scope| scope.get(name));
+        if let Some(shadowed) = shadowed {
+            let severity = match self.options.shadowing {
+                ShadowingPolicy::Allow => None,
+                ShadowingPolicy::Warn => Some(Severity::Warning),
+                ShadowingPolicy::Deny => Some(Severity::Error),
+            };
+            if let Some(severity) = severity {
+                let diagnostic = Diagnostic::new(
+                    severity,
+                    SemanticError::Shadowing(name.to_string()).to_string(),
+                )
+                .with_span(span)
+                .with_note(
+                    format!("shadowed declaration of `{}` is here", name),
+                    Some(shadowed.span),
+                );
+                self.diagnostics.push(diagnostic);
+            }
+        }
+
+        current.insert(name.to_string(), symbol);
+    }
+
+    // Checks the statements in a new lexical scope.
+    fn check_block(&mut self, stmts: &[Statement], return_type: &Type) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.check_statement(stmt, return_type);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_statement(&mut self, stmt: &Statement, return_type: &Type) {
+        match stmt {
+            Statement::Declaration {
+                mutable,
+                type_name,
+                name,
+                initializer,
+                span,
+            } => {
+                let found = initializer
+                    .as_ref()
+                    .and_then(|initializer| self.check_expression(initializer));
+                let ty = match type_name {
+                    Some(type_name) => {
+                        self.check_type(type_name, *span);
+                        self.check_not_atomic(type_name, *span);
+                        if let (Some(found), Some(initializer)) = (found, initializer) {
+                            self.expect_type(type_name, found, initializer.span());
+                        }
+                        Some(type_name.clone())
+                    }
+                    None => {
+                        if let Some(found) = &found {
+                            self.resolved.declaration_types.insert(*span, found.clone());
+                        }
+                        found
+                    }
+                };
+                if ty == Some(Type::Void) {
+                    self.error(SemanticError::VoidVariable(name.clone()), *span);
+                }
+                let symbol = Symbol {
+                    ty,
+                    span: *span,
+                    mutable: *mutable,
+                    is_parameter: false,
+                    deferred: !*mutable && initializer.is_none(),
+                };
+                self.declare(name, symbol);
+            }
+            Statement::Block(stmts) => self.check_block(stmts, return_type),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                if let Some(found) = self.check_expression(condition) {
+                    self.expect_type(&Type::Bool, found, condition.span());
+                }
+                self.check_block(if_block, return_type);
+                if let Some(else_block) = else_block {
+                    self.check_block(else_block, return_type);
+                }
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                self.check_switch(selector, cases, return_type);
+            }
+            Statement::StaticIf { .. } => {
+                unreachable!("`static if` is resolved before semantic analysis")
+            }
+            Statement::Match {
+                selector,
+                arms,
+                span,
+            } => {
+                self.check_match(selector, arms, *span, return_type);
+            }
+            Statement::Break { span } => {
+                if self.switch_depth == 0 {
+                    self.error(SemanticError::BreakOutsideSwitch, *span);
+                }
+            }
+            // A correctly placed `fallthrough` is skipped by `check_switch`,
+            // so reaching one here means it is somewhere it cannot apply.
+            Statement::Fallthrough { span } => {
+                self.error(SemanticError::MisplacedFallthrough, *span)
+            }
+            // Which stages may run these is checked over the call graph.
+            Statement::Discard { .. } | Statement::Demote { .. } => {}
+            Statement::Return { value, span } => {
+                let (found, span) = match value {
+                    Some(value) => (self.check_expression(value), value.span()),
+                    None => (Some(Type::Void), *span),
+                };
+                if let Some(found) = found {
+                    self.expect_type(return_type, found, span);
+                }
+            }
+            Statement::Expression(expr) => {
+                self.check_expression(expr);
+            }
+        }
+    }
+
+    fn check_switch(&mut self, selector: &Expression, cases: &[SwitchCase], return_type: &Type) {
+        if let Some(found) = self.check_expression(selector)
+            && found != Type::Int
+        {
+            self.error(SemanticError::NonIntegerSelector(found), selector.span());
+        }
+
+        // The location of each label value and of the `default` label seen
+        // so far.
+        let mut seen = HashMap::new();
+        let mut default = None;
+        for label in cases.iter().flat_map(|case| &case.labels) {
+            match label {
+                CaseLabel::Value(value) => {
+                    let span = value.span();
+                    if let Some(found) = self.check_expression(value) {
+                        self.expect_type(&Type::Int, found, span);
+                    }
+                    match const_eval::evaluate_int(value) {
+                        Some(value) if i32::try_from(value).is_err() => {
+                            self.error(SemanticError::CaseLabelOutOfRange(value), span);
+                        }
+                        Some(value) => {
+                            if let Some(&first) = seen.get(&value) {
+                                self.duplicate_label(
+                                    SemanticError::DuplicateCaseLabel(value),
+                                    span,
+                                    first,
+                                );
+                            } else {
+                                seen.insert(value, span);
+                            }
+                        }
+                        None => self.error(SemanticError::NonConstantCaseLabel, span),
+                    }
+                }
+                CaseLabel::Default { span } => match default {
+                    Some(first) => {
+                        self.duplicate_label(SemanticError::DuplicateDefault, *span, first)
+                    }
+                    None => default = Some(*span),
+                },
+            }
+        }
+
+        // Each case body is its own scope, since control never falls into
+        // it implicitly.
+        self.switch_depth += 1;
+        for (i, case) in cases.iter().enumerate() {
+            let has_next = i + 1 < cases.len();
+            let body = match case.body.split_last() {
+                Some((Statement::Fallthrough { .. }, rest)) if has_next => rest,
+                _ => &case.body,
+            };
+            self.check_block(body, return_type);
+        }
+        self.switch_depth -= 1;
+    }
+
+    // Reports a switch label at `span` that repeats the one at `first`.
+    fn duplicate_label(&mut self, error: SemanticError, span: Span, first: Span) {
+        let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+            .with_span(span)
+            .with_note("first used here", Some(first));
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn check_match(
+        &mut self,
+        selector: &Expression,
+        arms: &[MatchArm],
+        span: Span,
+        return_type: &Type,
+    ) {
+        let selector_span = selector.span();
+        let selector = self.check_expression(selector);
+        let domain = match &selector {
+            Some(Type::Bool) => Some(Domain::Bool),
+            Some(Type::Int) => Some(Domain::Int),
+            Some(Type::Named(name)) if self.enums.contains_key(name.as_str()) => {
+                Some(Domain::Enum(self.enums[name.as_str()]))
+            }
+            Some(found) => {
+                self.error(
+                    SemanticError::UnmatchableSelector(found.clone()),
+                    selector_span,
+                );
+                None
+            }
+            None => None,
+        };
+
+        for pattern in arms.iter().flat_map(|arm| &arm.patterns) {
+            let found = match &pattern.kind {
+                PatternKind::Wildcard => continue,
+                PatternKind::Bool(_) => Type::Bool,
+                PatternKind::Int(_) => Type::Int,
+                PatternKind::IntRange(start, end) => {
+                    if start > end {
+                        self.error(
+                            SemanticError::EmptyRange {
+                                start: *start,
+                                end: *end,
+                            },
+                            pattern.span,
+                        );
+                    }
+                    Type::Int
+                }
+                PatternKind::EnumVariant { enum_name, variant } => {
+                    if !self.check_enum_variant(enum_name, variant, pattern.span) {
+                        continue;
+                    }
+                    Type::Named(enum_name.clone())
+                }
+            };
+            if domain.is_some()
+                && let Some(selector) = &selector
+            {
+                self.expect_type(selector, found, pattern.span);
+            }
+        }
+        if let Some(domain) = domain {
+            exhaustiveness::check(domain, arms, span, &mut self.diagnostics);
+        }
+
+        // Each arm is its own scope, like a case of a switch.
+        for arm in arms {
+            self.check_block(&arm.body, return_type);
+        }
+    }
+
+    // Reports `enum_name::variant` if it does not name a variant, returning
+    // whether it does.
+    fn check_enum_variant(&mut self, enum_name: &str, variant: &str, span: Span) -> bool {
+        let Some(&definition) = self.enums.get(enum_name) else {
+            self.error(SemanticError::UnknownType(enum_name.to_string()), span);
+            return false;
+        };
+        self.check_visible(
+            "enum",
+            enum_name,
+            definition.module,
+            definition.public,
+            definition.span,
+            span,
+        );
+        if definition.discriminant(variant).is_none() {
+            self.error(
+                SemanticError::UnknownVariant {
+                    enum_name: enum_name.to_string(),
+                    variant: variant.to_string(),
+                },
+                span,
+            );
+            return false;
+        }
+        true
+    }
+
+    // Reports `found` at `span` if it is not the type expected there.
+    fn expect_type(&mut self, expected: &Type, found: Type, span: Span) {
+        if *expected != found {
+            self.error(
+                SemanticError::TypeMismatch {
+                    expected: expected.clone(),
+                    found,
+                },
+                span,
+            );
+        }
+    }
+
+    // Returns the type of the expression, or `None` if it could not be
+    // determined because of an error that has already been reported.
+    fn check_expression(&mut self, expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Identifier { name, span } => {
+                let ty = self.lookup(name, *span)?;
+                self.check_not_atomic_access(name, &ty, *span).then_some(ty)
+            }
+            Expression::IntLiteral { .. } => Some(Type::Int),
+            Expression::FloatLiteral { .. } => Some(Type::Float),
+            Expression::BoolLiteral { .. } => Some(Type::Bool),
+            Expression::EnumVariant {
+                enum_name,
+                variant,
+                span,
+            } => self
+                .check_enum_variant(enum_name, variant, *span)
+                .then(|| Type::Named(enum_name.clone())),
+            Expression::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                let left = self.check_expression(left)?;
+                let right = self.check_expression(right)?;
+                let result = self.apply_rule(&[left.clone(), right.clone()], |args| {
+                    binary_result(*op, &args[0], &args[1])
+                });
+                if result.is_none() {
+                    self.error(
+                        SemanticError::InvalidOperands {
+                            op: *op,
+                            left,
+                            right,
+                        },
+                        *span,
+                    );
+                }
+                result
+            }
+            Expression::Assignment { name, value, span } => {
+                let target = self
+                    .lookup(name, *span)
+                    .filter(|target| self.check_not_atomic_access(name, target, *span));
+                self.check_mutable(name, *span);
+                let found = self.check_expression(value);
+                if let (Some(target), Some(found)) = (&target, found) {
+                    self.expect_type(target, found, value.span());
+                }
+                target
+            }
+            Expression::Call {
+                name, args, span, ..
+            } => self.check_call(name, args, *span),
+            Expression::Constructor {
+                type_name,
+                args,
+                span,
+            } => self.check_constructor(type_name.clone(), args, *span),
+        }
+    }
+
+    // Checks every argument, returning their types only if all of them
+    // could be determined.
+    fn check_arguments(&mut self, args: &[Expression]) -> Option<Vec<Type>> {
+        let types: Vec<Option<Type>> = args.iter().map(|arg| self.check_expression(arg)).collect();
+        types.into_iter().collect()
+    }
+
+    fn check_constructor(
+        &mut self,
+        type_name: Type,
+        args: &[Expression],
+        span: Span,
+    ) -> Option<Type> {
+        let args = self.check_arguments(args)?;
+        let mut types = vec![type_name.clone()];
+        types.extend(args.iter().cloned());
+        let result = self.apply_rule(&types, |types| constructor_result(&types[0], &types[1..]));
+        if result.is_none() {
+            self.error(SemanticError::InvalidConstructor { type_name, args }, span);
+        }
+        result
+    }
+
+    fn check_call(&mut self, name: &str, args: &[Expression], span: Span) -> Option<Type> {
+        // A type parameter used as a function, such as `T(0.0)`, constructs
+        // a value of the type it stands for.
+        if self.generics.contains_key(name) {
+            return self.check_constructor(Type::Named(name.to_string()), args, span);
+        }
+        let Some(&function) = self.functions.get(name) else {
+            let Some(intrinsic) = Intrinsic::from_name(name) else {
+                self.error(SemanticError::UndefinedFunction(name.to_string()), span);
+                self.check_arguments(args);
+                return None;
+            };
+            if intrinsic.synchronizes_workgroup() {
+                self.use_compute_only(&format!("{}()", name), span);
+            }
+            if let Some(capability) = intrinsic.capability()
+                && !self.options.target.supports(capability)
+            {
+                let error = SemanticError::MissingCapability {
+                    intrinsic,
+                    capability,
+                    target: self.options.target,
+                };
+                let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                    .with_span(span)
+                    .with_help(format!(
+                        "compile for a target that supports it with --target: {}",
+                        Target::supporting(capability)
+                    ));
+                self.diagnostics.push(diagnostic);
+            }
+            if intrinsic.is_atomic() {
+                return self.check_atomic_call(intrinsic, args, span);
+            }
+            if intrinsic == Intrinsic::SubgroupBroadcast
+                && let Some(id) = args.get(1)
+                && const_eval::evaluate_int(id).is_none()
+            {
+                let diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    SemanticError::NonConstantBroadcastId.to_string(),
+                )
+                .with_span(span)
+                .with_help("use `subgroup_shuffle` to read from an invocation chosen at run time");
+                self.diagnostics.push(diagnostic);
+            }
+            let args = self.check_arguments(args)?;
+            let result = self.apply_rule(&args, |args| intrinsic.result_type(args));
+            if result.is_none() {
+                self.error(SemanticError::NoMatchingOverload { intrinsic, args }, span);
+            }
+            return result;
+        };
+        self.check_visible(
+            "function",
+            name,
+            function.module,
+            function.public,
+            function.span,
+            span,
+        );
+
+        if function.params.len() != args.len() {
+            self.error(
+                SemanticError::ArgumentCount {
+                    function: name.to_string(),
+                    expected: function.params.len(),
+                    found: args.len(),
+                },
+                span,
+            );
+        }
+
+        // The type bound to each type parameter of a generic callee by the
+        // arguments seen so far.
+        let mut bindings: HashMap<String, Type> = HashMap::new();
+        let mut bound_all = true;
+        for (param, arg) in function.params.iter().zip(args) {
+            if param.qualifier != ParameterQualifier::In
+                && !matches!(arg, Expression::Identifier { .. })
+            {
+                self.error(
+                    SemanticError::NonLvalueArgument {
+                        function: name.to_string(),
+                        parameter: param.name.clone(),
+                    },
+                    arg.span(),
+                );
+            }
+            if let (
+                ParameterQualifier::Out | ParameterQualifier::InOut,
+                Expression::Identifier { name, span },
+            ) = (param.qualifier, arg)
+            {
+                self.check_mutable(name, *span);
+            }
+            let Some(found) = self.check_expression(arg) else {
+                bound_all = false;
+                continue;
+            };
+            let generic = function
+                .generics
+                .iter()
+                .find(|generic| param.type_name == Type::Named(generic.name.clone()));
+            let Some(generic) = generic else {
+                self.expect_type(&param.type_name, found, arg.span());
+                continue;
+            };
+
+            if let Some(first) = bindings.get(&generic.name) {
+                if *first != found {
+                    self.error(
+                        SemanticError::ConflictingTypeArgument {
+                            function: name.to_string(),
+                            parameter: generic.name.clone(),
+                            first: first.clone(),
+                            second: found,
+                        },
+                        arg.span(),
+                    );
+                    bound_all = false;
+                }
+            } else {
+                if !self.satisfies(&found, generic.bound) {
+                    let error = SemanticError::UnsatisfiedBound {
+                        function: name.to_string(),
+                        parameter: generic.name.clone(),
+                        found: found.clone(),
+                        bound: generic.bound,
+                    };
+                    let diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+                        .with_span(arg.span())
+                        .with_note(
+                            format!("`{}` is declared here", generic.name),
+                            Some(generic.span),
+                        );
+                    self.diagnostics.push(diagnostic);
+                    bound_all = false;
+                }
+                // Bound even if unsatisfied, so later arguments are only
+                // reported if they disagree with this one.
+                bindings.insert(generic.name.clone(), found);
+            }
+        }
+
+        if function.generics.is_empty() {
+            return Some(function.return_type.clone());
+        }
+        if !bound_all || bindings.len() != function.generics.len() {
+            return None;
+        }
+        let type_args = function
+            .generics
+            .iter()
+            .map(|generic| bindings[&generic.name].clone())
+            .collect();
+        self.resolved.type_args.insert(span, type_args);
+        Some(function.return_type.substitute(&bindings))
+    }
+
+    // Reports a direct use at `span` of `name` if it is an atomic variable,
+    // returning whether it is not.
+    fn check_not_atomic_access(&mut self, name: &str, ty: &Type, span: Span) -> bool {
+        if !matches!(ty, Type::Atomic(_)) {
+            return true;
+        }
+        let diagnostic = Diagnostic::new(
+            Severity::Error,
+            SemanticError::AtomicAccess(name.to_string()).to_string(),
+        )
+        .with_span(span)
+        .with_help(format!(
+            "read it with `atomic_add({}, 0)`, or write it with `atomic_exchange`",
+            name
+        ));
+        self.diagnostics.push(diagnostic);
+        false
+    }
+
+    // The first argument of an atomic intrinsic names the variable it
+    // operates on, which is only ever accessed this way.
+    fn check_atomic_call(
+        &mut self,
+        intrinsic: Intrinsic,
+        args: &[Expression],
+        span: Span,
+    ) -> Option<Type> {
+        let Some((first, operands)) = args.split_first() else {
+            self.error(
+                SemanticError::NoMatchingOverload {
+                    intrinsic,
+                    args: Vec::new(),
+                },
+                span,
+            );
+            return None;
+        };
+        let target = match first {
+            Expression::Identifier { name, span } => self.lookup(name, *span),
+            first => self.check_expression(first),
+        };
+        let operands = self.check_arguments(operands);
+        let target = target?;
+        if !matches!(target, Type::Atomic(_)) {
+            self.error(SemanticError::NonAtomicOperand(intrinsic), first.span());
+            return None;
+        }
+
+        let mut types = vec![target];
+        types.extend(operands?);
+        let result = intrinsic.result_type(&types);
+        if result.is_none() {
+            self.error(
+                SemanticError::NoMatchingOverload {
+                    intrinsic,
+                    args: types,
+                },
+                span,
+            );
+        }
+        result
+    }
+
+    // Reports a write at `span` to the variable `name` if its binding is
+    // immutable, suggesting how to make it writable.
+    fn check_mutable(&mut self, name: &str, span: Span) {
+        let Some(symbol) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) else {
+            if let Some(constant) = self.spec_constants.get(name) {
+                let diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    SemanticError::ImmutableAssignment(name.to_string()).to_string(),
+                )
+                .with_span(span)
+                .with_note(
+                    format!("`{}` is a specialization constant", name),
+                    Some(constant.span),
+                )
+                .with_help("its value is set by the runtime when the pipeline is created");
+                self.diagnostics.push(diagnostic);
+            } else if !self.workgroup_variables.contains_key(name)
+                && BuiltIn::from_name(name).is_some()
+            {
+                let diagnostic = Diagnostic::new(
+                    Severity::Error,
+                    SemanticError::ImmutableAssignment(name.to_string()).to_string(),
+                )
+                .with_span(span)
+                .with_help(format!(
+                    "`{}` is a built-in input, which the pipeline provides",
+                    name
+                ));
+                self.diagnostics.push(diagnostic);
+            }
+            return;
+        };
+        if symbol.mutable || symbol.deferred {
+            return;
+        }
+
+        let (note, help) = if symbol.is_parameter {
+            (
+                format!("`{}` is an `in` parameter, which is immutable", name),
+                format!(
+                    "qualify `{}` as `inout` to write back to the caller, or copy it into a `var` local",
+                    name
+                ),
+            )
+        } else {
+            (
+                format!("`{}` is declared immutable here", name),
+                format!("declare `{}` with `var` to make it mutable", name),
+            )
+        };
+        let diagnostic = Diagnostic::new(
+            Severity::Error,
+            SemanticError::ImmutableAssignment(name.to_string()).to_string(),
+        )
+        .with_span(span)
+        .with_note(note, Some(symbol.span))
+        .with_help(help);
+        self.diagnostics.push(diagnostic);
+    }
+
+    // Returns the type of the variable, global or built-in `name`, used at
+    // `span`. Locals hide globals of the same name, which hide built-ins.
+    fn lookup(&mut self, name: &str, span: Span) -> Option<Type> {
+        let symbol = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+        if let Some(symbol) = symbol {
+            return symbol.ty.clone();
+        }
+        if let Some(&variable) = self.workgroup_variables.get(name) {
+            self.check_visible(
+                "workgroup variable",
+                name,
+                variable.module,
+                variable.public,
+                variable.span,
+                span,
+            );
+            self.use_compute_only(name, span);
+            return Some(variable.type_name.clone());
+        }
+        let Some(&constant) = self.spec_constants.get(name) else {
+            if let Some(built_in) = BuiltIn::from_name(name) {
+                self.use_compute_only(name, span);
+                return Some(built_in.ty());
+            }
+            self.error(SemanticError::UndeclaredVariable(name.to_string()), span);
+            return None;
+        };
+        self.check_visible(
+            "specialization constant",
+            name,
+            constant.module,
+            constant.public,
+            constant.span,
+            span,
+        );
+        Some(constant.type_name.clone())
+    }
+}
+
+// Returns the type a binary operator produces for concrete operand types,
+// or `None` if it does not apply to them. Arithmetic works component-wise
+// on numeric scalars and vectors, and also between a vector and a scalar of
+// its element type.
+fn binary_result(op: BinaryOp, left: &Type, right: &Type) -> Option<Type> {
+    let numeric = |ty: &Type| matches!(ty.element(), Some(Type::Float | Type::Int));
+    match op {
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide => {
+            if !numeric(left) || left.element() != right.element() {
+                None
+            } else if left == right || right.width() == Some(1) {
+                Some(left.clone())
+            } else if left.width() == Some(1) {
+                Some(right.clone())
+            } else {
+                None
+            }
+        }
+        BinaryOp::Equal | BinaryOp::NotEqual => {
+            (left == right && *left != Type::Void).then_some(Type::Bool)
+        }
+        BinaryOp::LessThan
+        | BinaryOp::GreaterThan
+        | BinaryOp::LessThanOrEqual
+        | BinaryOp::GreaterThanOrEqual => {
+            (left == right && numeric(left) && left.width() == Some(1)).then_some(Type::Bool)
+        }
+    }
+}
+
+// Returns the type a constructor call produces, or `None` if the arguments
+// are not valid for it. A scalar constructor converts one scalar. A vector
+// constructor either repeats one scalar in every component or concatenates
+// the components of its arguments, which must add up to its width; each
+// component is converted to the vector's element type.
+fn constructor_result(type_name: &Type, args: &[Type]) -> Option<Type> {
+    type_name.element()?;
+    let width = type_name.width()?;
+    let widths: Option<Vec<u8>> = args.iter().map(Type::width).collect();
+    let valid = match widths?.as_slice() {
+        [1] => true,
+        [] => false,
+        widths => width > 1 && widths.iter().sum::<u8>() == width,
+    };
+    valid.then(|| type_name.clone())
+}
+
+// Formats argument types for a message, as in `float, int3`.
+fn type_list(types: &[Type]) -> String {
+    let types: Vec<String> = types.iter().map(Type::to_string).collect();
+    types.join(", ")
+}
+
+// The location of the first `discard` or `demote` among the statements.
+fn first_fragment_only_statement(stmts: &[Statement]) -> Option<Span> {
+    stmts.iter().find_map(|stmt| match stmt {
+        Statement::Discard { span } | Statement::Demote { span } => Some(*span),
+        Statement::Block(stmts) => first_fragment_only_statement(stmts),
+        Statement::If {
+            if_block,
+            else_block,
+            ..
+        } => first_fragment_only_statement(if_block).or_else(|| {
+            else_block
+                .as_deref()
+                .and_then(first_fragment_only_statement)
+        }),
+        Statement::Switch { cases, .. } => cases
+            .iter()
+            .find_map(|case| first_fragment_only_statement(&case.body)),
+        Statement::Match { arms, .. } => arms
+            .iter()
+            .find_map(|arm| first_fragment_only_statement(&arm.body)),
+        _ => None,
+    })
+}
@@ -0,0 +1,48 @@
+// src/source_map.rs
+// This file keeps the text of every source file the compiler reads. Each
+// file is placed at its own range of offsets in one address space, so a
+// `Span` identifies the file it points into as well as the text, and
+// diagnostics can cite the right file without spans carrying a file id.
+
+pub struct SourceFile {
+    // The path as given on the command line or in the `import` that loaded
+    // it, used when citing the file.
+    pub path: String,
+    pub text: String,
+    // The offset of the file's first byte.
+    pub base: usize,
+}
+
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    // Adds a file and returns its id. Ids are assigned in order from 0.
+    pub fn add(&mut self, path: impl Into<String>, text: impl Into<String>) -> usize {
+        // Leave a gap of one after the previous file, so the offset just
+        // past the end of a file still belongs to it.
+        let base = self
+            .files
+            .last()
+            .map_or(0, |file| file.base + file.text.len() + 1);
+        self.files.push(SourceFile {
+            path: path.into(),
+            text: text.into(),
+            base,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, id: usize) -> &SourceFile {
+        &self.files[id]
+    }
+
+    // The file containing the offset, and the offset within that file.
+    pub fn lookup(&self, offset: usize) -> Option<(&SourceFile, usize)> {
+        let index = self.files.partition_point(|file| file.base <= offset);
+        let file = self.files.get(index.checked_sub(1)?)?;
+        Some((file, (offset - file.base).min(file.text.len())))
+    }
+}
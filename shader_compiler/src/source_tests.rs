@@ -0,0 +1,191 @@
+// src/source_tests.rs
+// This file runs the source tests in `tests/osl`. Each test is a `.osl`
+// file compiled up to semantic analysis, and a `.expected` file next to it
+// holding the diagnostics reported, followed by `accepted` or `rejected`.
+// A program that is accepted is also lowered, and its IR must verify.
+//
+// A test compiled with options has them in a `.options` file next to it:
+//
+//     --target vulkan1.0 --shadowing=deny -D MODE=1,2
+//
+// Giving `-D` several values checks each permutation in turn. The files a
+// test imports are in `tests/osl/modules`.
+//
+// Running the tests with `BLESS=1` writes each `.expected` file from what
+// the test produced, for reviewing the difference before committing it.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::ir_verifier;
+use crate::lower;
+use crate::modules::ModuleLoader;
+use crate::monomorphize;
+use crate::semantic::{self, AnalysisOptions, ShadowingPolicy};
+use crate::static_if;
+use crate::target::Target;
+
+// The options given in the test's `.options` file, if it has one.
+fn options(path: &Path) -> (AnalysisOptions, Vec<String>) {
+    let mut options = AnalysisOptions {
+        shadowing: ShadowingPolicy::default(),
+        target: Target::default(),
+    };
+    let mut defines = Vec::new();
+    let text = fs::read_to_string(path.with_extension("options")).unwrap_or_default();
+    let mut args = text.split_whitespace();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--target" => {
+                let name = args.next().unwrap_or("");
+                options.target =
+                    Target::from_name(name).unwrap_or_else(|| panic!("unknown target `{}`", name));
+            }
+            "-D" => defines.extend(args.next().map(str::to_string)),
+            _ => match arg.strip_prefix("--shadowing=") {
+                Some(name) => {
+                    options.shadowing = ShadowingPolicy::from_name(name)
+                        .unwrap_or_else(|| panic!("unknown shadowing policy `{}`", name));
+                }
+                None => panic!("unknown option `{}`", arg),
+            },
+        }
+    }
+    (options, defines)
+}
+
+// What compiling the test at `path` produces, to compare with its
+// `.expected` file.
+fn output(path: &Path) -> String {
+    let (options, defines) = options(path);
+    let mut out = String::new();
+    let permutations = defines
+        .iter()
+        .map(|define| static_if::parse_define(define))
+        .collect::<Result<Vec<_>, _>>()
+        .and_then(|defines| static_if::permutations(&defines));
+    let permutations = match permutations {
+        Ok(permutations) => permutations,
+        Err(error) => return format!("error: {}\nrejected\n", error),
+    };
+
+    let mut loader = ModuleLoader::new(Vec::new());
+    let unit = match loader.load(path) {
+        Ok(unit) => unit,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                out.push_str(&diagnostic.render(loader.sources()));
+            }
+            out.push_str("rejected\n");
+            return out;
+        }
+    };
+    for permutation in &permutations {
+        if permutations.len() > 1 {
+            let mut values: Vec<String> = permutation
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            values.sort();
+            let _ = writeln!(out, "--- {} ---", values.join(" "));
+        }
+        let mut unit = unit.clone();
+        let mut diagnostics = static_if::resolve(&mut unit, permutation);
+        if diagnostics.is_empty() {
+            diagnostics = semantic::analyze(&mut unit, &options);
+        }
+        for diagnostic in &diagnostics {
+            out.push_str(&diagnostic.render(loader.sources()));
+        }
+        if diagnostics.iter().any(|d| d.is_error()) {
+            out.push_str("rejected\n");
+            continue;
+        }
+        monomorphize::monomorphize(&mut unit);
+        let module = lower::lower(&unit);
+        let violations = ir_verifier::verify(&module);
+        assert!(
+            violations.is_empty(),
+            "`{}` is accepted, but lowers to invalid IR:\n{}",
+            path.display(),
+            violations
+                .iter()
+                .map(|violation| format!("  {}\n", violation))
+                .collect::<String>()
+        );
+        out.push_str("accepted\n");
+    }
+    out
+}
+
+fn run(name: &str) {
+    // Tests run in the package's directory, so the paths the diagnostics
+    // name are relative to it.
+    let input = Path::new("tests/osl").join(format!("{}.osl", name));
+    let expected_path = Path::new("tests/osl").join(format!("{}.expected", name));
+    let actual = output(&input);
+    if std::env::var_os("BLESS").is_some() {
+        fs::write(&expected_path, &actual).expect("cannot write the expected output");
+        return;
+    }
+    let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+    assert!(
+        actual == expected,
+        "`{}` does not match `{}`:\n{}",
+        input.display(),
+        expected_path.display(),
+        actual
+    );
+}
+
+macro_rules! source_tests {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() {
+                run(stringify!($name));
+            }
+        )*
+    };
+}
+
+source_tests! {
+    atomics_accepted,
+    atomics_errors,
+    compute_accepted,
+    compute_errors,
+    definite_assignment_accepted,
+    definite_assignment_errors,
+    discard_accepted,
+    discard_errors,
+    enums_accepted,
+    enums_errors,
+    generics_accepted,
+    generics_errors,
+    immutability_errors,
+    inference_accepted,
+    inference_errors,
+    missing_return,
+    modules_accepted,
+    modules_cycle,
+    modules_missing,
+    modules_private,
+    qualifiers_accepted,
+    qualifiers_errors,
+    returns_accepted,
+    scoping_allow,
+    scoping_deny,
+    scoping_errors,
+    scoping_warn,
+    spec_constants_accepted,
+    spec_constants_errors,
+    static_if_errors,
+    static_if_permutations,
+    static_if_too_many,
+    subgroups_accepted,
+    subgroups_errors,
+    subgroups_webgpu,
+    switch_accepted,
+    switch_errors,
+}
@@ -0,0 +1,224 @@
+// src/static_if.rs
+// This file resolves `static if` statements against the compile-time
+// constants the driver defines with `-D NAME=value`. Each one is replaced
+// by the statements of the branch its condition selects, before semantic
+// analysis, so the branches not taken are never type-checked. A define
+// given several values (`-D QUALITY=0,1,2`) makes the driver compile every
+// combination of them: one permutation of the shader each.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::{Expression, Statement, TranslationUnit};
+use crate::const_eval::{self, ConstValue};
+use crate::diagnostics::{Diagnostic, Severity};
+
+// The value of every defined constant in one permutation.
+pub type Defines = HashMap<String, ConstValue>;
+
+// The most permutations one compilation may produce. Each is compiled in
+// full, and their number is the product of the defines' value counts, so a
+// few defines with several values each quickly get out of hand.
+pub const MAX_PERMUTATIONS: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum StaticIfError {
+    // A `-D` argument that is not `NAME` or `NAME=value[,value...]`.
+    InvalidDefine(String),
+    UndefinedConstant(String),
+    NonConstantCondition,
+    NonBoolCondition(ConstValue),
+    // The defines' values combine into more than `MAX_PERMUTATIONS`
+    // permutations. The count saturates rather than overflowing.
+    TooManyPermutations(usize),
+}
+
+impl fmt::Display for StaticIfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaticIfError::InvalidDefine(define) => {
+                write!(f, "invalid definition `{}`", define)
+            }
+            StaticIfError::UndefinedConstant(name) => {
+                write!(f, "compile-time constant `{}` is not defined", name)
+            }
+            StaticIfError::NonConstantCondition => write!(
+                f,
+                "condition of `static if` must be a compile-time constant"
+            ),
+            StaticIfError::NonBoolCondition(value) => write!(
+                f,
+                "condition of `static if` must be a bool, found `{}`",
+                value
+            ),
+            StaticIfError::TooManyPermutations(count) => write!(
+                f,
+                "the defines give {} permutations, more than the limit of {}",
+                count, MAX_PERMUTATIONS
+            ),
+        }
+    }
+}
+
+// Parses the argument of `-D`: `NAME` defines it as `true`, and
+// `NAME=value` as an int, float or bool literal. Several values separated
+// by commas each give a permutation.
+pub fn parse_define(define: &str) -> Result<(String, Vec<ConstValue>), StaticIfError> {
+    let invalid = || StaticIfError::InvalidDefine(define.to_string());
+    let (name, values) = define.split_once('=').unwrap_or((define, "true"));
+    let is_identifier = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(invalid());
+    }
+
+    let values = values
+        .split(',')
+        .map(|value| match value.trim() {
+            "true" => Some(ConstValue::Bool(true)),
+            "false" => Some(ConstValue::Bool(false)),
+            value if value.contains('.') => value.parse().ok().map(ConstValue::Float),
+            value => value.parse().ok().map(ConstValue::Int),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    Ok((name.to_string(), values))
+}
+
+// Every combination of the defines' values, varying the last define
+// fastest. With no define given several values, there is one.
+pub fn permutations(defines: &[(String, Vec<ConstValue>)]) -> Result<Vec<Defines>, StaticIfError> {
+    let count = defines.iter().fold(1usize, |count, (_, values)| {
+        count.saturating_mul(values.len())
+    });
+    if count > MAX_PERMUTATIONS {
+        return Err(StaticIfError::TooManyPermutations(count));
+    }
+    let mut permutations = vec![Defines::new()];
+    for (name, values) in defines {
+        permutations = permutations
+            .into_iter()
+            .flat_map(|permutation| {
+                values.iter().map(move |value| {
+                    let mut permutation = permutation.clone();
+                    permutation.insert(name.clone(), *value);
+                    permutation
+                })
+            })
+            .collect();
+    }
+    Ok(permutations)
+}
+
+// Replaces every `static if` in the unit by the branch `defines` selects.
+// A `static if` whose condition cannot be evaluated is reported and
+// removed along with both of its branches.
+pub fn resolve(unit: &mut TranslationUnit, defines: &Defines) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for function in &mut unit.functions {
+        resolve_statements(&mut function.body, defines, &mut diagnostics);
+    }
+    diagnostics
+}
+
+fn resolve_statements(
+    stmts: &mut Vec<Statement>,
+    defines: &Defines,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    // The chosen branch is spliced in place of the `static if`, like the
+    // text between `#if` and `#endif`, so its declarations stay visible
+    // after it.
+    let mut resolved = Vec::with_capacity(stmts.len());
+    for stmt in stmts.drain(..) {
+        match stmt {
+            Statement::StaticIf {
+                condition,
+                if_block,
+                else_block,
+                span,
+            } => {
+                let mut taken = match evaluate_condition(&condition, defines) {
+                    Ok(true) => if_block,
+                    Ok(false) => else_block.unwrap_or_default(),
+                    Err((error, help)) => {
+                        let mut diagnostic =
+                            Diagnostic::new(Severity::Error, error.to_string()).with_span(span);
+                        if let Some(help) = help {
+                            diagnostic = diagnostic.with_help(help);
+                        }
+                        diagnostics.push(diagnostic);
+                        Vec::new()
+                    }
+                };
+                resolve_statements(&mut taken, defines, diagnostics);
+                resolved.extend(taken);
+            }
+            mut stmt => {
+                resolve_nested(&mut stmt, defines, diagnostics);
+                resolved.push(stmt);
+            }
+        }
+    }
+    *stmts = resolved;
+}
+
+// Resolves the `static if`s in the blocks nested in a statement.
+fn resolve_nested(stmt: &mut Statement, defines: &Defines, diagnostics: &mut Vec<Diagnostic>) {
+    match stmt {
+        Statement::Block(stmts) => resolve_statements(stmts, defines, diagnostics),
+        Statement::If {
+            if_block,
+            else_block,
+            ..
+        } => {
+            resolve_statements(if_block, defines, diagnostics);
+            if let Some(else_block) = else_block {
+                resolve_statements(else_block, defines, diagnostics);
+            }
+        }
+        Statement::Switch { cases, .. } => {
+            for case in cases {
+                resolve_statements(&mut case.body, defines, diagnostics);
+            }
+        }
+        Statement::Match { arms, .. } => {
+            for arm in arms {
+                resolve_statements(&mut arm.body, defines, diagnostics);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Evaluates a condition, or returns why it cannot be, with a suggestion.
+fn evaluate_condition(
+    condition: &Expression,
+    defines: &Defines,
+) -> Result<bool, (StaticIfError, Option<String>)> {
+    match const_eval::evaluate_in(condition, defines) {
+        Some(ConstValue::Bool(value)) => Ok(value),
+        Some(value) => Err((StaticIfError::NonBoolCondition(value), None)),
+        None => match undefined_name(condition, defines) {
+            Some(name) => {
+                let help = format!("define it with `-D {}=<value>`", name);
+                Err((StaticIfError::UndefinedConstant(name), Some(help)))
+            }
+            None => Err((StaticIfError::NonConstantCondition, None)),
+        },
+    }
+}
+
+// The first identifier in the expression that is not a defined constant.
+fn undefined_name(expr: &Expression, defines: &Defines) -> Option<String> {
+    match expr {
+        Expression::Identifier { name, .. } => (!defines.contains_key(name)).then(|| name.clone()),
+        Expression::BinaryOp { left, right, .. } => {
+            undefined_name(left, defines).or_else(|| undefined_name(right, defines))
+        }
+        Expression::Constructor { args, .. } => {
+            args.iter().find_map(|arg| undefined_name(arg, defines))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,145 @@
+// src/strip.rs
+// This file implements the pass that strips a module down to what its
+// entry points use: the functions they call, directly or through other
+// functions, and the globals and specialization constants those functions
+// refer to. When an entry point is selected, the other entry points are
+// removed first, so the module only holds what that one stage needs.
+//
+// A module without entry points, such as a file of helper functions, is
+// left as it is: nothing in it is unused until something calls it.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{InstKind, Module};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+pub struct Strip {
+    // The entry point to keep, or `None` to keep them all.
+    pub entry_point: Option<String>,
+}
+
+impl Pass for Strip {
+    fn name(&self) -> &'static str {
+        "strip"
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> Preserved {
+        let count = (
+            module.functions.len(),
+            module.globals.len(),
+            module.spec_constants.len(),
+        );
+        if let Some(entry_point) = &self.entry_point {
+            module
+                .functions
+                .retain(|function| function.stage.is_none() || function.name == *entry_point);
+        }
+        if module
+            .functions
+            .iter()
+            .any(|function| function.stage.is_some())
+        {
+            strip_functions(module);
+            strip_globals(module);
+        }
+
+        let stripped = count
+            != (
+                module.functions.len(),
+                module.globals.len(),
+                module.spec_constants.len(),
+            );
+        // The functions left only had the indices they refer to globals
+        // and specialization constants by renumbered.
+        if stripped {
+            Preserved::Cfg
+        } else {
+            Preserved::All
+        }
+    }
+}
+
+// Removes the functions no entry point calls.
+fn strip_functions(module: &mut Module) {
+    let mut used = HashSet::new();
+    let mut worklist: Vec<&str> = module
+        .functions
+        .iter()
+        .filter(|function| function.stage.is_some())
+        .map(|function| function.name.as_str())
+        .collect();
+    while let Some(name) = worklist.pop() {
+        if !used.insert(name.to_string()) {
+            continue;
+        }
+        let Some(function) = module.functions.iter().find(|f| f.name == name) else {
+            continue;
+        };
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                if let InstKind::Call(callee, _) = &instruction.kind {
+                    worklist.push(callee);
+                }
+            }
+        }
+    }
+    module
+        .functions
+        .retain(|function| used.contains(&function.name));
+}
+
+// Removes the globals and specialization constants no function refers to,
+// and renumbers the references to the rest.
+fn strip_globals(module: &mut Module) {
+    let mut used_globals = HashSet::new();
+    let mut used_spec_constants = HashSet::new();
+    for function in &module.functions {
+        for block in &function.blocks {
+            for instruction in &block.instructions {
+                match instruction.kind {
+                    InstKind::Global(index) => {
+                        used_globals.insert(index);
+                    }
+                    InstKind::SpecConstant(index) => {
+                        used_spec_constants.insert(index);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let globals = renumber(module.globals.len(), &used_globals);
+    let spec_constants = renumber(module.spec_constants.len(), &used_spec_constants);
+    let mut index = 0;
+    module.globals.retain(|_| {
+        index += 1;
+        used_globals.contains(&(index - 1))
+    });
+    let mut index = 0;
+    module.spec_constants.retain(|_| {
+        index += 1;
+        used_spec_constants.contains(&(index - 1))
+    });
+    for function in &mut module.functions {
+        for block in &mut function.blocks {
+            for instruction in &mut block.instructions {
+                match &mut instruction.kind {
+                    InstKind::Global(index) => *index = globals[index],
+                    InstKind::SpecConstant(index) => *index = spec_constants[index],
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// The new index of each of `count` items once only those in `used` are
+// kept.
+fn renumber(count: usize, used: &HashSet<usize>) -> HashMap<usize, usize> {
+    (0..count)
+        .filter(|index| used.contains(index))
+        .enumerate()
+        .map(|(new, old)| (old, new))
+        .collect()
+}
@@ -0,0 +1,73 @@
+// src/target.rs
+// This file describes the targets a program can be compiled for, and the
+// optional features, or capabilities, each of them supports. Language
+// features that not every target has declare the capability they need, so
+// compiling for a target without it is reported where the feature is used
+// instead of producing output the target would reject.
+
+use std::fmt;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Capability {
+    // Operations across the invocations of a subgroup (a wave or warp).
+    Subgroups,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Subgroups => write!(f, "subgroup operations"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Target {
+    Vulkan1_0,
+    #[default]
+    Vulkan1_1,
+    OpenGl4_5,
+    WebGpu,
+}
+
+impl Target {
+    pub const ALL: [Target; 4] = [
+        Target::Vulkan1_0,
+        Target::Vulkan1_1,
+        Target::OpenGl4_5,
+        Target::WebGpu,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Target> {
+        Target::ALL.into_iter().find(|target| target.name() == name)
+    }
+
+    // The name the target is selected by with `--target`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::Vulkan1_0 => "vulkan1.0",
+            Target::Vulkan1_1 => "vulkan1.1",
+            Target::OpenGl4_5 => "opengl4.5",
+            Target::WebGpu => "webgpu",
+        }
+    }
+
+    // Only capabilities every implementation of the target must provide
+    // count; those behind an optional extension do not.
+    pub fn supports(self, capability: Capability) -> bool {
+        match capability {
+            Capability::Subgroups => self == Target::Vulkan1_1,
+        }
+    }
+
+    // The names of the targets that support the capability, as in
+    // `vulkan1.1, webgpu`.
+    pub fn supporting(capability: Capability) -> String {
+        let names: Vec<&str> = Target::ALL
+            .into_iter()
+            .filter(|target| target.supports(capability))
+            .map(Target::name)
+            .collect();
+        names.join(", ")
+    }
+}
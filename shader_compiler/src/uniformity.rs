@@ -0,0 +1,448 @@
+// src/uniformity.rs
+// This file checks that workgroup barriers are only reached in uniform
+// control flow: where every invocation of a workgroup takes the same path.
+// If some invocations skipped a barrier the others wait at, the workgroup
+// would hang, so whether a barrier is reached must not depend on a value
+// that can differ between invocations.
+//
+// Literals, specialization constants and the workgroup-wide built-ins are
+// uniform. The per-invocation built-ins, entry point inputs and anything
+// read from workgroup memory are not, and neither is whatever is computed
+// from them. A helper's parameters are summarized instead of assumed
+// either way: a helper that branches on a parameter before a barrier
+// requires every call to pass a uniform argument for it, and what it
+// returns, or writes to an `out` or `inout` parameter, depends on the
+// arguments its summary names.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::ast::{
+    CaseLabel, Expression, FunctionDefinition, ParameterQualifier, Span, Statement, TranslationUnit,
+};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::intrinsics::{BuiltIn, Intrinsic};
+use crate::semantic::SemanticError;
+
+// What a value, or the decision to run a statement, depends on.
+#[derive(Debug, PartialEq, Clone, Default)]
+struct Dependence {
+    // Where a value that can differ between invocations is used, if this
+    // depends on one.
+    varying: Option<Span>,
+    // The parameters of the function being checked that this depends on.
+    params: BTreeSet<usize>,
+}
+
+impl Dependence {
+    fn varying(span: Span) -> Self {
+        Dependence {
+            varying: Some(span),
+            params: BTreeSet::new(),
+        }
+    }
+
+    fn join(&mut self, other: &Dependence) {
+        self.varying = self.varying.or(other.varying);
+        self.params.extend(&other.params);
+    }
+}
+
+// How calling a function constrains the caller.
+#[derive(Debug, PartialEq, Clone, Default)]
+struct Summary {
+    // The first barrier the function reaches, directly or through a call,
+    // if it reaches one. It must then be called in uniform control flow.
+    barrier: Option<Span>,
+    // The parameters that decide whether a barrier is reached.
+    uniform_params: BTreeSet<usize>,
+    // What the return value depends on.
+    result: Dependence,
+    // What the value each `out` and `inout` parameter holds on return
+    // depends on, by parameter index.
+    outputs: BTreeMap<usize, Dependence>,
+}
+
+// Reports every barrier, and every call to a function that reaches one,
+// that not all invocations of a workgroup may get to.
+pub fn check(unit: &TranslationUnit, diagnostics: &mut Vec<Diagnostic>) {
+    // Duplicate definitions are reported by semantic analysis; calls
+    // resolve to the first.
+    let mut functions: HashMap<&str, &FunctionDefinition> = HashMap::new();
+    for function in &unit.functions {
+        functions.entry(function.name.as_str()).or_insert(function);
+    }
+    let globals = Globals {
+        functions,
+        workgroup_variables: unit
+            .workgroup_variables
+            .iter()
+            .map(|variable| variable.name.as_str())
+            .collect(),
+        spec_constants: unit
+            .spec_constants
+            .iter()
+            .map(|constant| constant.name.as_str())
+            .collect(),
+    };
+
+    // A function's summary depends on those of the functions it calls, so
+    // summaries are refined until none changes. Each only ever grows, so
+    // this ends.
+    let mut summaries: HashMap<&str, Summary> = HashMap::new();
+    loop {
+        let mut changed = false;
+        for &function in globals.functions.values() {
+            let (summary, _) = check_function(function, &globals, &summaries);
+            if summaries.get(function.name.as_str()) != Some(&summary) {
+                summaries.insert(&function.name, summary);
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for function in &unit.functions {
+        if std::ptr::eq(globals.functions[function.name.as_str()], function) {
+            let (_, found) = check_function(function, &globals, &summaries);
+            diagnostics.extend(found);
+        }
+    }
+}
+
+struct Globals<'a> {
+    functions: HashMap<&'a str, &'a FunctionDefinition>,
+    workgroup_variables: BTreeSet<&'a str>,
+    spec_constants: BTreeSet<&'a str>,
+}
+
+fn check_function(
+    function: &FunctionDefinition,
+    globals: &Globals,
+    summaries: &HashMap<&str, Summary>,
+) -> (Summary, Vec<Diagnostic>) {
+    // An entry point's parameters are inputs of the invocation, so they
+    // are not known to be uniform.
+    let params = function
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let dependence = if function.stage.is_some() {
+                Dependence::varying(param.span)
+            } else {
+                Dependence {
+                    varying: None,
+                    params: BTreeSet::from([i]),
+                }
+            };
+            (param.name.clone(), dependence)
+        })
+        .collect();
+
+    let mut checker = FunctionChecker {
+        globals,
+        summaries,
+        scopes: vec![params],
+        control: Dependence::default(),
+        exits: Dependence::default(),
+        breaks: Dependence::default(),
+        summary: Summary::default(),
+        diagnostics: Vec::new(),
+    };
+    checker.check_statements(&function.body);
+    // Writes join what they assign into the variable, so once the body is
+    // checked each parameter depends on everything ever written to it.
+    for (i, param) in function.params.iter().enumerate() {
+        if param.qualifier != ParameterQualifier::In {
+            let output = checker.scopes[0][param.name.as_str()].clone();
+            checker.summary.outputs.insert(i, output);
+        }
+    }
+    (checker.summary, checker.diagnostics)
+}
+
+struct FunctionChecker<'a> {
+    globals: &'a Globals<'a>,
+    summaries: &'a HashMap<&'a str, Summary>,
+    // What each variable's value depends on, one map per lexical scope.
+    scopes: Vec<HashMap<String, Dependence>>,
+    // What the decision to run the statement being checked depends on.
+    control: Dependence,
+    // What the decisions to leave the function early depend on. Once one
+    // is reached, control flow after the statement holding it depends on
+    // them too.
+    exits: Dependence,
+    // Likewise for the decisions to leave the innermost switch, up to the
+    // end of the switch.
+    breaks: Dependence,
+    summary: Summary,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FunctionChecker<'_> {
+    fn check_statements(&mut self, stmts: &[Statement]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.check_statement(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    // Checks statements that only run if `condition` selects them.
+    fn check_branch(&mut self, condition: &Dependence, stmts: &[Statement]) {
+        let enclosing = self.control.clone();
+        self.control.join(condition);
+        self.check_statements(stmts);
+        self.control = enclosing;
+    }
+
+    fn check_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Declaration {
+                name, initializer, ..
+            } => {
+                let mut dependence = match initializer {
+                    Some(initializer) => self.check_expression(initializer),
+                    None => Dependence::default(),
+                };
+                dependence.join(&self.control);
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.insert(name.clone(), dependence);
+                }
+            }
+            Statement::Block(stmts) => self.check_statements(stmts),
+            Statement::If {
+                condition,
+                if_block,
+                else_block,
+                ..
+            }
+            | Statement::StaticIf {
+                condition,
+                if_block,
+                else_block,
+                ..
+            } => {
+                let condition = self.check_expression(condition);
+                self.check_branch(&condition, if_block);
+                if let Some(else_block) = else_block {
+                    self.check_branch(&condition, else_block);
+                }
+            }
+            Statement::Switch {
+                selector, cases, ..
+            } => {
+                let selector = self.check_expression(selector);
+                let enclosing = std::mem::take(&mut self.breaks);
+                for case in cases {
+                    for label in &case.labels {
+                        if let CaseLabel::Value(value) = label {
+                            self.check_expression(value);
+                        }
+                    }
+                    // A case fallen into is only reached by the invocations
+                    // that did not break out of the cases before it.
+                    let mut condition = selector.clone();
+                    condition.join(&self.breaks);
+                    self.check_branch(&condition, &case.body);
+                }
+                self.breaks = enclosing;
+            }
+            Statement::Match { selector, arms, .. } => {
+                let selector = self.check_expression(selector);
+                for arm in arms {
+                    self.check_branch(&selector, &arm.body);
+                }
+            }
+            Statement::Return { value, .. } => {
+                if let Some(value) = value {
+                    let mut dependence = self.check_expression(value);
+                    dependence.join(&self.control);
+                    self.summary.result.join(&dependence);
+                }
+                self.exits.join(&self.control.clone());
+            }
+            Statement::Break { .. } => self.breaks.join(&self.control.clone()),
+            Statement::Discard { .. } => self.exits.join(&self.control.clone()),
+            Statement::Fallthrough { .. } | Statement::Demote { .. } => {}
+            Statement::Expression(expr) => {
+                self.check_expression(expr);
+            }
+        }
+        // Invocations that left early no longer run what follows.
+        let exits = self.exits.clone();
+        self.control.join(&exits);
+        let breaks = self.breaks.clone();
+        self.control.join(&breaks);
+    }
+
+    fn check_expression(&mut self, expr: &Expression) -> Dependence {
+        match expr {
+            Expression::Identifier { name, span } => self.read(name, *span),
+            Expression::IntLiteral { .. }
+            | Expression::FloatLiteral { .. }
+            | Expression::BoolLiteral { .. }
+            | Expression::EnumVariant { .. } => Dependence::default(),
+            Expression::BinaryOp { left, right, .. } => {
+                let mut dependence = self.check_expression(left);
+                dependence.join(&self.check_expression(right));
+                dependence
+            }
+            Expression::Assignment { name, value, .. } => {
+                let mut dependence = self.check_expression(value);
+                dependence.join(&self.control);
+                self.write(name, &dependence);
+                dependence
+            }
+            Expression::Constructor { args, .. } => {
+                let mut dependence = Dependence::default();
+                for arg in args {
+                    dependence.join(&self.check_expression(arg));
+                }
+                dependence
+            }
+            Expression::Call {
+                name, args, span, ..
+            } => self.check_call(name, args, *span),
+        }
+    }
+
+    fn check_call(&mut self, name: &str, arg_exprs: &[Expression], span: Span) -> Dependence {
+        let args: Vec<Dependence> = arg_exprs
+            .iter()
+            .map(|arg| self.check_expression(arg))
+            .collect();
+        let Some(&function) = self.globals.functions.get(name) else {
+            if let Some(intrinsic) = Intrinsic::from_name(name)
+                && intrinsic.synchronizes_workgroup()
+            {
+                let error = SemanticError::NonUniformBarrier(name.to_string());
+                self.require_uniform(&self.control.clone(), error, span, None);
+                self.summary.barrier.get_or_insert(span);
+            }
+            // Other intrinsics compute their result from their arguments
+            // alone.
+            if let Some(intrinsic) = Intrinsic::from_name(name)
+                && intrinsic.is_varying()
+            {
+                return Dependence::varying(span);
+            }
+            let mut dependence = Dependence::default();
+            for arg in &args {
+                dependence.join(arg);
+            }
+            return dependence;
+        };
+        let Some(summary) = self.summaries.get(name) else {
+            return Dependence::default();
+        };
+
+        if let Some(barrier) = summary.barrier {
+            let note = (format!("`{}` reaches this barrier", name), barrier);
+            let error = SemanticError::NonUniformCall(name.to_string());
+            self.require_uniform(&self.control.clone(), error, span, Some(note));
+            self.summary.barrier.get_or_insert(span);
+        }
+        for &i in &summary.uniform_params {
+            let (Some(param), Some(arg)) = (function.params.get(i), args.get(i)) else {
+                continue;
+            };
+            let error = SemanticError::NonUniformArgument {
+                function: name.to_string(),
+                parameter: param.name.clone(),
+            };
+            let note = (
+                format!("`{}` decides whether a barrier is reached here", param.name),
+                summary.barrier.unwrap_or(function.span),
+            );
+            self.require_uniform(arg, error, span, Some(note));
+        }
+
+        let result = at_call(&summary.result, &args, span);
+        for (i, output) in &summary.outputs {
+            if let Some(Expression::Identifier { name, .. }) = arg_exprs.get(*i) {
+                let mut written = at_call(output, &args, span);
+                written.join(&self.control);
+                self.write(name, &written);
+            }
+        }
+        result
+    }
+
+    // Reports `error` at `span` if `dependence` can differ between
+    // invocations. If it depends on parameters instead, callers are
+    // required to pass uniform arguments for them.
+    fn require_uniform(
+        &mut self,
+        dependence: &Dependence,
+        error: SemanticError,
+        span: Span,
+        note: Option<(String, Span)>,
+    ) {
+        self.summary
+            .uniform_params
+            .extend(dependence.params.iter().copied());
+        let Some(varying) = dependence.varying else {
+            return;
+        };
+        let mut diagnostic = Diagnostic::new(Severity::Error, error.to_string())
+            .with_span(span)
+            .with_note(
+                "this depends on a value that can differ between invocations",
+                Some(varying),
+            );
+        if let Some((message, at)) = note {
+            diagnostic = diagnostic.with_note(message, Some(at));
+        }
+        self.diagnostics.push(diagnostic.with_help(
+            "every invocation of the workgroup must reach the barrier, or none of them",
+        ));
+    }
+
+    fn read(&self, name: &str, span: Span) -> Dependence {
+        if let Some(dependence) = self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return dependence.clone();
+        }
+        // Other invocations may have written workgroup memory since.
+        if self.globals.workgroup_variables.contains(name) {
+            return Dependence::varying(span);
+        }
+        if self.globals.spec_constants.contains(name) {
+            return Dependence::default();
+        }
+        match BuiltIn::from_name(name) {
+            Some(built_in) if !built_in.is_uniform() => Dependence::varying(span),
+            _ => Dependence::default(),
+        }
+    }
+
+    // Values assigned since a variable was declared add to what it depends
+    // on rather than replace it, since the assignment may be in a branch.
+    fn write(&mut self, name: &str, dependence: &Dependence) {
+        if let Some(current) = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+        {
+            current.join(dependence);
+        }
+    }
+}
+
+// What a value the callee's summary says depends on `dependence` depends
+// on at a call at `span` with arguments that depend on `args`.
+fn at_call(dependence: &Dependence, args: &[Dependence], span: Span) -> Dependence {
+    let mut result = Dependence {
+        varying: dependence.varying.map(|_| span),
+        params: BTreeSet::new(),
+    };
+    for &i in &dependence.params {
+        if let Some(arg) = args.get(i) {
+            result.join(arg);
+        }
+    }
+    result
+}
@@ -0,0 +1,359 @@
+// src/unroll.rs
+// This file implements full loop unrolling, an optimization pass that
+// replaces a loop running a number of times known at compile time with
+// that many copies of its body, one after the other. A blur kernel's loop
+// over its taps becomes the straight-line code that would be written by
+// hand, and once the induction variable is a constant in each copy,
+// constant propagation folds the offsets and weights it indexes.
+//
+// Only innermost loops are unrolled, as copying a loop within a loop
+// copies the inner loop too; once an inner loop is unrolled, the loop
+// around it is innermost and can be unrolled in turn. The loop must be
+// left from its header alone, with one latch, which is its continue
+// target, and have a trip count `Loop::trip_count` can work out. Each copy
+// of the header keeps the instructions it computes but not its branch, as
+// every copy but the last goes on into the body and the last leaves.
+//
+// A loop is unrolled if its copies would have no more instructions than
+// the threshold. A loop hinted `unroll` is unrolled however large it gets,
+// as long as it runs at most `MAX_HINTED_TRIP_COUNT` times, and one hinted
+// `dont_unroll` never is.
+
+use std::collections::HashMap;
+
+use crate::ir::{
+    Block, BlockId, Function, InstKind, Instruction, LoopControl, Merge, Module, Terminator, Value,
+};
+use crate::loops::{Loop, LoopInfo};
+use crate::pass_manager::{Analyses, Pass, Preserved};
+
+// The most times a loop hinted `unroll` may run to be unrolled.
+const MAX_HINTED_TRIP_COUNT: u32 = 1024;
+
+pub struct Unroll {
+    // The most instructions the copies of a loop may have in all.
+    pub threshold: usize,
+}
+
+impl Pass for Unroll {
+    fn name(&self) -> &'static str {
+        "unroll"
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> Preserved {
+        let mut preserved = Preserved::All;
+        for function in &mut module.functions {
+            // Unrolling a loop changes the loops around it, so they are
+            // found again after each.
+            loop {
+                let loops = analyses.loops(function);
+                let Some((index, trip_count)) = (0..loops.loops.len()).find_map(|index| {
+                    let trip_count = self.trip_count(function, &loops, index)?;
+                    Some((index, trip_count))
+                }) else {
+                    break;
+                };
+                unroll(function, &loops.loops[index], trip_count);
+                analyses.invalidate_function(&function.name, Preserved::Nothing);
+                preserved = Preserved::Nothing;
+            }
+        }
+        preserved
+    }
+}
+
+impl Unroll {
+    // The trip count of the loop, if it is one to unroll.
+    fn trip_count(&self, function: &Function, loops: &LoopInfo, index: usize) -> Option<u32> {
+        let l = &loops.loops[index];
+        if loops.loops.iter().any(|inner| inner.parent == Some(index)) {
+            return None;
+        }
+        let header = function.block(l.header)?;
+        let Some(Merge::Loop {
+            merge,
+            continue_target,
+            control,
+        }) = header.merge
+        else {
+            return None;
+        };
+        let [latch] = l.latches.as_slice() else {
+            return None;
+        };
+        if control == LoopControl::DontUnroll
+            || *latch != continue_target
+            || l.exits != [merge]
+            || function.block(*latch)?.terminator != Terminator::Branch(l.header)
+        {
+            return None;
+        }
+        // Each copy of the body gets its own copies of the selections in
+        // it, so those must merge inside the loop, and only the header may
+        // branch out of it.
+        let mut size = 0;
+        for &block in &l.blocks {
+            let block = function.block(block)?;
+            size += block.instructions.len() + 1;
+            if block.id == l.header {
+                continue;
+            }
+            let merges = match block.merge {
+                Some(Merge::Selection(merge)) => vec![merge],
+                Some(Merge::Loop { .. }) => return None,
+                None => Vec::new(),
+            };
+            let mut targets = merges.into_iter().chain(block.terminator.successors());
+            if !targets.all(|target| l.blocks.contains(&target)) {
+                return None;
+            }
+        }
+        // Every value the header's phis take comes from before the loop or
+        // from the latch.
+        for instruction in &header.instructions {
+            if let InstKind::Phi(incoming) = &instruction.kind
+                && incoming.len() != 2
+            {
+                return None;
+            }
+        }
+
+        let limit = match control {
+            LoopControl::Unroll => MAX_HINTED_TRIP_COUNT,
+            _ => (self.threshold / size) as u32,
+        };
+        l.trip_count(function, limit)
+    }
+}
+
+// Replaces the loop with `trip_count` copies of its header and body,
+// followed by a last copy of its header that branches to its merge block.
+fn unroll(function: &mut Function, l: &Loop, trip_count: u32) {
+    let preheader = l.preheader.expect("a counted loop has a preheader");
+    let latch = l.latches[0];
+    let header = function
+        .block(l.header)
+        .expect("the header is a block")
+        .clone();
+    let Terminator::CondBranch {
+        if_true, if_false, ..
+    } = header.terminator
+    else {
+        unreachable!("a counted loop's header branches on its condition");
+    };
+    let (body_entry, exit) = if l.blocks.contains(&if_true) {
+        (if_true, if_false)
+    } else {
+        (if_false, if_true)
+    };
+    let mut body: Vec<Block> = function
+        .blocks
+        .iter()
+        .filter(|block| block.id != l.header && l.blocks.contains(&block.id))
+        .cloned()
+        .collect();
+    // The body's entry is only entered from the header, so its phis each
+    // have the one value the header passes.
+    let mut entry_phis = Vec::new();
+    if let Some(entry) = body.iter_mut().find(|block| block.id == body_entry) {
+        entry.instructions.retain(
+            |instruction| match (instruction.result, &instruction.kind) {
+                (Some(result), InstKind::Phi(incoming)) => {
+                    entry_phis.push((result, incoming[0].1));
+                    false
+                }
+                _ => true,
+            },
+        );
+    }
+
+    // Each phi of the header, with the value it takes on entering the loop
+    // and the one it takes from the latch.
+    let mut phis = Vec::new();
+    let mut header_instructions = Vec::new();
+    for instruction in header.instructions {
+        match (instruction.result, &instruction.kind) {
+            (Some(result), InstKind::Phi(incoming)) => {
+                let from = |block: BlockId| {
+                    incoming
+                        .iter()
+                        .find(|&&(from, _)| from == block)
+                        .map(|&(_, value)| value)
+                        .expect("the phi takes a value from each predecessor")
+                };
+                phis.push((result, from(preheader), from(latch)));
+            }
+            _ => header_instructions.push(instruction),
+        }
+    }
+
+    // The copy of each value defined in the loop in the iteration being
+    // copied.
+    let mut values: HashMap<Value, Value> = phis
+        .iter()
+        .map(|&(phi, initial, _)| (phi, initial))
+        .collect();
+    // The header's id goes to the first block, which the preheader already
+    // branches to.
+    let mut blocks = vec![Block {
+        id: l.header,
+        instructions: Vec::new(),
+        merge: None,
+        terminator: Terminator::Unreachable,
+    }];
+    // The block the next copy of the header goes at the end of.
+    let mut tail = 0;
+
+    for _ in 0..trip_count {
+        // The body's entry is only entered from the header, so it joins
+        // the header's copy; the other blocks are copied.
+        let mut block_map = HashMap::from([(body_entry, blocks[tail].id)]);
+        for block in &body {
+            if block.id != body_entry {
+                block_map.insert(block.id, fresh_block(function));
+            }
+        }
+        copy_results(function, &header_instructions, &mut values);
+        for &(phi, value) in &entry_phis {
+            values.insert(phi, map_value(&values, value));
+        }
+        for block in &body {
+            copy_results(function, &block.instructions, &mut values);
+        }
+
+        let header_copy = copy_instructions(&header_instructions, &values, &block_map);
+        blocks[tail].instructions.extend(header_copy);
+        for block in &body {
+            let instructions = copy_instructions(&block.instructions, &values, &block_map);
+            let merge = block
+                .merge
+                .map(|merge| merge.map_blocks(|block| block_map[&block]));
+            // The latch's branch back to the header is replaced below.
+            let mut terminator = block.terminator.clone();
+            for successor in terminator.successors_mut() {
+                *successor = block_map.get(successor).copied().unwrap_or(*successor);
+            }
+            for operand in terminator.operands_mut() {
+                *operand = map_value(&values, *operand);
+            }
+            if block.id == body_entry {
+                blocks[tail].instructions.extend(instructions);
+                blocks[tail].merge = merge;
+                blocks[tail].terminator = terminator;
+            } else {
+                blocks.push(Block {
+                    id: block_map[&block.id],
+                    instructions,
+                    merge,
+                    terminator,
+                });
+            }
+        }
+        tail = blocks
+            .iter()
+            .position(|block| block.id == block_map[&latch])
+            .expect("the latch was copied");
+
+        // The next iteration's phis take what the latch passes back.
+        let next: Vec<Value> = phis
+            .iter()
+            .map(|&(_, _, from_latch)| map_value(&values, from_latch))
+            .collect();
+        for (&(phi, ..), value) in phis.iter().zip(next) {
+            values.insert(phi, value);
+        }
+    }
+
+    // The last copy of the header finds the condition false and leaves.
+    copy_results(function, &header_instructions, &mut values);
+    let header_copy = copy_instructions(&header_instructions, &values, &HashMap::new());
+    blocks[tail].instructions.extend(header_copy);
+    blocks[tail].terminator = Terminator::Branch(exit);
+    let last = blocks[tail].id;
+
+    let position = function
+        .blocks
+        .iter()
+        .take_while(|block| block.id != l.header)
+        .filter(|block| !l.blocks.contains(&block.id))
+        .count();
+    function
+        .blocks
+        .retain(|block| !l.blocks.contains(&block.id));
+    function.blocks.splice(position..position, blocks);
+
+    // After the loop, the header's values are those of its last copy.
+    if let Some(exit) = function.block_mut(exit) {
+        for instruction in &mut exit.instructions {
+            if let InstKind::Phi(incoming) = &mut instruction.kind {
+                for (from, _) in incoming {
+                    if *from == l.header {
+                        *from = last;
+                    }
+                }
+            }
+        }
+    }
+    let header_values = phis.iter().map(|&(phi, ..)| phi).chain(
+        header_instructions
+            .iter()
+            .filter_map(|instruction| instruction.result),
+    );
+    for value in header_values.collect::<Vec<_>>() {
+        function.replace_all_uses(value, values[&value]);
+    }
+}
+
+// Gives each result of the instructions a new value in `values`.
+fn copy_results(
+    function: &mut Function,
+    instructions: &[Instruction],
+    values: &mut HashMap<Value, Value>,
+) {
+    for instruction in instructions {
+        if let Some(result) = instruction.result {
+            let copy = function.new_value(function.value_type(result).clone());
+            values.insert(result, copy);
+        }
+    }
+}
+
+// Copies the instructions, renaming their results, operands and the blocks
+// their phis name.
+fn copy_instructions(
+    instructions: &[Instruction],
+    values: &HashMap<Value, Value>,
+    block_map: &HashMap<BlockId, BlockId>,
+) -> Vec<Instruction> {
+    instructions
+        .iter()
+        .map(|instruction| {
+            let mut kind = instruction.kind.clone();
+            for operand in kind.operands_mut() {
+                *operand = map_value(values, *operand);
+            }
+            if let InstKind::Phi(incoming) = &mut kind {
+                for (from, _) in incoming {
+                    *from = block_map.get(from).copied().unwrap_or(*from);
+                }
+            }
+            Instruction {
+                result: instruction.result.map(|result| values[&result]),
+                kind,
+            }
+        })
+        .collect()
+}
+
+// The copy of a value defined in the loop, or the value itself if it is
+// defined outside.
+fn map_value(values: &HashMap<Value, Value>, value: Value) -> Value {
+    values.get(&value).copied().unwrap_or(value)
+}
+
+// A block id not yet in use. The block itself is added by the caller.
+fn fresh_block(function: &mut Function) -> BlockId {
+    let id = BlockId(function.next_block);
+    function.next_block += 1;
+    id
+}